@@ -1,20 +1,20 @@
 //! For your DM code coverage """needs"""
 
 mod codecov;
+mod line_profiler;
 
 use std::any::{Any, TypeId};
 
 use auxtools::*;
 use codecov::Tracker;
 use instruction_hooking::INSTRUCTION_HOOKS;
+use line_profiler::LineProfiler;
 
 fn with_tracker_option<F>(f: F, create: bool)
 where
 	F: FnOnce(&mut Tracker)
 {
-	unsafe {
-		let hooks = INSTRUCTION_HOOKS.get_mut();
-
+	INSTRUCTION_HOOKS.with(|hooks| {
 		let tracker_tid = TypeId::of::<Tracker>();
 		let tracker_option = hooks.iter_mut().find(|hook| (*hook).as_ref().type_id() == tracker_tid);
 
@@ -33,7 +33,7 @@ where
 				}
 			}
 		}
-	}
+	})
 }
 
 // INSTRUCTION_HOOKS are cleared on shutdown so we don't need to worry about
@@ -82,5 +82,54 @@ fn stop_code_coverage(coverage_file: Value) {
 	result
 }
 
+// The line profiler procs are optional so that existing users of the code
+// coverage procs don't have to define them.
+#[init(partial)]
+fn line_profiler_init() -> Result<(), String> {
+	let (start, stop) = match (Proc::find("/proc/start_line_profiling"), Proc::find("/proc/stop_line_profiling")) {
+		(Some(start), Some(stop)) => (start, stop),
+		_ => return Ok(())
+	};
+
+	start
+		.hook(start_line_profiling)
+		.map_err(|e| format!("Couldn't hook /proc/start_line_profiling: {:?}", e))?;
+	stop.hook(stop_line_profiling)
+		.map_err(|e| format!("Couldn't hook /proc/stop_line_profiling: {:?}", e))?;
+
+	Ok(())
+}
+
+#[hook]
+fn start_line_profiling(output_file: Value) {
+	let output_file_string = output_file.as_string()?;
+
+	INSTRUCTION_HOOKS.with(|hooks| {
+		if let Some(profiler) = hooks.iter_mut().find_map(|hook| hook.as_any().downcast_mut::<LineProfiler>()) {
+			return Err(runtime!("The line profiler is already running for {}!", profiler.output_file_name()));
+		}
+
+		hooks.push(Box::new(LineProfiler::new(output_file_string)));
+		Ok(Value::NULL)
+	})
+}
+
+#[hook]
+fn stop_line_profiling() {
+	let profiler = INSTRUCTION_HOOKS.with(|hooks| {
+		let index = hooks.iter_mut().position(|hook| hook.as_any().is::<LineProfiler>())?;
+		Some(hooks.remove(index))
+	});
+
+	let mut profiler = match profiler {
+		Some(profiler) => profiler,
+		None => return Err(runtime!("The line profiler is not running!"))
+	};
+
+	let result = profiler.as_any().downcast_mut::<LineProfiler>().unwrap().finalize();
+
+	result.map(|_| Value::NULL).map_err(|error| runtime!("A error occurred while trying to save the line profile: {}", error))
+}
+
 #[allow(clippy::missing_const_for_fn)]
 pub fn anti_dce_stub() {}
@@ -0,0 +1,349 @@
+use std::{
+	cmp::Reverse,
+	collections::HashMap,
+	fs::{create_dir_all, File},
+	io::{BufWriter, Error, Write},
+	path::Path,
+	time::{Duration, Instant}
+};
+
+use auxtools::{
+	raw_types::{
+		procs::{ExecutionContext, ProcId},
+		strings::StringId
+	},
+	*
+};
+use instruction_hooking::InstructionHook;
+
+#[derive(Default)]
+struct LineStats {
+	hits: u64,
+	total: Duration,
+	self_time: Duration,
+	max: Duration
+}
+
+// A line that is currently executing in one of the frames of the active stack
+struct OpenLine {
+	ctx: *mut ExecutionContext,
+	// None for frames BYOND hasn't attached a proc instance to
+	proc: Option<ProcId>,
+	file: StringId,
+	line: u32,
+	start: Instant,
+	// Time spent in lines of procs called from this line
+	child_time: Duration
+}
+
+pub struct LineProfiler {
+	output_file_name: String,
+	stack: Vec<OpenLine>,
+	last_instruction: Instant,
+	lines: HashMap<(StringId, u32), LineStats>
+}
+
+impl LineProfiler {
+	pub fn new(output_file_name: String) -> LineProfiler {
+		LineProfiler {
+			output_file_name,
+			stack: Vec::new(),
+			last_instruction: Instant::now(),
+			lines: HashMap::new()
+		}
+	}
+
+	pub fn output_file_name(&self) -> &str {
+		&self.output_file_name
+	}
+
+	fn proc_id(ctx: *mut ExecutionContext) -> Option<ProcId> {
+		unsafe {
+			let instance = (*ctx).proc_instance();
+			if instance.is_null() {
+				return None;
+			}
+
+			Some((*instance).proc)
+		}
+	}
+
+	fn open_line(ctx: *mut ExecutionContext, now: Instant) -> OpenLine {
+		unsafe {
+			OpenLine {
+				ctx,
+				proc: Self::proc_id(ctx),
+				file: (*ctx).filename(),
+				line: (*ctx).line(),
+				start: now,
+				child_time: Duration::ZERO
+			}
+		}
+	}
+
+	// Pops the top-most open line and attributes the time spent in it
+	fn close_line(&mut self, end: Instant) {
+		let open = match self.stack.pop() {
+			Some(open) => open,
+			None => return
+		};
+
+		let elapsed = end.saturating_duration_since(open.start);
+
+		if let Some(parent) = self.stack.last_mut() {
+			parent.child_time += elapsed;
+		}
+
+		if open.line == 0 || !open.file.valid() {
+			return;
+		}
+
+		let stats = self.lines.entry((open.file, open.line)).or_default();
+		stats.hits += 1;
+		stats.total += elapsed;
+		stats.self_time += elapsed.saturating_sub(open.child_time);
+		stats.max = stats.max.max(elapsed);
+	}
+
+	// Throws away our view of the stack and rebuilds it from the one BYOND is
+	// currently executing
+	fn rebuild_stack(&mut self, ctx: *mut ExecutionContext, now: Instant) {
+		// Whatever we had open finished executing at some point after the last
+		// instruction we saw. Anything after that is idle time.
+		let end = self.last_instruction;
+		while !self.stack.is_empty() {
+			self.close_line(end);
+		}
+
+		let mut current = ctx;
+		while !current.is_null() {
+			self.stack.push(Self::open_line(current, now));
+			current = unsafe { (*current).parent_context() };
+		}
+
+		self.stack.reverse();
+	}
+
+	fn process_instruction(&mut self, ctx: *mut ExecutionContext) {
+		let now = Instant::now();
+
+		let proc = match Self::proc_id(ctx) {
+			Some(proc) => proc,
+			None => return
+		};
+		let (file, line, parent) = unsafe { ((*ctx).filename(), (*ctx).line(), (*ctx).parent_context()) };
+
+		match self.stack.iter().rposition(|open| open.ctx == ctx && open.proc == Some(proc)) {
+			Some(index) => {
+				// Anything above us has returned
+				while self.stack.len() > index + 1 {
+					self.close_line(now);
+				}
+
+				let top = self.stack.last().unwrap();
+				if top.line != line || top.file != file {
+					self.close_line(now);
+					self.stack.push(Self::open_line(ctx, now));
+				}
+			}
+
+			None => match self.stack.last() {
+				// A new call from the proc at the top of our stack
+				Some(top) if top.ctx == parent => {
+					self.stack.push(Self::open_line(ctx, now));
+				}
+
+				// Something unrelated is running (new tick, a sleeping proc resuming, etc.)
+				_ => self.rebuild_stack(ctx, now)
+			}
+		}
+
+		self.last_instruction = now;
+	}
+
+	pub fn finalize(&mut self) -> Result<(), Error> {
+		let end = self.last_instruction;
+		while !self.stack.is_empty() {
+			self.close_line(end);
+		}
+
+		let mut file_names: HashMap<StringId, String> = HashMap::new();
+		let mut rows: Vec<(String, u32, &LineStats)> = self
+			.lines
+			.iter()
+			.map(|((file, line), stats)| {
+				let file_name = file_names
+					.entry(*file)
+					.or_insert_with(|| {
+						let quoted_file_name = unsafe { StringRef::from_id(*file).to_string() };
+
+						// strip quotes
+						quoted_file_name[1..quoted_file_name.len() - 1].to_string()
+					})
+					.clone();
+
+				(file_name, *line, stats)
+			})
+			.collect();
+
+		// Hottest lines first
+		rows.sort_by_key(|row| Reverse(row.2.self_time));
+
+		let output_path = Path::new(&self.output_file_name);
+		let mut path_buf = output_path.to_path_buf();
+		if path_buf.pop() {
+			create_dir_all(path_buf)?;
+		}
+
+		write_rows(BufWriter::new(File::create(output_path)?), &rows)?;
+		self.lines.clear();
+		Ok(())
+	}
+}
+
+fn write_rows<W: Write>(mut writer: W, rows: &[(String, u32, &LineStats)]) -> Result<(), Error> {
+	writeln!(writer, "file\tline\thits\tself_ms\ttotal_ms\tmean_us\tmax_us")?;
+
+	for (file_name, line, stats) in rows {
+		writeln!(
+			writer,
+			"{}\t{}\t{}\t{:.3}\t{:.3}\t{:.1}\t{:.1}",
+			file_name,
+			line,
+			stats.hits,
+			stats.self_time.as_secs_f64() * 1000.0,
+			stats.total.as_secs_f64() * 1000.0,
+			stats.total.as_secs_f64() * 1_000_000.0 / stats.hits as f64,
+			stats.max.as_secs_f64() * 1_000_000.0
+		)?;
+	}
+
+	writer.flush()
+}
+
+impl InstructionHook for LineProfiler {
+	fn handle_instruction(&mut self, ctx: *mut ExecutionContext) {
+		self.process_instruction(ctx);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::ptr;
+
+	use auxtools::raw_types::procs::ProcInstance;
+
+	use super::*;
+
+	const FILE: StringId = StringId(1);
+
+	fn open(profiler: &mut LineProfiler, line: u32, start: Instant) {
+		profiler.stack.push(OpenLine {
+			ctx: ptr::null_mut(),
+			proc: Some(ProcId(0)),
+			file: FILE,
+			line,
+			start,
+			child_time: Duration::ZERO
+		});
+	}
+
+	fn stats(profiler: &LineProfiler, line: u32) -> &LineStats {
+		&profiler.lines[&(FILE, line)]
+	}
+
+	#[test]
+	fn self_time_excludes_calls() {
+		let mut profiler = LineProfiler::new(String::new());
+		let start = Instant::now();
+		let ms = Duration::from_millis;
+
+		open(&mut profiler, 1, start);
+		open(&mut profiler, 10, start + ms(2));
+		profiler.close_line(start + ms(5));
+		open(&mut profiler, 11, start + ms(5));
+		profiler.close_line(start + ms(6));
+		profiler.close_line(start + ms(10));
+
+		assert!(profiler.stack.is_empty());
+
+		let outer = stats(&profiler, 1);
+		assert_eq!(outer.hits, 1);
+		assert_eq!(outer.total, ms(10));
+		assert_eq!(outer.self_time, ms(6));
+
+		let inner = stats(&profiler, 10);
+		assert_eq!(inner.hits, 1);
+		assert_eq!((inner.total, inner.self_time, inner.max), (ms(3), ms(3), ms(3)));
+	}
+
+	#[test]
+	fn unknown_lines_still_count_as_calls() {
+		let mut profiler = LineProfiler::new(String::new());
+		let start = Instant::now();
+		let ms = Duration::from_millis;
+
+		open(&mut profiler, 1, start);
+		open(&mut profiler, 0, start + ms(1));
+		profiler.close_line(start + ms(4));
+		profiler.close_line(start + ms(4));
+
+		assert_eq!(profiler.lines.len(), 1);
+		assert_eq!(stats(&profiler, 1).self_time, ms(1));
+	}
+
+	#[test]
+	fn calls_and_returns() {
+		unsafe {
+			let mut instance: ProcInstance = std::mem::zeroed();
+			let mut caller: ExecutionContext = std::mem::zeroed();
+			let mut callee: ExecutionContext = std::mem::zeroed();
+			let mut detached: ExecutionContext = std::mem::zeroed();
+
+			caller.pre1668.proc_instance = &mut instance;
+			caller.pre1668.filename = FILE;
+			caller.pre1668.line = 1;
+			callee.pre1668.proc_instance = &mut instance;
+			callee.pre1668.parent_context = &mut caller;
+			callee.pre1668.filename = FILE;
+			callee.pre1668.line = 5;
+			detached.pre1668.filename = FILE;
+			detached.pre1668.line = 7;
+
+			let mut profiler = LineProfiler::new(String::new());
+			profiler.process_instruction(&mut caller);
+			profiler.process_instruction(&mut callee);
+			assert_eq!(profiler.stack.len(), 2);
+
+			// No proc instance to tell frames apart with, so it's skipped
+			profiler.process_instruction(&mut detached);
+			assert_eq!(profiler.stack.len(), 2);
+
+			caller.pre1668.line = 2;
+			profiler.process_instruction(&mut caller);
+			assert_eq!(profiler.stack.len(), 1);
+			assert_eq!(profiler.stack[0].line, 2);
+			assert_eq!(stats(&profiler, 1).hits, 1);
+			assert_eq!(stats(&profiler, 5).hits, 1);
+			assert!(!profiler.lines.contains_key(&(FILE, 7)));
+		}
+	}
+
+	#[test]
+	fn tsv() {
+		let ms = Duration::from_millis;
+		let stats = LineStats {
+			hits: 4,
+			total: ms(2),
+			self_time: ms(1),
+			max: Duration::from_micros(1500)
+		};
+
+		let mut out = vec![];
+		write_rows(&mut out, &[("code/foo.dm".to_owned(), 12, &stats)]).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"file\tline\thits\tself_ms\ttotal_ms\tmean_us\tmax_us\ncode/foo.dm\t12\t4\t1.000\t2.000\t500.0\t1500.0\n"
+		);
+	}
+}
@@ -0,0 +1,247 @@
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Path, PathBuf},
+	process::exit
+};
+
+use clap::{Arg, Command};
+use dmasm::{Instruction, Node};
+use dmb::Dmb;
+
+// Lines of the source files that DbgFile instructions point at, which are
+// relative to the directory the .dme (and usually the .dmb) is in
+struct Sources {
+	root: PathBuf,
+	files: HashMap<String, Option<Vec<String>>>
+}
+
+impl Sources {
+	fn line(&mut self, file: &str, line: u32) -> Option<&str> {
+		let root = &self.root;
+		let lines = self.files.entry(file.to_owned()).or_insert_with(|| {
+			let data = fs::read(root.join(file)).ok()?;
+			Some(String::from_utf8_lossy(&data).lines().map(str::to_owned).collect())
+		});
+
+		lines.as_ref()?.get((line as usize).checked_sub(1)?).map(|x| x.trim())
+	}
+}
+
+fn annotate(file: Option<&str>, line: u32, sources: Option<&mut Sources>) -> String {
+	let file = match file {
+		Some(file) => file,
+		None => return format!("// line {}\n", line)
+	};
+
+	match sources.and_then(|sources| sources.line(file, line)) {
+		Some(text) => format!("// {}:{}: {}\n", file, line, text),
+		None => format!("// {}:{}\n", file, line)
+	}
+}
+
+// What a node means for splitting the disassembly up by source line
+enum Marker {
+	File(String),
+	Line(u32)
+}
+
+fn marker(node: &Node) -> Option<Marker> {
+	match node {
+		Node::Instruction(Instruction::DbgFile(name), _) => Some(Marker::File(String::from_utf8_lossy(&name.0).into_owned())),
+		Node::Instruction(Instruction::DbgLine(line), _) => Some(Marker::Line(*line)),
+		_ => None
+	}
+}
+
+// The index of each DbgLine node, with the file and line it's for
+fn line_starts<I: IntoIterator<Item = Option<Marker>>>(markers: I) -> Vec<(usize, Option<String>, u32)> {
+	let mut file = None;
+	let mut starts = vec![];
+
+	for (index, marker) in markers.into_iter().enumerate() {
+		match marker {
+			Some(Marker::File(name)) => file = Some(name),
+			Some(Marker::Line(line)) => starts.push((index, file.clone(), line)),
+			None => {}
+		}
+	}
+
+	starts
+}
+
+// Adds a chunk of disassembly, making sure whatever comes next starts on a new
+// line
+fn push_chunk(dism: &mut String, chunk: &str) {
+	dism.push_str(chunk);
+	if !dism.is_empty() && !dism.ends_with('\n') {
+		dism.push('\n');
+	}
+}
+
+// Matches the header of the debug server's `#dis`, which shows the path the way
+// the proc table has it
+fn header(proc: &dmb::Proc) -> String {
+	format!("Dism for Proc({:?})", proc.raw_path)
+}
+
+// The same as the debug server's `#dis`, with the source line each DbgLine
+// starts written above it
+fn disassemble(dmb: &Dmb, proc: &dmb::Proc, mut sources: Option<Sources>) -> String {
+	let (nodes, error) = dmb.disassemble(proc);
+
+	let mut dism = String::new();
+	let mut chunk_start = 0;
+
+	for (index, file, line) in line_starts(nodes.iter().map(marker)) {
+		push_chunk(&mut dism, &dmasm::format_disassembly(&nodes[chunk_start..index], None));
+		dism.push_str(&annotate(file.as_deref(), line, sources.as_mut()));
+		chunk_start = index;
+	}
+
+	dism.push_str(&dmasm::format_disassembly(&nodes[chunk_start..], None));
+
+	match error {
+		Some(error) => format!("{}\n{}\n\tError: {:?}", header(proc), dism, error),
+		None => format!("{}\n{}", header(proc), dism)
+	}
+}
+
+fn main() {
+	let matches = Command::new("auxtools-dis")
+		.about("Disassembles a proc from a compiled .dmb")
+		.disable_version_flag(true)
+		.arg(Arg::new("dmb").help("the .dmb to read").required(true).takes_value(true))
+		.arg(
+			Arg::new("proc")
+				.help("path of the proc, like /mob/proc/Login")
+				.required(true)
+				.takes_value(true)
+		)
+		.arg(
+			Arg::new("override")
+				.long("override")
+				.short('o')
+				.help("which definition of the proc to show, counting from 0")
+				.default_value("0")
+				.takes_value(true)
+		)
+		.arg(
+			Arg::new("source")
+				.long("source")
+				.help("directory the .dme is in, for showing source lines (defaults to the directory of the .dmb)")
+				.takes_value(true)
+		)
+		.arg(Arg::new("no-source").long("no-source").help("don't show source lines"))
+		.get_matches();
+
+	let path = Path::new(matches.value_of("dmb").unwrap());
+	let override_id = match matches.value_of("override").unwrap().parse::<u32>() {
+		Ok(override_id) => override_id,
+		Err(_) => {
+			eprintln!("override must be an integer");
+			exit(1);
+		}
+	};
+
+	let dmb = match Dmb::read(path) {
+		Ok(dmb) => dmb,
+		Err(e) => {
+			eprintln!("couldn't read {}: {}", path.display(), e);
+			exit(1);
+		}
+	};
+
+	let proc = match dmb.find_proc_override(matches.value_of("proc").unwrap(), override_id) {
+		Some(proc) => proc,
+		None => {
+			eprintln!("Proc not found");
+			exit(1);
+		}
+	};
+
+	let sources = match matches.is_present("no-source") {
+		true => None,
+		false => Some(Sources {
+			root: match matches.value_of("source") {
+				Some(root) => PathBuf::from(root),
+				None => path.parent().map(Path::to_path_buf).unwrap_or_default()
+			},
+			files: HashMap::new()
+		})
+	};
+
+	println!("{}", disassemble(&dmb, proc, sources));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn chunks() {
+		let markers = vec![
+			Some(Marker::Line(1)),
+			None,
+			Some(Marker::File("code/foo.dm".to_owned())),
+			Some(Marker::Line(4)),
+			None,
+			None,
+			Some(Marker::Line(5))
+		];
+
+		let starts = line_starts(markers);
+		assert_eq!(
+			starts,
+			[
+				(0, None, 1),
+				(3, Some("code/foo.dm".to_owned()), 4),
+				(6, Some("code/foo.dm".to_owned()), 5)
+			]
+		);
+
+		let mut dism = String::new();
+		push_chunk(&mut dism, "");
+		assert_eq!(dism, "");
+		push_chunk(&mut dism, "\tPushNull");
+		push_chunk(&mut dism, "\tRet\n");
+		assert_eq!(dism, "\tPushNull\n\tRet\n");
+	}
+
+	#[test]
+	fn annotations() {
+		let root = std::env::temp_dir().join(format!("auxtools-dis-{}", std::process::id()));
+		fs::create_dir_all(root.join("code")).unwrap();
+		fs::write(root.join("code/foo.dm"), "/proc/foo()\r\n\treturn 1\n").unwrap();
+
+		let mut sources = Sources {
+			root: root.clone(),
+			files: HashMap::new()
+		};
+
+		assert_eq!(annotate(Some("code/foo.dm"), 2, Some(&mut sources)), "// code/foo.dm:2: return 1\n");
+		assert_eq!(annotate(Some("code/foo.dm"), 3, Some(&mut sources)), "// code/foo.dm:3\n");
+		assert_eq!(annotate(Some("code/foo.dm"), 0, Some(&mut sources)), "// code/foo.dm:0\n");
+		assert_eq!(annotate(Some("code/missing.dm"), 1, Some(&mut sources)), "// code/missing.dm:1\n");
+		assert_eq!(annotate(Some("code/foo.dm"), 1, None), "// code/foo.dm:1\n");
+		assert_eq!(annotate(None, 7, Some(&mut sources)), "// line 7\n");
+
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn header_uses_raw_path() {
+		let proc = dmb::Proc {
+			id: 0,
+			path: "/mob/Login".to_owned(),
+			raw_path: "/mob/proc/Login".to_owned(),
+			override_id: 0,
+			name: "Login".to_owned(),
+			bytecode: vec![],
+			locals: vec![],
+			parameters: vec![]
+		};
+
+		assert_eq!(header(&proc), "Dism for Proc(\"/mob/proc/Login\")");
+	}
+}
@@ -56,21 +56,90 @@ fn extract_args(a: &syn::FnArg) -> &syn::PatType {
 	}
 }
 
+// Parses the `priority = N` and `after = "other_fn"` options shared by the init
+// and shutdown attributes into an `auxtools::InitOrder`
+fn parse_order<'a>(func_name: &syn::Ident, options: impl Iterator<Item = &'a syn::Meta>) -> Result<proc_macro2::TokenStream, syn::Error> {
+	let mut priority = quote! { 0 };
+	let mut after = vec![];
+
+	for option in options {
+		let option = match option {
+			syn::Meta::NameValue(option) => option,
+			_ => return Err(syn::Error::new(option.span(), "expected `priority = N` or `after = \"name\"`"))
+		};
+
+		match option.path.get_ident().map(|x| x.to_string()).as_deref() {
+			Some("priority") => {
+				let value = &option.value;
+				priority = quote! { #value };
+			}
+
+			Some("after") => match &option.value {
+				syn::Expr::Lit(syn::ExprLit { lit: Lit::Str(name), .. }) => after.push(name.clone()),
+				value => return Err(syn::Error::new(value.span(), "expected the name of a function"))
+			},
+
+			_ => return Err(syn::Error::new(option.path.span(), "expected `priority` or `after`"))
+		}
+	}
+
+	Ok(quote! {
+		auxtools::InitOrder {
+			name: concat!(module_path!(), "::", stringify!(#func_name)),
+			priority: #priority,
+			after: &[#(#after),*]
+		}
+	})
+}
+
+/// The `init` attribute registers a function to run when the library is
+/// initialized. `#[init(full)]` functions run once, when the library is first
+/// loaded, and `#[init(partial)]` functions run every time `auxtools_init` is
+/// called after `auxtools_shutdown`.
+///
+/// Functions run in order of `priority` (lowest first, defaulting to 0), but
+/// never before the functions named by `after`. Names can be just the function
+/// name or any part of its path, like `"instruction_hooking_init"` or
+/// `"instruction_hooking::instruction_hooking_init"`. If a function fails, the
+/// rest still run except for those ordered after it, and all of the errors are
+/// reported together.
+///
+/// # Examples
+/// ```ignore
+/// #[init(full, after = "instruction_hooking_init")]
+/// fn debugger_init() -> Result<(), String> {
+///     Ok(())
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn init(attr: TokenStream, item: TokenStream) -> TokenStream {
-	let init_type = syn::parse_macro_input!(attr as syn::Ident);
+	let options = syn::parse_macro_input!(attr with syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated);
 	let func = syn::parse_macro_input!(item as syn::ItemFn);
 	let func_name = &func.sig.ident;
 
-	let func_type = match init_type.to_string().as_str() {
-		"full" => quote! { auxtools::FullInitFunc },
-		"partial" => quote! { auxtools::PartialInitFunc },
+	let init_type = match options.first() {
+		Some(syn::Meta::Path(path)) => path,
+		_ => {
+			return syn::Error::new(func_name.span(), "expected `full` or `partial`")
+				.to_compile_error()
+				.into()
+		}
+	};
+
+	let func_type = match init_type.get_ident().map(|x| x.to_string()).as_deref() {
+		Some("full") => quote! { auxtools::FullInitFunc },
+		Some("partial") => quote! { auxtools::PartialInitFunc },
 		_ => return syn::Error::new(init_type.span(), "invalid init type").to_compile_error().into()
 	};
 
+	let order = match parse_order(func_name, options.iter().skip(1)) {
+		Ok(order) => order,
+		Err(e) => return e.to_compile_error().into()
+	};
+
 	let inventory_define = quote! {
 		auxtools::inventory::submit!(
-			#func_type(#func_name)
+			#func_type { func: #func_name, order: #order }
 		);
 	};
 
@@ -101,14 +170,19 @@ pub fn runtime_handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
 	code.into()
 }
 
-#[proc_macro_attribute]
-pub fn shutdown(_: TokenStream, item: TokenStream) -> TokenStream {
+fn shutdown_func(func_type: proc_macro2::TokenStream, attr: TokenStream, item: TokenStream) -> TokenStream {
+	let options = syn::parse_macro_input!(attr with syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated);
 	let func = syn::parse_macro_input!(item as syn::ItemFn);
 	let func_name = &func.sig.ident;
 
+	let order = match parse_order(func_name, options.iter()) {
+		Ok(order) => order,
+		Err(e) => return e.to_compile_error().into()
+	};
+
 	let inventory_define = quote! {
 		auxtools::inventory::submit!(
-			auxtools::PartialShutdownFunc(#func_name)
+			#func_type { func: #func_name, order: #order }
 		);
 	};
 
@@ -120,21 +194,68 @@ pub fn shutdown(_: TokenStream, item: TokenStream) -> TokenStream {
 	code.into()
 }
 
+/// The `shutdown` attribute registers a function to run when
+/// `auxtools_shutdown` is called. It takes the same `priority` and `after`
+/// options as [init](macro@init), and shutdown functions run in the reverse of
+/// that order, so anything ordered after another function is shut down before
+/// it.
+#[proc_macro_attribute]
+pub fn shutdown(attr: TokenStream, item: TokenStream) -> TokenStream {
+	shutdown_func(quote! { auxtools::PartialShutdownFunc }, attr, item)
+}
+
+/// Like [shutdown](macro@shutdown), but only runs when the library is fully
+/// shut down with `auxtools_full_shutdown`.
 #[proc_macro_attribute]
-pub fn full_shutdown(_: TokenStream, item: TokenStream) -> TokenStream {
+pub fn full_shutdown(attr: TokenStream, item: TokenStream) -> TokenStream {
+	shutdown_func(quote! { auxtools::FullShutdownFunc }, attr, item)
+}
+
+/// The `persist` attribute is used to carry state over when the library is
+/// replaced while the world is running. A `save` function returns the state as
+/// bytes when DM calls `auxtools_persist`, and the `restore` function with the
+/// same key receives those bytes when the new library is initialized.
+///
+/// # Examples
+/// ```ignore
+/// #[persist(save = "counter")]
+/// fn save_counter() -> Vec<u8> {
+///     unsafe { COUNTER }.to_le_bytes().to_vec()
+/// }
+///
+/// #[persist(restore = "counter")]
+/// fn restore_counter(data: Vec<u8>) -> Result<(), String> {
+///     let bytes = data.try_into().map_err(|_| "wrong size".to_owned())?;
+///     unsafe { COUNTER = u32::from_le_bytes(bytes) };
+///     Ok(())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn persist(attr: TokenStream, item: TokenStream) -> TokenStream {
+	let args = syn::parse_macro_input!(attr as syn::MetaNameValue);
 	let func = syn::parse_macro_input!(item as syn::ItemFn);
 	let func_name = &func.sig.ident;
 
-	let inventory_define = quote! {
-		auxtools::inventory::submit!(
-			#![crate = auxtools]
-			auxtools::FullShutdownFunc(#func_name)
-		);
+	let key = match &args.value {
+		syn::Expr::Lit(syn::ExprLit { lit: Lit::Str(key), .. }) => key,
+		value => return syn::Error::new(value.span(), "expected a string key").to_compile_error().into()
+	};
+
+	let func_type = match args.path.get_ident().map(|x| x.to_string()).as_deref() {
+		Some("save") => quote! { auxtools::PersistSaveFunc },
+		Some("restore") => quote! { auxtools::PersistRestoreFunc },
+		_ => {
+			return syn::Error::new(args.path.span(), "expected `save` or `restore`")
+				.to_compile_error()
+				.into()
+		}
 	};
 
 	let code = quote! {
 		#func
-		#inventory_define
+		auxtools::inventory::submit!(
+			#func_type { key: #key, func: #func_name }
+		);
 	};
 
 	code.into()
@@ -1,4 +1,9 @@
 fn main() {
+	// The mock feature replaces everything the C++ provides
+	if std::env::var_os("CARGO_FEATURE_MOCK").is_some() {
+		return;
+	}
+
 	cc::Build::new()
 		.include("src/")
 		.file("src/hooks.cpp")
@@ -116,3 +116,46 @@ macro_rules! byond_ffi_fn {
         }
     };
 }
+
+/// Like [byond_ffi_fn], but for BYOND's byondapi, which passes arguments and
+/// return values as typed values instead of strings. Call it from DM with
+/// `call_ext("library", "byond:name")(args...)`.
+///
+/// Arguments are [Value](crate::Value)s, with missing ones being null, and the
+/// body returns a [DMResult](crate::DMResult). Errors are passed to
+/// `/proc/auxtools_stack_trace`, just like errors returned from hooks, and
+/// null is returned to DM. Returns null without running the body if auxtools
+/// hasn't been initialized.
+///
+/// # Examples
+/// ```ignore
+/// byondapi_fn! { add(a, b) {
+///     Ok(Value::from(a.as_number()? + b.as_number()?))
+/// } }
+/// ```
+#[macro_export]
+macro_rules! byondapi_fn {
+    ($name:ident($($arg:ident),* $(, ...$rest:ident)?) $body:block) => {
+        #[no_mangle]
+        #[allow(clippy::missing_safety_doc)]
+        extern "C" fn $name(
+            __argc: u32, __argv: *const $crate::byondapi::CByondValue
+        ) -> $crate::byondapi::CByondValue {
+            unsafe {
+                $crate::byondapi::call(stringify!($name), __argc, __argv, |__args: &[$crate::Value]| -> $crate::DMResult {
+                    let mut __argn = 0;
+                    $(
+                        let $arg: $crate::Value = __args.get(__argn).cloned().unwrap_or($crate::Value::NULL);
+                        __argn += 1;
+                    )*
+                    $(
+                        let $rest: &[$crate::Value] = __args.get(__argn..).unwrap_or(&[]);
+                    )?
+                    let _ = __argn;
+
+                    $body
+                })
+            }
+        }
+    };
+}
@@ -0,0 +1,100 @@
+//! Interop with the official byondapi that BYOND 515 and up ship with.
+//!
+//! A [CByondValue] has the same layout as the values auxtools works with, so
+//! converting between the two is a copy plus whatever reference counting
+//! [Value] needs. Functions made with [byondapi_fn](crate::byondapi_fn) can be
+//! called from DM with `call_ext("library", "byond:name")(args...)` and work
+//! with [Value]s directly, which lets a library move between `call_ext`
+//! functions and hooks one function at a time.
+use std::cell::RefCell;
+
+use crate::{
+	init::{get_init_level, InitLevel},
+	raw_types, DMResult, Proc, Value
+};
+
+/// A value as byondapi passes it around
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct CByondValue {
+	pub type_: u8,
+	pub junk1: u8,
+	pub junk2: u8,
+	pub junk3: u8,
+	pub data: CByondValueData
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union CByondValueData {
+	pub ref_: u32,
+	pub num: f32
+}
+
+const _: () = assert!(std::mem::size_of::<CByondValue>() == std::mem::size_of::<raw_types::values::Value>());
+
+impl CByondValue {
+	pub const NULL: Self = CByondValue {
+		type_: 0,
+		junk1: 0,
+		junk2: 0,
+		junk3: 0,
+		data: CByondValueData { ref_: 0 }
+	};
+}
+
+thread_local! {
+	// Keeps whatever we last returned to BYOND alive until it has taken its own
+	// reference
+	static RETURN_VALUE: RefCell<Option<Value>> = const { RefCell::new(None) };
+}
+
+impl Value {
+	/// Takes a new reference to a value byondapi gave us.
+	///
+	/// The value has to be one BYOND created, as its type isn't checked.
+	pub unsafe fn from_byondapi(value: &CByondValue) -> Value {
+		Value::from_raw(std::mem::transmute::<CByondValue, raw_types::values::Value>(*value))
+	}
+
+	/// Returns this value as byondapi expects it. No reference is taken, so it is
+	/// only valid for as long as this [Value] is alive.
+	pub fn as_byondapi(&self) -> CByondValue {
+		unsafe { std::mem::transmute::<raw_types::values::Value, CByondValue>(self.raw) }
+	}
+}
+
+/// Used by [byondapi_fn](crate::byondapi_fn) to run its body and hand the
+/// result back to BYOND
+#[doc(hidden)]
+pub unsafe fn call(name: &str, argc: u32, argv: *const CByondValue, func: impl FnOnce(&[Value]) -> DMResult) -> CByondValue {
+	// Values can't be touched until auxtools_init has set everything up
+	if get_init_level() != InitLevel::None {
+		return CByondValue::NULL;
+	}
+
+	let args: Vec<Value> = match argv.is_null() {
+		true => vec![],
+		false => std::slice::from_raw_parts(argv, argc as usize)
+			.iter()
+			.map(|x| Value::from_byondapi(x))
+			.collect()
+	};
+
+	let result = func(&args).unwrap_or_else(|e| {
+		tracing::debug!("{} returned an error: {}", name, e.message);
+
+		// Reported the same way hooks report their errors
+		if let Some(proc) = Proc::find("/proc/auxtools_stack_trace") {
+			if let Ok(message) = Value::from_string(format!("{} FFI: {}", e.message, name)) {
+				let _ = proc.call(&[&message]);
+			}
+		}
+
+		Value::NULL
+	});
+
+	let raw = result.as_byondapi();
+	RETURN_VALUE.with(|cell| cell.replace(Some(result)));
+	raw
+}
@@ -0,0 +1,446 @@
+//! Heap snapshots of every live datum, list and string.
+//!
+//! Snapshots are written in a compact binary format meant to be read by the
+//! `heapdiff` tool, which can compare two of them. All integers are unsigned
+//! LEB128 varints.
+//!
+//! ```text
+//! magic        "AUXHEAP1"
+//! type count   followed by that many (length, utf-8 bytes) type names
+//! object count followed by that many objects:
+//!     tag          the object's `ValueTag` as a single byte
+//!     id           the object's id
+//!     type         index into the type names
+//!     refcount     see below
+//!     size         approximate size in bytes
+//!     references   count followed by that many object indices
+//! root count   followed by that many object indices
+//! ```
+//!
+//! BYOND only exposes reference counts for strings. For everything else the
+//! refcount is the number of references found while building the snapshot.
+//!
+//! Atoms in nullspace and lists that nothing points to are included, as every
+//! object table is walked rather than just what's reachable from the world.
+use std::{
+	collections::{HashMap, VecDeque},
+	ffi::CStr,
+	io::{self, Write},
+	mem::size_of
+};
+
+use crate::{
+	raw_types::{
+		lists::AssociativeListEntry,
+		strings::{StringEntry, StringId},
+		values::{ValueData, ValueTag}
+	},
+	*
+};
+
+const MAGIC: &[u8] = b"AUXHEAP1";
+
+struct Object {
+	tag: u8,
+	id: u32,
+	ty: u32,
+	refcount: u32,
+	size: u32,
+	references: Vec<u32>
+}
+
+pub struct HeapSnapshot {
+	types: Vec<String>,
+	objects: Vec<Object>,
+	roots: Vec<u32>
+}
+
+impl HeapSnapshot {
+	pub fn len(&self) -> usize {
+		self.objects.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.objects.is_empty()
+	}
+
+	/// Total of the approximate sizes of every object in the snapshot.
+	pub fn total_size(&self) -> u64 {
+		self.objects.iter().map(|x| x.size as u64).sum()
+	}
+
+	pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+		writer.write_all(MAGIC)?;
+
+		write_varint(&mut writer, self.types.len() as u64)?;
+		for ty in &self.types {
+			write_varint(&mut writer, ty.len() as u64)?;
+			writer.write_all(ty.as_bytes())?;
+		}
+
+		write_varint(&mut writer, self.objects.len() as u64)?;
+		for object in &self.objects {
+			writer.write_all(&[object.tag])?;
+			write_varint(&mut writer, object.id as u64)?;
+			write_varint(&mut writer, object.ty as u64)?;
+			write_varint(&mut writer, object.refcount as u64)?;
+			write_varint(&mut writer, object.size as u64)?;
+
+			write_varint(&mut writer, object.references.len() as u64)?;
+			for reference in &object.references {
+				write_varint(&mut writer, *reference as u64)?;
+			}
+		}
+
+		write_varint(&mut writer, self.roots.len() as u64)?;
+		for root in &self.roots {
+			write_varint(&mut writer, *root as u64)?;
+		}
+
+		writer.flush()
+	}
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+	let mut buf = [0u8; 10];
+	let mut len = 0;
+
+	while value >= 0x80 {
+		buf[len] = (value as u8) | 0x80;
+		value >>= 7;
+		len += 1;
+	}
+
+	buf[len] = value as u8;
+	writer.write_all(&buf[..=len])
+}
+
+struct Builder {
+	indices: HashMap<(u8, u32), u32>,
+	// Values are held here until they're processed, so the only reference we
+	// hold to an object while processing it is our own
+	values: Vec<Option<Value>>,
+	queue: VecDeque<u32>,
+	objects: Vec<Object>,
+	types: Vec<String>,
+	type_indices: HashMap<String, u32>,
+	roots: Vec<u32>
+}
+
+impl Builder {
+	fn new() -> Self {
+		Self {
+			indices: HashMap::new(),
+			values: vec![],
+			queue: VecDeque::new(),
+			objects: vec![],
+			types: vec![],
+			type_indices: HashMap::new(),
+			roots: vec![]
+		}
+	}
+
+	fn type_index(&mut self, ty: &str) -> u32 {
+		if let Some(index) = self.type_indices.get(ty) {
+			return *index;
+		}
+
+		let index = self.types.len() as u32;
+		self.types.push(ty.to_owned());
+		self.type_indices.insert(ty.to_owned(), index);
+		index
+	}
+
+	// Returns the index of the object for a value, queueing it up to be
+	// processed if we haven't seen it before. Values that aren't heap objects
+	// (numbers, typepaths, etc.) return None.
+	fn intern(&mut self, value: &Value) -> Option<u32> {
+		match value.raw.tag {
+			ValueTag::String
+			| ValueTag::List
+			| ValueTag::Datum
+			| ValueTag::Turf
+			| ValueTag::Obj
+			| ValueTag::Mob
+			| ValueTag::Area
+			| ValueTag::Client
+			| ValueTag::Image => {}
+			_ => return None
+		}
+
+		let key = (value.raw.tag as u8, unsafe { value.raw.data.id });
+		if let Some(index) = self.indices.get(&key) {
+			return Some(*index);
+		}
+
+		let index = self.objects.len() as u32;
+		self.objects.push(Object {
+			tag: key.0,
+			id: key.1,
+			ty: 0,
+			refcount: 0,
+			size: 0,
+			references: vec![]
+		});
+
+		self.indices.insert(key, index);
+		self.values.push(Some(value.clone()));
+		self.queue.push_back(index);
+		Some(index)
+	}
+
+	fn add_root(&mut self, value: &Value) {
+		if let Some(index) = self.intern(value) {
+			self.roots.push(index);
+		}
+	}
+
+	fn add_roots(&mut self) {
+		if let Ok(names) = unsafe { Value::new(ValueTag::GlobalVars, ValueData { id: 0 }) }.as_list() {
+			for name in var_names(&names) {
+				if let Ok(value) = Value::GLOBAL.get(name) {
+					self.add_root(&value);
+				}
+			}
+		}
+
+		if let Ok(contents) = Value::WORLD.get_list(byond_string!("contents")) {
+			for i in 1..=contents.len() {
+				if let Ok(atom) = contents.get(i) {
+					self.add_root(&atom);
+				}
+			}
+		}
+
+		let stacks = debug::CallStacks::new();
+		for frame in stacks.active.iter().chain(stacks.suspended.iter().flatten()) {
+			self.add_root(&frame.src);
+			self.add_root(&frame.usr);
+			self.add_root(&frame.dot);
+
+			for (_, value) in &frame.args {
+				self.add_root(value);
+			}
+
+			for (_, value) in &frame.locals {
+				self.add_root(value);
+			}
+		}
+	}
+
+	fn add_strings(&mut self) {
+		let count = unsafe { *raw_types::funcs::STRING_TABLE_LENGTH };
+
+		for id in 0..count {
+			// Free slots are left in the table
+			let string_id = StringId(id);
+			if string_entry(string_id).is_none() {
+				continue;
+			}
+
+			let value = unsafe { Value::new(ValueTag::String, ValueData { string: string_id }) };
+			self.intern(&value);
+		}
+	}
+
+	fn process(&mut self, index: u32, value: &Value) {
+		let (ty, refcount, size, references) = match value.raw.tag {
+			ValueTag::String => match string_entry(unsafe { value.raw.data.string }) {
+				Some(entry) => unsafe {
+					let len = CStr::from_ptr(entry.data).to_bytes().len();
+					// Don't count the reference we're holding
+					("string".to_owned(), entry.ref_count.saturating_sub(1), size_of::<StringEntry>() + len + 1, vec![])
+				},
+				None => ("string".to_owned(), 0, 0, vec![])
+			},
+
+			ValueTag::List => {
+				let (references, len, assoc_count) = match List::from_value(value) {
+					Ok(list) => {
+						let (references, assoc_count) = self.list_references(&list);
+						(references, list.len() as usize, assoc_count)
+					}
+					Err(_) => (vec![], 0, 0)
+				};
+
+				let size = size_of::<raw_types::lists::List>()
+					+ len * size_of::<raw_types::values::Value>()
+					+ assoc_count * size_of::<AssociativeListEntry>();
+
+				("/list".to_owned(), 0, size, references)
+			}
+
+			_ => {
+				let ty = value.get_type().unwrap_or_else(|_| "<unknown>".to_owned());
+				let (references, var_count) = self.var_references(value);
+				(ty, 0, var_count * size_of::<raw_types::values::Value>(), references)
+			}
+		};
+
+		let ty = self.type_index(&ty);
+
+		let object = &mut self.objects[index as usize];
+		object.ty = ty;
+		object.refcount = refcount;
+		object.size = size as u32;
+		object.references = references;
+	}
+
+	// Returns the references held by a list along with how many of them are
+	// associated values
+	fn list_references(&mut self, list: &List) -> (Vec<u32>, usize) {
+		let mut references = vec![];
+		let mut assoc_count = 0;
+
+		for i in 1..=list.len() {
+			let key = match list.get(i) {
+				Ok(key) => key,
+				Err(_) => continue
+			};
+
+			references.extend(self.intern(&key));
+
+			// Numbers can't be assoc keys
+			if key.raw.tag == ValueTag::Number || key.raw.tag == ValueTag::Null {
+				continue;
+			}
+
+			if let Ok(value) = list.get(&key) {
+				if value.raw.tag != ValueTag::Null {
+					assoc_count += 1;
+					references.extend(self.intern(&value));
+				}
+			}
+		}
+
+		(references, assoc_count)
+	}
+
+	// Returns the references held by an object's vars along with how many vars it
+	// has
+	fn var_references(&mut self, object: &Value) -> (Vec<u32>, usize) {
+		let mut references = vec![];
+
+		let names = match object.get_list(byond_string!("vars")) {
+			Ok(names) => var_names(&names),
+			Err(_) => return (references, 0)
+		};
+
+		for name in &names {
+			if let Ok(value) = object.get(name.clone()) {
+				references.extend(self.intern(&value));
+			}
+		}
+
+		(references, names.len())
+	}
+
+	fn build(mut self) -> HeapSnapshot {
+		self.add_roots();
+		for tag in refs::OBJECT_TABLES {
+			refs::for_each_object(tag, |object| {
+				self.intern(&object);
+			});
+		}
+		self.add_strings();
+
+		while let Some(index) = self.queue.pop_front() {
+			if let Some(value) = self.values[index as usize].take() {
+				self.process(index, &value);
+			}
+		}
+
+		// We only know real refcounts for strings
+		let mut inbound = vec![0u32; self.objects.len()];
+		for object in &self.objects {
+			for reference in &object.references {
+				inbound[*reference as usize] += 1;
+			}
+		}
+
+		for (object, inbound) in self.objects.iter_mut().zip(inbound) {
+			if object.tag != ValueTag::String as u8 {
+				object.refcount = inbound;
+			}
+		}
+
+		HeapSnapshot {
+			types: self.types,
+			objects: self.objects,
+			roots: self.roots
+		}
+	}
+}
+
+fn var_names(vars: &List) -> Vec<StringRef> {
+	(1..=vars.len())
+		.filter_map(|i| vars.get(i).ok())
+		.filter_map(|name| name.as_string().ok())
+		// This just points back at the object itself
+		.filter(|name| name != "vars")
+		.filter_map(|name| StringRef::new(&name).ok())
+		.collect()
+}
+
+fn string_entry(id: StringId) -> Option<&'static StringEntry> {
+	unsafe {
+		let mut entry: *mut StringEntry = std::ptr::null_mut();
+		if raw_types::funcs::get_string_table_entry(&mut entry, id) != 1 || entry.is_null() || (*entry).data.is_null() {
+			return None;
+		}
+
+		Some(&*entry)
+	}
+}
+
+/// Walks every live datum, atom, list and string and records their types,
+/// sizes and the references between them. This can take a while on big
+/// worlds.
+pub fn snapshot() -> HeapSnapshot {
+	Builder::new().build()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn write() {
+		let snapshot = HeapSnapshot {
+			types: vec!["/list".to_owned(), "/datum/thing".to_owned()],
+			objects: vec![
+				Object {
+					tag: ValueTag::Datum as u8,
+					id: 5,
+					ty: 1,
+					refcount: 1,
+					size: 200,
+					references: vec![1]
+				},
+				Object {
+					tag: ValueTag::List as u8,
+					id: 3,
+					ty: 0,
+					refcount: 1,
+					size: 24,
+					references: vec![]
+				}
+			],
+			roots: vec![0]
+		};
+
+		let mut data = vec![];
+		snapshot.write(&mut data).unwrap();
+
+		// heapdiff's snapshot tests parse these same bytes
+		let mut expected = b"AUXHEAP1".to_vec();
+		expected.extend([0x02, 0x05]);
+		expected.extend(b"/list");
+		expected.push(0x0C);
+		expected.extend(b"/datum/thing");
+		expected.extend([0x02, 0x21, 0x05, 0x01, 0x01, 0xC8, 0x01, 0x01, 0x01]);
+		expected.extend([0x0F, 0x03, 0x00, 0x01, 0x18, 0x00]);
+		expected.extend([0x01, 0x00]);
+		assert_eq!(data, expected);
+	}
+}
@@ -0,0 +1,427 @@
+//! Lets more than one library built on auxtools run in the same Dream Daemon.
+//!
+//! Only one of the libraries (the owner) detours `call_proc_by_id` and
+//! `runtime`. Every library exports `auxtools_hook_registry`, which the others
+//! look for in the loaded modules to find and join the registry instead of
+//! installing detours of their own. Calls to procs the owner hasn't hooked are
+//! passed on to the other libraries, and hooking a proc another library has
+//! already hooked fails. When the owner shuts down, the next library in the
+//! registry installs its detours and takes over.
+//!
+//! The registry also holds the runtime context stack shared by all of the
+//! libraries, so a runtime is only ever captured by whoever made the innermost
+//! call into BYOND. That library throws it back to its own call, so the only
+//! frames unwound are BYOND's and the owner's C++ runtime hook.
+//!
+//! The libraries may have been built with different compilers or versions of
+//! auxtools, so everything in the registry is `repr(C)` and is versioned. It is
+//! only ever touched from the main thread.
+use std::os::raw::c_char;
+
+use crate::{hooks, raw_types};
+
+const REGISTRY_SYMBOL: &[u8] = b"auxtools_hook_registry\0";
+const REGISTRY_VERSION: u32 = 2;
+const MAX_LIBRARIES: usize = 16;
+
+type CallProcHook = extern "C" fn(
+	*mut raw_types::values::Value,
+	raw_types::values::Value,
+	u32,
+	raw_types::procs::ProcId,
+	u32,
+	raw_types::values::Value,
+	*mut raw_types::values::Value,
+	usize,
+	u32,
+	u32
+) -> u8;
+
+type RuntimeCapture = unsafe extern "C" fn(*mut c_char);
+
+/// An entry in the runtime context stack, pushed by `RuntimeContext` in
+/// hooks.h around every call into BYOND.
+#[repr(C)]
+pub struct RuntimeContext {
+	prev: *mut RuntimeContext,
+	// Doesn't return, throwing the runtime back to the library that pushed this
+	// context. `None` if BYOND should handle the runtime as usual.
+	capture: Option<RuntimeCapture>
+}
+
+#[repr(C)]
+struct Library {
+	// Only runs this library's hooks. Returns 1 if one of them handled the call.
+	call_proc_by_id_hook: CallProcHook,
+	// Only runs this library's runtime handlers
+	on_runtime: extern "C" fn(*const c_char),
+	is_hooked: extern "C" fn(raw_types::procs::ProcId) -> u8,
+	// Installs this library's detours. Returns 1 on success.
+	take_over: extern "C" fn() -> u8
+}
+
+#[repr(C)]
+struct Registry {
+	version: u32,
+	owner: *const Library,
+	libraries: [*const Library; MAX_LIBRARIES],
+	runtime_context: *mut RuntimeContext
+}
+
+static LIBRARY: Library = Library {
+	call_proc_by_id_hook: hooks::call_local_hook,
+	on_runtime: hooks::run_local_runtime_handlers,
+	is_hooked,
+	take_over
+};
+
+static mut REGISTRY: *mut Registry = std::ptr::null_mut();
+
+// Where contexts go while we aren't part of a registry
+static mut LOCAL_RUNTIME_CONTEXT: *mut RuntimeContext = std::ptr::null_mut();
+
+extern "C" fn is_hooked(id: raw_types::procs::ProcId) -> u8 {
+	hooks::is_hooked_locally(id) as u8
+}
+
+extern "C" fn take_over() -> u8 {
+	hooks::install_detours().is_ok() as u8
+}
+
+/// Lets other libraries find the registry we've joined
+#[no_mangle]
+extern "C" fn auxtools_hook_registry() -> *mut Registry {
+	unsafe { REGISTRY }
+}
+
+type RegistryGetter = extern "C" fn() -> *mut Registry;
+
+// Asks every loaded module that exports `auxtools_hook_registry` for its
+// registry, including us (which won't have one yet)
+#[cfg(windows)]
+fn find_registry() -> Option<*mut Registry> {
+	use winapi::{
+		shared::minwindef::{DWORD, HMODULE},
+		um::{libloaderapi, processthreadsapi, psapi}
+	};
+
+	unsafe {
+		let process = processthreadsapi::GetCurrentProcess();
+		let mut modules: Vec<HMODULE> = vec![std::ptr::null_mut(); 1024];
+		let size = (modules.len() * std::mem::size_of::<HMODULE>()) as DWORD;
+		let mut needed: DWORD = 0;
+
+		if psapi::EnumProcessModules(process, modules.as_mut_ptr(), size, &mut needed) == 0 {
+			return None;
+		}
+
+		// There may be more modules than we have room for, but that's unlikely
+		modules.truncate(needed.min(size) as usize / std::mem::size_of::<HMODULE>());
+		modules.into_iter().find_map(|module| {
+			let getter = libloaderapi::GetProcAddress(module, REGISTRY_SYMBOL.as_ptr() as *const c_char);
+			if getter.is_null() {
+				return None;
+			}
+
+			let registry = std::mem::transmute::<_, RegistryGetter>(getter)();
+			(!registry.is_null()).then_some(registry)
+		})
+	}
+}
+
+#[cfg(unix)]
+fn find_registry() -> Option<*mut Registry> {
+	use std::{
+		ffi::{c_void, CStr, CString},
+		os::raw::c_int
+	};
+
+	extern "C" fn add_name(info: *mut libc::dl_phdr_info, _size: usize, data: *mut c_void) -> c_int {
+		let names = unsafe { &mut *(data as *mut Vec<CString>) };
+		let name = unsafe { (*info).dlpi_name };
+
+		if !name.is_null() && unsafe { *name } != 0 {
+			names.push(unsafe { CStr::from_ptr(name) }.to_owned());
+		}
+
+		0
+	}
+
+	let mut names: Vec<CString> = vec![];
+	unsafe {
+		libc::dl_iterate_phdr(Some(add_name), &mut names as *mut Vec<CString> as *mut c_void);
+	}
+
+	names.into_iter().find_map(|name| unsafe {
+		// Only gets a handle to modules that are already loaded
+		let handle = libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_NOLOAD);
+		if handle.is_null() {
+			return None;
+		}
+
+		let getter = libc::dlsym(handle, REGISTRY_SYMBOL.as_ptr() as *const c_char);
+		let registry = match getter.is_null() {
+			true => std::ptr::null_mut(),
+			false => std::mem::transmute::<_, RegistryGetter>(getter)()
+		};
+
+		libc::dlclose(handle);
+		(!registry.is_null()).then_some(registry)
+	})
+}
+
+fn registry() -> Option<&'static mut Registry> {
+	unsafe { REGISTRY.as_mut() }
+}
+
+fn others() -> impl Iterator<Item = &'static Library> {
+	registry()
+		.into_iter()
+		.flat_map(|registry| registry.libraries.iter())
+		.filter(|library| !library.is_null() && !std::ptr::eq(**library, &LIBRARY))
+		.map(|library| unsafe { &**library })
+}
+
+/// Adds us to the registry, creating it if we're the first library to load.
+/// Returns true if we should install the detours.
+pub fn join() -> Result<bool, String> {
+	let registry = match find_registry() {
+		Some(registry) => unsafe { &mut *registry },
+		// Never freed, as other libraries may still be using it after we unload
+		None => Box::leak(Box::new(Registry {
+			version: REGISTRY_VERSION,
+			owner: std::ptr::null(),
+			libraries: [std::ptr::null(); MAX_LIBRARIES],
+			runtime_context: std::ptr::null_mut()
+		}))
+	};
+
+	join_registry(registry)
+}
+
+fn join_registry(registry: &'static mut Registry) -> Result<bool, String> {
+	if registry.version != REGISTRY_VERSION {
+		return Err(format!(
+			"another auxtools library uses hook registry version {} (expected {})",
+			registry.version, REGISTRY_VERSION
+		));
+	}
+
+	let slot = registry
+		.libraries
+		.iter_mut()
+		.find(|x| x.is_null())
+		.ok_or_else(|| format!("more than {} auxtools libraries are loaded", MAX_LIBRARIES))?;
+	*slot = &LIBRARY;
+
+	let is_owner = registry.owner.is_null();
+	if is_owner {
+		registry.owner = &LIBRARY;
+	}
+
+	unsafe {
+		REGISTRY = registry;
+	}
+
+	Ok(is_owner)
+}
+
+/// Returns true if we installed the detours everybody else relies on
+pub fn is_owner() -> bool {
+	registry().is_some_and(|registry| std::ptr::eq(registry.owner, &LIBRARY))
+}
+
+/// Removes us from the registry. If we were the owner, our detours must already
+/// be disabled, and the next library in line installs its own.
+pub fn leave() {
+	let registry = match registry() {
+		Some(registry) => registry,
+		None => return
+	};
+
+	for library in registry.libraries.iter_mut().filter(|x| std::ptr::eq(**x, &LIBRARY)) {
+		*library = std::ptr::null();
+	}
+
+	if std::ptr::eq(registry.owner, &LIBRARY) {
+		registry.owner = std::ptr::null();
+
+		for library in registry.libraries.iter().filter(|x| !x.is_null()) {
+			registry.owner = *library;
+			if (unsafe { &**library }.take_over)() == 1 {
+				break;
+			}
+
+			registry.owner = std::ptr::null();
+		}
+	}
+
+	unsafe {
+		REGISTRY = std::ptr::null_mut();
+	}
+}
+
+pub fn is_hooked_elsewhere(id: raw_types::procs::ProcId) -> bool {
+	others().any(|library| (library.is_hooked)(id) == 1)
+}
+
+/// Gives the other libraries a chance to handle a call we don't have a hook for
+#[allow(clippy::too_many_arguments)]
+pub fn call_others(
+	ret: *mut raw_types::values::Value,
+	usr: raw_types::values::Value,
+	proc_type: u32,
+	proc_id: raw_types::procs::ProcId,
+	unknown1: u32,
+	src: raw_types::values::Value,
+	args: *mut raw_types::values::Value,
+	num_args: usize,
+	unknown2: u32,
+	unknown3: u32
+) -> u8 {
+	others().any(|library| (library.call_proc_by_id_hook)(ret, usr, proc_type, proc_id, unknown1, src, args, num_args, unknown2, unknown3) == 1) as u8
+}
+
+pub fn runtime_to_others(error: *const c_char) {
+	for library in others() {
+		(library.on_runtime)(error);
+	}
+}
+
+fn runtime_context_top() -> &'static mut *mut RuntimeContext {
+	match registry() {
+		Some(registry) => &mut registry.runtime_context,
+		None => unsafe { &mut *std::ptr::addr_of_mut!(LOCAL_RUNTIME_CONTEXT) }
+	}
+}
+
+// Called by `RuntimeContext` in hooks.h, whichever library it belongs to
+#[no_mangle]
+extern "C" fn runtime_context_push(context: *mut RuntimeContext) {
+	let top = runtime_context_top();
+
+	unsafe {
+		(*context).prev = *top;
+	}
+	*top = context;
+}
+
+#[no_mangle]
+extern "C" fn runtime_context_pop(context: *mut RuntimeContext) {
+	let top = runtime_context_top();
+	debug_assert!(std::ptr::eq(*top, context), "runtime contexts popped out of order");
+
+	*top = unsafe { (*context).prev };
+}
+
+// Called by our runtime hook in hooks.cpp. Returns the capture of the innermost
+// context, if it wants the runtime.
+#[no_mangle]
+extern "C" fn runtime_context_capture() -> Option<RuntimeCapture> {
+	unsafe { runtime_context_top().as_ref() }.and_then(|context| context.capture)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Stand-ins for another library's exports
+	extern "C" fn other_call_proc_by_id_hook(
+		_: *mut raw_types::values::Value,
+		_: raw_types::values::Value,
+		_: u32,
+		_: raw_types::procs::ProcId,
+		_: u32,
+		_: raw_types::values::Value,
+		_: *mut raw_types::values::Value,
+		_: usize,
+		_: u32,
+		_: u32
+	) -> u8 {
+		0
+	}
+
+	extern "C" fn other_on_runtime(_: *const c_char) {}
+
+	extern "C" fn other_is_hooked(id: raw_types::procs::ProcId) -> u8 {
+		(id.0 == 7) as u8
+	}
+
+	extern "C" fn other_take_over() -> u8 {
+		1
+	}
+
+	unsafe extern "C" fn other_capture(_: *mut c_char) {}
+
+	static OTHER: Library = Library {
+		call_proc_by_id_hook: other_call_proc_by_id_hook,
+		on_runtime: other_on_runtime,
+		is_hooked: other_is_hooked,
+		take_over: other_take_over
+	};
+
+	fn capture() -> Option<usize> {
+		runtime_context_capture().map(|x| x as usize)
+	}
+
+	// Everything touches the same statics, so this is one test
+	#[test]
+	fn shared_registry() {
+		let mut libraries = [std::ptr::null(); MAX_LIBRARIES];
+		libraries[0] = &OTHER as *const Library;
+
+		let registry = Box::leak(Box::new(Registry {
+			version: REGISTRY_VERSION,
+			owner: &OTHER,
+			libraries,
+			runtime_context: std::ptr::null_mut()
+		}));
+		let registry_ptr = registry as *mut Registry;
+
+		// The other library already owns the detours
+		assert_eq!(join_registry(registry), Ok(false));
+		assert!(!is_owner());
+		assert_eq!(auxtools_hook_registry(), registry_ptr);
+		assert!(is_hooked_elsewhere(raw_types::procs::ProcId(7)));
+		assert!(!is_hooked_elsewhere(raw_types::procs::ProcId(8)));
+		assert_eq!(capture(), None);
+
+		// The other library makes a call into BYOND that wants runtimes, which ends
+		// up in one of our hooks making a call that doesn't
+		let mut outer = RuntimeContext {
+			prev: std::ptr::null_mut(),
+			capture: Some(other_capture)
+		};
+		runtime_context_push(&mut outer);
+		assert_eq!(capture(), Some(other_capture as RuntimeCapture as usize));
+
+		let mut inner = RuntimeContext {
+			prev: std::ptr::null_mut(),
+			capture: None
+		};
+		runtime_context_push(&mut inner);
+		assert_eq!(unsafe { (*registry_ptr).runtime_context }, &mut inner as *mut RuntimeContext);
+		// The other library mustn't catch a runtime from our call
+		assert_eq!(capture(), None);
+
+		runtime_context_pop(&mut inner);
+		assert_eq!(capture(), Some(other_capture as RuntimeCapture as usize));
+		runtime_context_pop(&mut outer);
+		assert_eq!(capture(), None);
+
+		leave();
+		assert_eq!(auxtools_hook_registry(), std::ptr::null_mut());
+		assert!(unsafe { (*registry_ptr).libraries }.iter().all(|x| !std::ptr::eq(*x, &LIBRARY)));
+		assert!(std::ptr::eq(unsafe { (*registry_ptr).owner }, &OTHER));
+
+		// Contexts pushed outside of a registry stay with us
+		let mut local = RuntimeContext {
+			prev: std::ptr::null_mut(),
+			capture: Some(other_capture)
+		};
+		runtime_context_push(&mut local);
+		assert_eq!(capture(), Some(other_capture as RuntimeCapture as usize));
+		assert!(unsafe { (*registry_ptr).runtime_context }.is_null());
+		runtime_context_pop(&mut local);
+	}
+}
@@ -17,23 +17,27 @@ extern "C" {
 	CallProcById_Ptr call_proc_by_id_original = nullptr;
 }
 
-// If the top of this stack is true, we replace byond's runtime exceptions with our own
-std::stack<bool> runtime_contexts({false});
-
 extern "C" void on_runtime(const char* pError);
 
-extern "C" void runtime_hook(char* pError) {
+// Defined in hook_registry.rs. Returns the capture of whoever (us or another
+// auxtools library) pushed the innermost runtime context, if they want it.
+extern "C" RuntimeCapture_Ptr runtime_context_capture();
+
+extern "C" void runtime_capture(char* pError) {
 	const char* pErrorCorrected = (pError != nullptr) ? pError : "<null>";
-	if (runtime_contexts.top()) {
 #ifdef USE_SJLJ
-		longjmp(*current_jmp, 1);
+	longjmp(*current_jmp, 1);
 #else
-		throw AuxtoolsException(pErrorCorrected);
+	throw AuxtoolsException(pErrorCorrected);
 #endif
-		return;
+}
+
+extern "C" void runtime_hook(char* pError) {
+	if (RuntimeCapture_Ptr capture = runtime_context_capture()) {
+		capture(pError);
 	}
 
-	on_runtime(pErrorCorrected);
+	on_runtime((pError != nullptr) ? pError : "<null>");
 	return runtime_original(pError);
 }
 
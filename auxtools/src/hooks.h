@@ -16,7 +16,6 @@
 #include <setjmp.h>
 #endif
 
-#include <stack>
 #include <string>
 
 #ifdef USE_SJLJ
@@ -40,15 +39,30 @@ struct AuxtoolsException {
     std::string message;
 };
 
-extern std::stack<bool> runtime_contexts;
+using RuntimeCapture_Ptr = void(*)(char *pError);
+
+// Doesn't return, throwing the runtime back to our innermost BYOND call
+extern "C" void runtime_capture(char* pError);
+
+// Pushed around every call into BYOND. The stack is shared with any other
+// auxtools libraries loaded alongside us (see hook_registry.rs), so the layout
+// must match `RuntimeContext` there.
+struct RuntimeContext;
+extern "C" void runtime_context_push(RuntimeContext* context);
+extern "C" void runtime_context_pop(RuntimeContext* context);
 
 struct RuntimeContext {
-    RuntimeContext(bool intercept_exceptions) {
-        runtime_contexts.push(intercept_exceptions);
+    RuntimeContext* prev;
+    RuntimeCapture_Ptr capture;
+
+    RuntimeContext(bool intercept_exceptions)
+        : prev(nullptr), capture(intercept_exceptions ? runtime_capture : nullptr)
+    {
+        runtime_context_push(this);
     }
 
     ~RuntimeContext() {
-        runtime_contexts.pop();
+        runtime_context_pop(this);
     }
     RuntimeContext(const RuntimeContext&) = delete;
     RuntimeContext(RuntimeContext&&) = delete;
@@ -1,13 +1,14 @@
-use std::{
-	cell::RefCell,
-	ffi::{c_void, CStr},
-	os::raw::c_char
-};
+#[cfg(not(feature = "mock"))]
+use std::ffi::c_void;
+use std::{cell::RefCell, ffi::CStr, os::raw::c_char};
 
 use fxhash::FxHashMap;
+#[cfg(not(feature = "mock"))]
 use retour::RawDetour;
 
 use super::{proc::Proc, raw_types, value::Value};
+#[cfg(not(feature = "mock"))]
+use crate::hook_registry;
 use crate::runtime::DMResult;
 
 #[doc(hidden)]
@@ -22,6 +23,7 @@ inventory::collect!(CompileTimeHook);
 pub struct RuntimeErrorHook(pub fn(&str));
 inventory::collect!(RuntimeErrorHook);
 
+#[cfg(not(feature = "mock"))]
 extern "C" {
 	static mut call_proc_by_id_original: *const c_void;
 
@@ -41,11 +43,13 @@ extern "C" {
 	) -> raw_types::values::Value;
 }
 
+#[cfg(not(feature = "mock"))]
 struct Detours {
 	pub runtime_detour: Option<RawDetour>,
 	pub call_proc_detour: Option<RawDetour>
 }
 
+#[cfg(not(feature = "mock"))]
 impl Detours {
 	pub const fn new() -> Self {
 		Self {
@@ -55,12 +59,14 @@ impl Detours {
 	}
 }
 
+#[cfg(not(feature = "mock"))]
 thread_local!(static DETOURS: RefCell<Detours> = const { RefCell::new(Detours::new()) });
 
 pub enum HookFailure {
 	NotInitialized,
 	ProcNotFound,
 	AlreadyHooked,
+	HookedByOtherLibrary,
 	UnknownFailure
 }
 
@@ -70,25 +76,37 @@ impl std::fmt::Debug for HookFailure {
 			Self::NotInitialized => write!(f, "Library not initialized"),
 			Self::ProcNotFound => write!(f, "Proc not found"),
 			Self::AlreadyHooked => write!(f, "Proc is already hooked"),
+			Self::HookedByOtherLibrary => write!(f, "Proc is already hooked by another auxtools library"),
 			Self::UnknownFailure => write!(f, "Unknown failure")
 		}
 	}
 }
 
+#[cfg(not(feature = "mock"))]
 pub fn init() -> Result<(), String> {
+	// Another auxtools library may already have detoured everything for us
+	if hook_registry::join()? {
+		install_detours()?;
+	}
+
+	Ok(())
+}
+
+#[cfg(not(feature = "mock"))]
+pub(crate) fn install_detours() -> Result<(), String> {
 	unsafe {
-		let runtime_hook = RawDetour::new(raw_types::funcs::runtime_byond as *const (), runtime_hook as *const ()).unwrap();
+		let runtime_hook = RawDetour::new(raw_types::funcs::runtime_byond as *const (), runtime_hook as *const ()).map_err(|e| format!("{:?}", e))?;
 
-		runtime_hook.enable().unwrap();
+		runtime_hook.enable().map_err(|e| format!("{:?}", e))?;
 		runtime_original = runtime_hook.trampoline() as *const () as *const c_void;
 
 		let call_hook = RawDetour::new(
 			raw_types::funcs::call_proc_by_id_byond as *const (),
 			call_proc_by_id_hook_trampoline as *const ()
 		)
-		.unwrap();
+		.map_err(|e| format!("{:?}", e))?;
 
-		call_hook.enable().unwrap();
+		call_hook.enable().map_err(|e| format!("{:?}", e))?;
 		call_proc_by_id_original = call_hook.trampoline() as *const () as *const c_void;
 
 		DETOURS.with(|detours_cell| {
@@ -100,18 +118,33 @@ pub fn init() -> Result<(), String> {
 	Ok(())
 }
 
+#[cfg(not(feature = "mock"))]
 pub fn shutdown() {
-	unsafe {
+	if hook_registry::is_owner() {
 		DETOURS.with(|detours_cell| {
-			let detours = detours_cell.borrow();
-			let runtime_hook = detours.runtime_detour.as_ref().unwrap();
-			let call_proc_hook = detours.call_proc_detour.as_ref().unwrap();
-			runtime_hook.disable().unwrap();
-			call_proc_hook.disable().unwrap();
+			let mut detours = detours_cell.borrow_mut();
+			let runtime_hook = detours.runtime_detour.take().unwrap();
+			let call_proc_hook = detours.call_proc_detour.take().unwrap();
+			unsafe {
+				runtime_hook.disable().unwrap();
+				call_proc_hook.disable().unwrap();
+			}
 		});
 	}
+
+	// Hands the detours over to the next library if there is one
+	hook_registry::leave();
 }
 
+// The mock calls call_local_hook itself, so there's nothing to detour
+#[cfg(feature = "mock")]
+pub const fn init() -> Result<(), String> {
+	Ok(())
+}
+
+#[cfg(feature = "mock")]
+pub const fn shutdown() {}
+
 pub type ProcHook = fn(&Value, &Value, Vec<Value>) -> DMResult;
 
 thread_local! {
@@ -119,6 +152,11 @@ thread_local! {
 }
 
 fn hook_by_id(id: raw_types::procs::ProcId, hook: ProcHook, hook_path: String) -> Result<(), HookFailure> {
+	#[cfg(not(feature = "mock"))]
+	if hook_registry::is_hooked_elsewhere(id) {
+		return Err(HookFailure::HookedByOtherLibrary);
+	}
+
 	PROC_HOOKS.with(|h| {
 		let mut map = h.borrow_mut();
 		if let std::collections::hash_map::Entry::Vacant(e) = map.entry(id) {
@@ -130,6 +168,11 @@ fn hook_by_id(id: raw_types::procs::ProcId, hook: ProcHook, hook_path: String) -
 	})
 }
 
+#[cfg(not(feature = "mock"))]
+pub(crate) fn is_hooked_locally(id: raw_types::procs::ProcId) -> bool {
+	PROC_HOOKS.with(|h| h.borrow().contains_key(&id))
+}
+
 pub fn clear_hooks() {
 	PROC_HOOKS.with(|h| h.borrow_mut().clear());
 }
@@ -149,15 +192,52 @@ impl Proc {
 
 #[no_mangle]
 extern "C" fn on_runtime(error: *const c_char) {
+	run_local_runtime_handlers(error);
+
+	#[cfg(not(feature = "mock"))]
+	hook_registry::runtime_to_others(error);
+}
+
+pub(crate) extern "C" fn run_local_runtime_handlers(error: *const c_char) {
 	let str = unsafe { CStr::from_ptr(error) }.to_string_lossy();
 
+	#[cfg(feature = "metrics")]
+	crate::metrics::record_runtime();
+	crate::runtime_aggregator::record(&str);
+
 	for func in inventory::iter::<RuntimeErrorHook> {
 		func.0(&str);
 	}
 }
 
+#[cfg(not(feature = "mock"))]
 #[no_mangle]
 extern "C" fn call_proc_by_id_hook(
+	ret: *mut raw_types::values::Value,
+	usr_raw: raw_types::values::Value,
+	proc_type: u32,
+	proc_id: raw_types::procs::ProcId,
+	unknown1: u32,
+	src_raw: raw_types::values::Value,
+	args_ptr: *mut raw_types::values::Value,
+	num_args: usize,
+	unknown2: u32,
+	unknown3: u32
+) -> u8 {
+	if call_local_hook(
+		ret, usr_raw, proc_type, proc_id, unknown1, src_raw, args_ptr, num_args, unknown2, unknown3
+	) == 1
+	{
+		return 1;
+	}
+
+	hook_registry::call_others(
+		ret, usr_raw, proc_type, proc_id, unknown1, src_raw, args_ptr, num_args, unknown2, unknown3
+	)
+}
+
+// Only runs our own hooks, without passing the call on to other libraries
+pub(crate) extern "C" fn call_local_hook(
 	ret: *mut raw_types::values::Value,
 	usr_raw: raw_types::values::Value,
 	_proc_type: u32,
@@ -183,8 +263,21 @@ extern "C" fn call_proc_by_id_hook(
 				)
 			};
 
+			let span = tracing::debug_span!(
+				"hook",
+				proc = path.as_str(),
+				src = src.get_type().unwrap_or_else(|_| "null".to_owned()).as_str()
+			);
+			let _span = span.enter();
+
+			#[cfg(feature = "metrics")]
+			let start = std::time::Instant::now();
+
 			let result = hook(&src, &usr, args);
 
+			#[cfg(feature = "metrics")]
+			crate::metrics::record_hook(path, start.elapsed().as_secs_f64(), result.is_err());
+
 			match result {
 				Ok(r) => {
 					let result_raw = r.raw;
@@ -193,6 +286,7 @@ extern "C" fn call_proc_by_id_hook(
 					Some(result_raw)
 				}
 				Err(e) => {
+					tracing::debug!("hook returned an error: {}", e.message);
 					Proc::find("/proc/auxtools_stack_trace")
 						.unwrap()
 						.call(&[&Value::from_string(format!("{} HookPath: {}", e.message.as_str(), path.as_str())).unwrap()])
@@ -21,47 +21,311 @@ pub fn set_init_level(level: InitLevel) {
 //
 pub type InitFunc = fn() -> Result<(), String>;
 
+/// Where an init or shutdown function runs relative to the others of its kind.
+/// Generated by the `priority` and `after` options of the attributes.
 #[doc(hidden)]
-pub struct FullInitFunc(pub InitFunc);
+pub struct InitOrder {
+	// The full path of the function
+	pub name: &'static str,
+	pub priority: i32,
+	pub after: &'static [&'static str]
+}
 
 #[doc(hidden)]
-pub struct PartialInitFunc(pub InitFunc);
+pub struct FullInitFunc {
+	pub func: InitFunc,
+	pub order: InitOrder
+}
 
 #[doc(hidden)]
-pub struct PartialShutdownFunc(pub fn());
+pub struct PartialInitFunc {
+	pub func: InitFunc,
+	pub order: InitOrder
+}
 
 #[doc(hidden)]
-pub struct FullShutdownFunc(pub fn());
+pub struct PartialShutdownFunc {
+	pub func: fn(),
+	pub order: InitOrder
+}
+
+#[doc(hidden)]
+pub struct FullShutdownFunc {
+	pub func: fn(),
+	pub order: InitOrder
+}
 
 inventory::collect!(FullInitFunc);
 inventory::collect!(PartialInitFunc);
 inventory::collect!(PartialShutdownFunc);
 inventory::collect!(FullShutdownFunc);
 
-pub fn run_full_init() -> Result<(), String> {
-	for func in inventory::iter::<FullInitFunc> {
-		func.0()?;
+trait Ordered {
+	fn order(&self) -> &InitOrder;
+}
+
+impl Ordered for FullInitFunc {
+	fn order(&self) -> &InitOrder {
+		&self.order
 	}
+}
 
-	Ok(())
+impl Ordered for PartialInitFunc {
+	fn order(&self) -> &InitOrder {
+		&self.order
+	}
 }
 
-pub fn run_partial_init() -> Result<(), String> {
-	for func in inventory::iter::<PartialInitFunc> {
-		func.0()?;
+impl Ordered for PartialShutdownFunc {
+	fn order(&self) -> &InitOrder {
+		&self.order
 	}
+}
 
-	Ok(())
+impl Ordered for FullShutdownFunc {
+	fn order(&self) -> &InitOrder {
+		&self.order
+	}
 }
 
-pub fn run_partial_shutdown() {
-	for func in inventory::iter::<PartialShutdownFunc> {
-		func.0();
+// Finds the function `name` refers to, which can be its full path or any
+// trailing part of it
+fn resolve(names: &[&'static str], name: &str) -> Result<Option<usize>, String> {
+	if let Some(index) = names.iter().position(|x| *x == name) {
+		return Ok(Some(index));
 	}
+
+	let suffix = format!("::{}", name);
+	let mut matches = names.iter().enumerate().filter(|(_, x)| x.ends_with(&suffix));
+
+	match (matches.next(), matches.next()) {
+		(Some((index, _)), None) => Ok(Some(index)),
+		(Some((_, a)), Some((_, b))) => Err(format!("`{}` could refer to both {} and {}", name, a, b)),
+		(None, _) => Ok(None)
+	}
+}
+
+fn sort<T: Ordered + inventory::Collect, Other: Ordered + inventory::Collect>() -> Result<(Vec<&'static T>, Vec<Vec<usize>>), String> {
+	let other_names: Vec<&'static str> = inventory::iter::<Other>.into_iter().map(|x| x.order().name).collect();
+	sort_funcs(inventory::iter::<T>.into_iter().collect(), &other_names)
+}
+
+// Returns the functions in the order they should run, along with the indices
+// of the functions each one runs after. An `after` naming a function of the
+// other kind (like a full init function named by a partial one, which always
+// runs first anyway) is ignored.
+fn sort_funcs<T: Ordered>(funcs: Vec<&'static T>, other_names: &[&'static str]) -> Result<(Vec<&'static T>, Vec<Vec<usize>>), String> {
+	let names: Vec<&'static str> = funcs.iter().map(|x| x.order().name).collect();
+
+	let mut dependencies = vec![vec![]; funcs.len()];
+	for (index, func) in funcs.iter().enumerate() {
+		for after in func.order().after {
+			match resolve(&names, after)? {
+				Some(dependency) if dependency == index => return Err(format!("{} can't run after itself", names[index])),
+				Some(dependency) => dependencies[index].push(dependency),
+				None if resolve(other_names, after)?.is_some() => {}
+				None => return Err(format!("{} runs after `{}`, which doesn't exist", names[index], after))
+			}
+		}
+	}
+
+	// Repeatedly take whichever function with nothing left to wait on has the
+	// lowest priority, using the name to break ties so the order is stable
+	let mut order = vec![];
+	let mut done = vec![false; funcs.len()];
+	while order.len() < funcs.len() {
+		let next = (0..funcs.len())
+			.filter(|&index| !done[index] && dependencies[index].iter().all(|&x| done[x]))
+			.min_by_key(|&index| (funcs[index].order().priority, names[index]));
+
+		match next {
+			Some(index) => {
+				done[index] = true;
+				order.push(index);
+			}
+			None => {
+				let cycle: Vec<&str> = (0..funcs.len()).filter(|&x| !done[x]).map(|x| names[x]).collect();
+				return Err(format!("cycle between {}", cycle.join(", ")));
+			}
+		}
+	}
+
+	// Remap dependencies to positions in the sorted list
+	let mut position = vec![0; funcs.len()];
+	for (new, &old) in order.iter().enumerate() {
+		position[old] = new;
+	}
+
+	Ok((
+		order.iter().map(|&x| funcs[x]).collect(),
+		order.iter().map(|&x| dependencies[x].iter().map(|&x| position[x]).collect()).collect()
+	))
+}
+
+fn run_init<T: Ordered + inventory::Collect, Other: Ordered + inventory::Collect>(call: fn(&T) -> Result<(), String>) -> Result<(), String> {
+	let (funcs, dependencies) = sort::<T, Other>()?;
+	run_sorted(&funcs, &dependencies, call)
+}
+
+// Runs every function, skipping those that run after one that failed, and
+// reports every failure at once
+fn run_sorted<T: Ordered>(funcs: &[&T], dependencies: &[Vec<usize>], call: fn(&T) -> Result<(), String>) -> Result<(), String> {
+	let mut failed = vec![false; funcs.len()];
+	let mut errors = vec![];
+	for (index, func) in funcs.iter().enumerate() {
+		let name = func.order().name;
+
+		if let Some(&dependency) = dependencies[index].iter().find(|&&x| failed[x]) {
+			errors.push(format!("{}: skipped as {} failed", name, funcs[dependency].order().name));
+			failed[index] = true;
+			continue;
+		}
+
+		if let Err(e) = call(func) {
+			tracing::error!("{} failed: {}", name, e);
+			errors.push(format!("{}: {}", name, e));
+			failed[index] = true;
+		}
+	}
+
+	match errors.is_empty() {
+		true => Ok(()),
+		false => Err(errors.join("; "))
+	}
+}
+
+// Shutdown functions run in reverse so they can rely on anything they were
+// ordered after still being around. We can't report an error from here, so if
+// the order is invalid they run in the order they were registered.
+fn run_shutdown<T: Ordered + inventory::Collect, Other: Ordered + inventory::Collect>(call: fn(&T)) {
+	match sort::<T, Other>() {
+		Ok((funcs, _)) => funcs.iter().rev().for_each(|func| call(func)),
+		Err(_) => inventory::iter::<T>.into_iter().for_each(call)
+	}
+}
+
+pub fn run_full_init() -> Result<(), String> {
+	// Checked here too, as shutdown functions can't report errors
+	sort::<FullShutdownFunc, PartialShutdownFunc>().map_err(|e| format!("invalid full_shutdown order: {}", e))?;
+	run_init::<FullInitFunc, PartialInitFunc>(|func| (func.func)())
+}
+
+pub fn run_partial_init() -> Result<(), String> {
+	sort::<PartialShutdownFunc, FullShutdownFunc>().map_err(|e| format!("invalid shutdown order: {}", e))?;
+	run_init::<PartialInitFunc, FullInitFunc>(|func| (func.func)())
+}
+
+pub fn run_partial_shutdown() {
+	run_shutdown::<PartialShutdownFunc, FullShutdownFunc>(|func| (func.func)());
 }
 
 pub fn run_full_shutdown() {
-	for func in inventory::iter::<FullShutdownFunc> {
-		func.0();
+	run_shutdown::<FullShutdownFunc, PartialShutdownFunc>(|func| (func.func)());
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+
+	use super::*;
+
+	struct Func {
+		order: InitOrder,
+		fails: bool
+	}
+
+	impl Ordered for Func {
+		fn order(&self) -> &InitOrder {
+			&self.order
+		}
+	}
+
+	const fn func(name: &'static str, priority: i32, after: &'static [&'static str]) -> Func {
+		Func {
+			order: InitOrder { name, priority, after },
+			fails: false
+		}
+	}
+
+	fn sorted(funcs: &'static [Func], other_names: &[&'static str]) -> Result<(Vec<&'static str>, Vec<Vec<usize>>), String> {
+		let (funcs, dependencies) = sort_funcs(funcs.iter().collect(), other_names)?;
+		Ok((funcs.iter().map(|x| x.order.name).collect(), dependencies))
+	}
+
+	#[test]
+	fn ordering() {
+		static FUNCS: [Func; 5] = [
+			func("a::setup", 0, &["late"]),
+			func("b::late", 5, &[]),
+			func("c::early", -5, &[]),
+			func("e::other", 0, &[]),
+			func("d::other", 0, &[])
+		];
+
+		let (names, dependencies) = sorted(&FUNCS, &[]).unwrap();
+		assert_eq!(names, ["c::early", "d::other", "e::other", "b::late", "a::setup"]);
+		assert_eq!(dependencies, [vec![], vec![], vec![], vec![], vec![3]]);
+	}
+
+	#[test]
+	fn names() {
+		static FULL_PATH: [Func; 3] = [func("a::setup", 0, &[]), func("b::setup", 0, &[]), func("c::after", 0, &["b::setup"])];
+		assert!(sorted(&FULL_PATH, &[]).is_ok());
+
+		static AMBIGUOUS: [Func; 3] = [func("a::setup", 0, &[]), func("b::setup", 0, &[]), func("c::after", 0, &["setup"])];
+		assert_eq!(sorted(&AMBIGUOUS, &[]).unwrap_err(), "`setup` could refer to both a::setup and b::setup");
+
+		static MISSING: [Func; 1] = [func("a::after", 0, &["missing"])];
+		assert_eq!(sorted(&MISSING, &[]).unwrap_err(), "a::after runs after `missing`, which doesn't exist");
+
+		// Functions of the other kind always run first, so they're ignored
+		assert!(sorted(&MISSING, &["b::missing"]).is_ok());
+	}
+
+	#[test]
+	fn cycles() {
+		static SELF: [Func; 1] = [func("a::first", 0, &["first"])];
+		assert_eq!(sorted(&SELF, &[]).unwrap_err(), "a::first can't run after itself");
+
+		static CYCLE: [Func; 4] = [
+			func("a::first", 0, &["second"]),
+			func("b::second", 0, &["third"]),
+			func("c::third", 0, &["first"]),
+			func("d::fine", 0, &[])
+		];
+		assert_eq!(sorted(&CYCLE, &[]).unwrap_err(), "cycle between a::first, b::second, c::third");
+	}
+
+	thread_local! {
+		static RAN: RefCell<Vec<&'static str>> = const { RefCell::new(vec![]) };
+	}
+
+	fn call(func: &Func) -> Result<(), String> {
+		RAN.with(|ran| ran.borrow_mut().push(func.order.name));
+
+		match func.fails {
+			true => Err("broken".to_owned()),
+			false => Ok(())
+		}
+	}
+
+	#[test]
+	fn failures() {
+		static FUNCS: [Func; 4] = [
+			Func {
+				fails: true,
+				..func("a::broken", 0, &[])
+			},
+			func("b::dependent", 0, &["broken"]),
+			func("c::independent", 0, &[]),
+			func("d::transitive", 0, &["dependent"])
+		];
+
+		let (funcs, dependencies) = sort_funcs(FUNCS.iter().collect(), &[]).unwrap();
+		assert_eq!(
+			run_sorted(&funcs, &dependencies, call).unwrap_err(),
+			"a::broken: broken; b::dependent: skipped as a::broken failed; d::transitive: skipped as b::dependent failed"
+		);
+		assert_eq!(RAN.with(|ran| ran.take()), ["a::broken", "c::independent"]);
 	}
 }
@@ -4,19 +4,34 @@
 // compile_error!("Auxtools must be compiled for a 32-bit target");
 
 mod byond_ffi;
+pub mod byondapi;
 mod bytecode_manager;
 pub mod debug;
+pub mod heap;
+#[cfg(not(feature = "mock"))]
+mod hook_registry;
 mod hooks;
 mod init;
 mod list;
+pub mod log;
+pub mod main_thread;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod persist;
 mod proc;
 pub mod raw_types;
+pub mod refs;
 mod runtime;
+pub mod runtime_aggregator;
 pub mod sigscan;
 mod string;
 mod string_intern;
+mod type_table;
 mod value;
 mod value_from;
+mod value_ops;
 pub mod version;
 mod weak_value;
 
@@ -25,20 +40,24 @@ use std::{
 	sync::atomic::{AtomicBool, Ordering}
 };
 
-pub use auxtools_impl::{full_shutdown, hook, init, pin_dll, runtime_handler, shutdown};
+pub use auxtools_impl::{full_shutdown, hook, init, persist, pin_dll, runtime_handler, shutdown};
 /// Used by the [pin_dll] macro to set dll pinning
 pub use ctor;
 pub use hooks::{CompileTimeHook, RuntimeErrorHook};
 use init::{get_init_level, set_init_level, InitLevel};
-pub use init::{FullInitFunc, FullShutdownFunc, PartialInitFunc, PartialShutdownFunc};
+pub use init::{FullInitFunc, FullShutdownFunc, InitOrder, PartialInitFunc, PartialShutdownFunc};
 /// Used by the [hook](attr.hook.html) macro to aggregate all compile-time hooks
 pub use inventory;
 pub use list::List;
+pub use main_thread::{MainThreadCell, ReloadableState};
+pub use persist::{PersistRestoreFunc, PersistSaveFunc};
 pub use proc::Proc;
 pub use raw_types::variables::VariableNameIdTable;
 pub use runtime::{DMResult, Runtime};
 pub use string::StringRef;
 pub use string_intern::InternedString;
+/// Used by the [log] module, and re-exported so libraries log through the same version
+pub use tracing;
 pub use value::Value;
 pub use weak_value::WeakValue;
 
@@ -73,6 +92,8 @@ signatures! {
 		1602..1614 => "55 8B EC 6A FF 68 ?? ?? ?? ?? 64 A1 00 00 00 00 50 83 EC 08 56 57 A1 ?? ?? ?? ?? 33 C5 50 8D 45 F4 64 A3 00 00 00 00 8B 4D",
 		..1602 => "55 8B EC 8B 4D 08 0F B6 C1 48 57 8B 7D 10 83 F8 53 0F ?? ?? ?? ?? ?? 0F B6 80 ?? ?? ?? ?? FF 24 85 ?? ?? ?? ?? FF 75 18 FF 75 14 57 FF 75 0C E8 ?? ?? ?? ?? 83 C4 10 5F 5D C3"),
 	get_string_table_entry => universal_signature!("55 8B EC 8B 4D 08 3B 0D ?? ?? ?? ?? 73 10 A1"),
+	// The operand of the bounds check at the start of `get_string_table_entry`
+	string_table_length => universal_signature!(8, "55 8B EC 8B 4D 08 3B 0D ?? ?? ?? ?? 73 10 A1"),
 	call_datum_proc_by_name => version_dependent_signature!(
 		1615.. => "55 8B EC 6A FF 68 ?? ?? ?? ?? 64 A1 00 00 00 00 50 83 EC 14 53 56 57 A1 ?? ?? ?? ?? 33 C5 50 8D 45 F4 64 A3 00 00 00 00 8B 75 1C 8D 45 F3 8B 7D 18 8B 5D 10 6A 00",
 		1602..1614 => "55 8B EC 6A FF 68 ?? ?? ?? ?? 64 A1 00 00 00 00 50 83 EC 18 53 56 57 A1 ?? ?? ?? ?? 33 C5 50 8D 45 F4 64 A3 00 00 00 00 8B 75 14 8D 45 F3 8B 5D 10 6A 01",
@@ -161,6 +182,8 @@ signatures! {
 	),
 
 	get_string_table_entry => universal_signature!("55 89 E5 83 EC 18 8B 45 ?? 39 05 ?? ?? ?? ?? 76 ?? 8B 15 ?? ?? ?? ?? 8B 04 ??"),
+	// The operand of the bounds check at the start of `get_string_table_entry`
+	string_table_length => universal_signature!(11, "55 89 E5 83 EC 18 8B 45 ?? 39 05 ?? ?? ?? ?? 76 ?? 8B 15 ?? ?? ?? ?? 8B 04 ??"),
 	call_datum_proc_by_name => version_dependent_signature!(
 		1606.. => "55 89 E5 57 56 89 CE 53 89 D3 83 EC ?? 0F B6 55 ?? 89 45 ?? 8B 45 ?? 8B 7D ?? C6 45 E7 ?? 0F B6 CA 89 45 B0 8D 45 ?? 89 44 24 ?? 8B 45 ?? 89 ?? BC 31 C9 88 ?? BB 8B 55 ?? C7 44 24 ?? 01 00 00 00",
 		..1606 => "55 89 E5 57 56 53 83 EC 5C 8B 55 ?? 0F B6 45 ?? 8B 4D ?? 8B 5D ?? 89 14 24 8B 55 ?? 88 45 ?? 0F B6 F8 8B 75 ?? 8D 45 ?? 89 44 24 ?? 89 F8 89 4C 24 ?? 31 C9 C6 45 ?? 00 C7 44 24 ?? 01 00 00 00"
@@ -229,7 +252,9 @@ fn pin_dll() -> Result<(), ()> {
 	Ok(())
 }
 
-byond_ffi_fn! { auxtools_init(_input) {
+byond_ffi_fn! { auxtools_init(input) {
+	main_thread::set_main_thread();
+
 	if get_init_level() == InitLevel::None {
 		return Some("SUCCESS".to_owned())
 	}
@@ -252,6 +277,7 @@ byond_ffi_fn! { auxtools_init(_input) {
 			(current_execution_context as *mut *mut raw_types::procs::ExecutionContext),
 			(suspended_procs as *mut raw_types::procs::SuspendedProcs),
 			(suspended_procs_buffer as *mut raw_types::procs::SuspendedProcsBuffer),
+			(string_table_length as *const u32),
 			call_proc_by_id,
 			call_datum_proc_by_name,
 			get_proc_array_entry,
@@ -276,6 +302,7 @@ byond_ffi_fn! { auxtools_init(_input) {
 			raw_types::funcs::CURRENT_EXECUTION_CONTEXT = current_execution_context;
 			raw_types::funcs::SUSPENDED_PROCS = suspended_procs;
 			raw_types::funcs::SUSPENDED_PROCS_BUFFER = suspended_procs_buffer;
+			raw_types::funcs::STRING_TABLE_LENGTH = string_table_length;
 			raw_types::funcs::call_proc_by_id_byond = call_proc_by_id;
 			raw_types::funcs::call_datum_proc_by_name_byond = call_datum_proc_by_name;
 			raw_types::funcs::get_proc_array_entry_byond = get_proc_array_entry;
@@ -346,6 +373,31 @@ byond_ffi_fn! { auxtools_init(_input) {
 		}
 	}
 
+	// State handed over by the library we're replacing. Init has gone through
+	// by now, so a failure here is reported without failing the call
+	if persist::is_persisted_state(input) {
+		if let Err(err) = persist::restore(input) {
+			return Some(format!("SUCCESS (Couldn't restore persisted state: {})", err));
+		}
+	}
+
+	Some("SUCCESS".to_owned())
+} }
+
+byond_ffi_fn! { auxtools_persist(_input) {
+	if get_init_level() != InitLevel::None {
+		return Some("FAILED (auxtools is not initialized)".to_owned())
+	}
+
+	Some(persist::save())
+} }
+
+byond_ffi_fn! { auxtools_runtime_summary(limit) {
+	Some(runtime_aggregator::summary_json(limit.parse().ok()))
+} }
+
+byond_ffi_fn! { auxtools_runtime_reset(_input) {
+	runtime_aggregator::reset();
 	Some("SUCCESS".to_owned())
 } }
 
@@ -354,6 +406,7 @@ byond_ffi_fn! { auxtools_shutdown(_input) {
 		return Some("FAILED (already shut down)".to_owned())
 	};
 	init::run_partial_shutdown();
+	main_thread::reset_reloadable_states();
 	string_intern::destroy_interned_strings();
 	bytecode_manager::shutdown();
 
@@ -374,6 +427,7 @@ byond_ffi_fn! { auxtools_full_shutdown(_input) {
 	};
 	if get_init_level() == InitLevel::None {
 		init::run_partial_shutdown();
+		main_thread::reset_reloadable_states();
 		string_intern::destroy_interned_strings();
 		bytecode_manager::shutdown();
 
@@ -387,6 +441,11 @@ byond_ffi_fn! { auxtools_full_shutdown(_input) {
 	hooks::shutdown();
 	set_init_level(InitLevel::Full);
 	init::run_full_shutdown();
+	log::shutdown();
+	runtime_aggregator::stop_dumping();
+
+	#[cfg(feature = "metrics")]
+	metrics::stop();
 
 	if !PIN_DLL.load(Ordering::Relaxed) {
 		#[cfg(windows)]
@@ -413,6 +472,29 @@ byond_ffi_fn! { auxtools_full_shutdown(_input) {
 	Some("SUCCESS".to_owned())
 } }
 
+byond_ffi_fn! { auxtools_find_references(reference) {
+	if get_init_level() != InitLevel::None {
+		return Some("FAILED (auxtools is not initialized)".to_owned())
+	}
+
+	match refs::parse_ref(reference) {
+		Some(target) => Some(refs::find_references(&target).join("\n")),
+		None => Some(format!("FAILED (invalid reference {:?})", reference))
+	}
+} }
+
+byond_ffi_fn! { auxtools_heap_snapshot(path) {
+	if get_init_level() != InitLevel::None {
+		return Some("FAILED (auxtools is not initialized)".to_owned())
+	}
+
+	let snapshot = heap::snapshot();
+	match std::fs::File::create(path).and_then(|file| snapshot.write(std::io::BufWriter::new(file))) {
+		Ok(()) => Some("SUCCESS".to_owned()),
+		Err(e) => Some(format!("FAILED ({})", e))
+	}
+} }
+
 byond_ffi_fn! { auxtools_check_signatures(_input) {
 	let byondcore = match sigscan::Scanner::for_module(BYONDCORE) {
 		Some(v) => v,
@@ -0,0 +1,430 @@
+//! Routes [tracing] events to `world.log`, a rotating file or stderr.
+//!
+//! Nothing is logged until [init] is called, usually from an `#[init(full)]`
+//! function, and logging stops when the library is fully shut down. auxtools
+//! emits its own events (like init failures and errors returned from hooks)
+//! and wraps every hook call in a `hook` span holding the proc's path and the
+//! type of `src`. Hook spans are at the `DEBUG` level, so they cost nothing
+//! unless that level is enabled.
+//!
+//! Writing to `world.log` goes through a proc the world has to define:
+//!
+//! ```dm
+//! /proc/auxtools_log(msg)
+//!     world.log << msg
+//! ```
+//!
+//! BYOND can only be called from the main thread, so events from other threads
+//! are held until the main thread logs something itself.
+//!
+//! # Examples
+//! ```ignore
+//! #[init(full)]
+//! fn logging_init() -> Result<(), String> {
+//!     auxtools::log::init(tracing::Level::INFO, vec![
+//!         auxtools::log::Target::WorldLog,
+//!         auxtools::log::Target::File { path: "data/logs/auxtools.log".into(), max_size: 10 * 1024 * 1024, max_files: 5 },
+//!     ])
+//! }
+//! ```
+use std::{
+	cell::RefCell,
+	collections::HashMap,
+	fmt::{self, Write as _},
+	fs::{self, File},
+	io::Write,
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex, MutexGuard, Once
+	},
+	thread::ThreadId,
+	time::{SystemTime, UNIX_EPOCH}
+};
+
+use tracing::{
+	field::{Field, Visit},
+	span, Event, Level, Metadata, Subscriber
+};
+
+use crate::{Proc, Value};
+
+const WORLD_LOG_PROC: &str = "/proc/auxtools_log";
+
+/// Somewhere events are written to
+pub enum Target {
+	/// `world.log`, through `/proc/auxtools_log`
+	WorldLog,
+	Stderr,
+	/// A file that is renamed to `<path>.1` once it grows past `max_size`
+	/// bytes, keeping at most `max_files` old files around
+	File {
+		path: PathBuf,
+		max_size: u64,
+		max_files: usize
+	}
+}
+
+struct FileSink {
+	path: PathBuf,
+	max_size: u64,
+	max_files: usize,
+	file: Option<File>,
+	size: u64
+}
+
+impl FileSink {
+	fn new(path: PathBuf, max_size: u64, max_files: usize) -> Result<Self, String> {
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent).map_err(|e| format!("couldn't create {}: {}", parent.display(), e))?;
+		}
+
+		let mut sink = FileSink {
+			path,
+			max_size,
+			max_files,
+			file: None,
+			size: 0
+		};
+
+		sink.open()?;
+		Ok(sink)
+	}
+
+	fn open(&mut self) -> Result<(), String> {
+		let file = fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)
+			.map_err(|e| format!("couldn't open {}: {}", self.path.display(), e))?;
+
+		self.size = file.metadata().map(|x| x.len()).unwrap_or(0);
+		self.file = Some(file);
+		Ok(())
+	}
+
+	fn rotated_path(&self, index: usize) -> PathBuf {
+		let mut path = self.path.clone().into_os_string();
+		path.push(format!(".{}", index));
+		path.into()
+	}
+
+	fn rotate(&mut self) {
+		self.file = None;
+
+		if self.max_files == 0 {
+			let _ = fs::remove_file(&self.path);
+		} else {
+			let _ = fs::remove_file(self.rotated_path(self.max_files));
+			for index in (1..self.max_files).rev() {
+				let _ = fs::rename(self.rotated_path(index), self.rotated_path(index + 1));
+			}
+
+			let _ = fs::rename(&self.path, self.rotated_path(1));
+		}
+
+		let _ = self.open();
+	}
+
+	fn write(&mut self, line: &str) {
+		if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_size {
+			self.rotate();
+		}
+
+		if let Some(file) = &mut self.file {
+			if writeln!(file, "{}", line).is_ok() {
+				self.size += line.len() as u64 + 1;
+			}
+		}
+	}
+}
+
+struct Config {
+	level: Level,
+	world_log: bool,
+	stderr: bool,
+	files: Vec<FileSink>,
+	main_thread: ThreadId,
+	// Lines for world.log that were logged off the main thread
+	pending: Vec<String>
+}
+
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+static INSTALL: Once = Once::new();
+
+fn config() -> MutexGuard<'static, Option<Config>> {
+	CONFIG.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts writing events at `level` or above to the given targets, replacing
+/// whatever an earlier call set up. Must be called from the main thread.
+///
+/// Fails if a file can't be opened, or if something other than auxtools has
+/// already installed a global tracing subscriber.
+pub fn init(level: Level, targets: Vec<Target>) -> Result<(), String> {
+	let mut settings = Config {
+		level,
+		world_log: false,
+		stderr: false,
+		files: vec![],
+		main_thread: std::thread::current().id(),
+		pending: vec![]
+	};
+
+	for target in targets {
+		match target {
+			Target::WorldLog => settings.world_log = true,
+			Target::Stderr => settings.stderr = true,
+			Target::File { path, max_size, max_files } => settings.files.push(FileSink::new(path, max_size, max_files)?)
+		}
+	}
+
+	let mut result = Ok(());
+	INSTALL.call_once(|| {
+		result = tracing::subscriber::set_global_default(LogSubscriber::default()).map_err(|e| e.to_string());
+	});
+	result?;
+
+	*config() = Some(settings);
+	tracing::callsite::rebuild_interest_cache();
+	Ok(())
+}
+
+/// Stops writing events until [init] is called again
+pub fn shutdown() {
+	*config() = None;
+	tracing::callsite::rebuild_interest_cache();
+}
+
+// Collects the fields of a span or event as ` name=value` pairs, apart from
+// the message which is kept separately
+#[derive(Default)]
+struct FieldVisitor {
+	message: String,
+	fields: String
+}
+
+impl Visit for FieldVisitor {
+	fn record_str(&mut self, field: &Field, value: &str) {
+		match field.name() {
+			"message" => self.message.push_str(value),
+			name => {
+				let _ = write!(self.fields, " {}={}", name, value);
+			}
+		}
+	}
+
+	fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+		match field.name() {
+			"message" => {
+				let _ = write!(self.message, "{:?}", value);
+			}
+			name => {
+				let _ = write!(self.fields, " {}={:?}", name, value);
+			}
+		}
+	}
+}
+
+struct SpanData {
+	name: &'static str,
+	fields: String,
+	refs: usize
+}
+
+#[derive(Default)]
+struct LogSubscriber {
+	next_id: AtomicU64,
+	spans: Mutex<HashMap<u64, SpanData>>
+}
+
+thread_local! {
+	static CURRENT_SPANS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+impl LogSubscriber {
+	fn spans(&self) -> MutexGuard<'_, HashMap<u64, SpanData>> {
+		self.spans.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	// Formats the spans we're inside of like `outer{a=1}:inner{b=2}: `
+	fn context(&self) -> String {
+		let spans = self.spans();
+		let mut context = String::new();
+
+		CURRENT_SPANS.with(|current| {
+			for id in current.borrow().iter() {
+				if let Some(span) = spans.get(id) {
+					let _ = write!(context, "{}{{{}}}:", span.name, span.fields.trim_start());
+				}
+			}
+		});
+
+		if !context.is_empty() {
+			context.push(' ');
+		}
+
+		context
+	}
+}
+
+impl Subscriber for LogSubscriber {
+	fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+		config().as_ref().is_some_and(|config| *metadata.level() <= config.level)
+	}
+
+	fn max_level_hint(&self) -> Option<tracing::level_filters::LevelFilter> {
+		Some(match config().as_ref() {
+			Some(config) => config.level.into(),
+			None => tracing::level_filters::LevelFilter::OFF
+		})
+	}
+
+	fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+		let mut visitor = FieldVisitor::default();
+		attrs.record(&mut visitor);
+
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+		self.spans().insert(
+			id,
+			SpanData {
+				name: attrs.metadata().name(),
+				fields: visitor.fields,
+				refs: 1
+			}
+		);
+
+		span::Id::from_u64(id)
+	}
+
+	fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+		let mut visitor = FieldVisitor::default();
+		values.record(&mut visitor);
+
+		if let Some(span) = self.spans().get_mut(&span.into_u64()) {
+			span.fields.push_str(&visitor.fields);
+		}
+	}
+
+	fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+	fn event(&self, event: &Event<'_>) {
+		let mut visitor = FieldVisitor::default();
+		event.record(&mut visitor);
+
+		let line = format!(
+			"[{}] {:>5} {}{}{}",
+			timestamp(),
+			event.metadata().level(),
+			self.context(),
+			visitor.message,
+			visitor.fields
+		);
+
+		let mut guard = config();
+		let config = match guard.as_mut() {
+			Some(config) => config,
+			None => return
+		};
+
+		if config.stderr {
+			eprintln!("{}", line);
+		}
+
+		for file in &mut config.files {
+			file.write(&line);
+		}
+
+		if config.world_log {
+			config.pending.push(line);
+
+			if std::thread::current().id() == config.main_thread {
+				let lines = std::mem::take(&mut config.pending);
+				// Logging from inside auxtools_log would deadlock on the config
+				drop(guard);
+				write_world_log(lines);
+			}
+		}
+	}
+
+	fn enter(&self, span: &span::Id) {
+		CURRENT_SPANS.with(|current| current.borrow_mut().push(span.into_u64()));
+	}
+
+	fn exit(&self, span: &span::Id) {
+		CURRENT_SPANS.with(|current| {
+			let mut current = current.borrow_mut();
+			if let Some(index) = current.iter().rposition(|x| *x == span.into_u64()) {
+				current.remove(index);
+			}
+		});
+	}
+
+	fn clone_span(&self, span: &span::Id) -> span::Id {
+		if let Some(span) = self.spans().get_mut(&span.into_u64()) {
+			span.refs += 1;
+		}
+
+		span.clone()
+	}
+
+	fn try_close(&self, span: span::Id) -> bool {
+		let mut spans = self.spans();
+		let closed = match spans.get_mut(&span.into_u64()) {
+			Some(data) => {
+				data.refs -= 1;
+				data.refs == 0
+			}
+			None => false
+		};
+
+		if closed {
+			spans.remove(&span.into_u64());
+		}
+
+		closed
+	}
+}
+
+fn write_world_log(lines: Vec<String>) {
+	// The world may not have defined the proc, or auxtools may be shut down
+	let proc = match Proc::find(WORLD_LOG_PROC) {
+		Some(proc) => proc,
+		None => return
+	};
+
+	for line in lines {
+		if let Ok(line) = Value::from_string(line) {
+			let _ = proc.call(&[&line]);
+		}
+	}
+}
+
+// UTC, as `YYYY-MM-DD HH:MM:SS.mmm`
+fn timestamp() -> String {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	let secs = now.as_secs() as i64;
+	let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+	// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + (month <= 2) as i64;
+
+	format!(
+		"{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+		year,
+		month,
+		day,
+		time / 3600,
+		time % 3600 / 60,
+		time % 60,
+		now.subsec_millis()
+	)
+}
@@ -0,0 +1,182 @@
+//! Global state for code that only runs on BYOND's main thread.
+//!
+//! Hooks, init functions and instruction hooks all run on the main thread, so
+//! their state doesn't need locking, but `static mut` makes it easy to touch it
+//! from somewhere else by accident and easy to forget to reset it when the
+//! library is shut down. [MainThreadCell] panics if it is used from any other
+//! thread, and [ReloadableState] also throws its value away whenever
+//! `auxtools_shutdown` is called, after the shutdown functions have run.
+//!
+//! # Examples
+//! ```ignore
+//! static HOOKED_CALLS: ReloadableState<u32> = ReloadableState::new(|| 0);
+//!
+//! #[hook("/proc/counted")]
+//! fn counted() {
+//!     HOOKED_CALLS.with(|x| *x += 1);
+//!     Ok(Value::from(HOOKED_CALLS.get()))
+//! }
+//! ```
+use std::cell::{Cell, UnsafeCell};
+
+thread_local! {
+	static IS_MAIN_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+// Set by auxtools_init, which BYOND always calls from its main thread
+pub(crate) fn set_main_thread() {
+	IS_MAIN_THREAD.with(|x| x.set(true));
+}
+
+/// Returns true if we're on the thread BYOND runs DM code on
+pub fn is_main_thread() -> bool {
+	IS_MAIN_THREAD.with(|x| x.get())
+}
+
+fn assert_main_thread() {
+	assert!(is_main_thread(), "main thread state accessed from another thread");
+}
+
+/// A value that can only be accessed from the main thread.
+pub struct MainThreadCell<T> {
+	value: UnsafeCell<T>,
+	borrowed: Cell<bool>
+}
+
+// Every access checks that it's on the main thread, so the value is never
+// shared between threads
+unsafe impl<T> Sync for MainThreadCell<T> {}
+
+impl<T> MainThreadCell<T> {
+	pub const fn new(value: T) -> Self {
+		Self {
+			value: UnsafeCell::new(value),
+			borrowed: Cell::new(false)
+		}
+	}
+
+	/// Runs `f` with the value. Panics if called from another thread, or from
+	/// inside another call to `with` on the same cell.
+	pub fn with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+		self.try_with(f).expect("main thread state is already in use")
+	}
+
+	/// Like [MainThreadCell::with], but returns `None` instead of panicking if
+	/// the value is already in use further up the stack.
+	///
+	/// For code that can be re-entered, like instruction hooks that end up
+	/// running more DM.
+	pub fn try_with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+		assert_main_thread();
+		if self.borrowed.replace(true) {
+			return None;
+		}
+
+		struct Release<'a>(&'a Cell<bool>);
+
+		impl Drop for Release<'_> {
+			fn drop(&mut self) {
+				self.0.set(false);
+			}
+		}
+
+		let _release = Release(&self.borrowed);
+		Some(f(unsafe { &mut *self.value.get() }))
+	}
+
+	pub fn replace(&self, value: T) -> T {
+		self.with(|x| std::mem::replace(x, value))
+	}
+
+	pub fn set(&self, value: T) {
+		drop(self.replace(value));
+	}
+
+	pub fn get(&self) -> T
+	where
+		T: Copy
+	{
+		self.with(|x| *x)
+	}
+}
+
+trait Reset {
+	fn reset(&self);
+}
+
+// Every ReloadableState that has been initialized since the last shutdown
+static RELOADABLE_STATES: MainThreadCell<Vec<&'static dyn Reset>> = MainThreadCell::new(Vec::new());
+
+/// A [MainThreadCell] that's created from `init` when first used, and dropped
+/// when auxtools is shut down so the next use starts fresh.
+pub struct ReloadableState<T: 'static> {
+	value: MainThreadCell<Option<T>>,
+	init: fn() -> T
+}
+
+impl<T: 'static> ReloadableState<T> {
+	pub const fn new(init: fn() -> T) -> Self {
+		Self {
+			value: MainThreadCell::new(None),
+			init
+		}
+	}
+
+	fn ensure_init(&'static self) {
+		// If it's in use further up the stack it has already been created
+		let created = self.value.try_with(|value| {
+			if value.is_some() {
+				return false;
+			}
+
+			*value = Some((self.init)());
+			true
+		});
+
+		if created == Some(true) {
+			RELOADABLE_STATES.with(|states| states.push(self));
+		}
+	}
+
+	/// See [MainThreadCell::with]
+	pub fn with<R, F: FnOnce(&mut T) -> R>(&'static self, f: F) -> R {
+		self.ensure_init();
+		self.value.with(|value| f(value.as_mut().unwrap()))
+	}
+
+	/// See [MainThreadCell::try_with]
+	pub fn try_with<R, F: FnOnce(&mut T) -> R>(&'static self, f: F) -> Option<R> {
+		self.ensure_init();
+		self.value.try_with(|value| f(value.as_mut().unwrap()))
+	}
+
+	pub fn replace(&'static self, value: T) -> T {
+		self.with(|x| std::mem::replace(x, value))
+	}
+
+	pub fn set(&'static self, value: T) {
+		drop(self.replace(value));
+	}
+
+	pub fn get(&'static self) -> T
+	where
+		T: Copy
+	{
+		self.with(|x| *x)
+	}
+}
+
+impl<T: 'static> Reset for ReloadableState<T> {
+	fn reset(&self) {
+		// Dropped outside of the cell in case its Drop uses other state
+		let value = self.value.replace(None);
+		drop(value);
+	}
+}
+
+// Called when auxtools shuts down, after the shutdown functions
+pub(crate) fn reset_reloadable_states() {
+	for state in RELOADABLE_STATES.replace(Vec::new()) {
+		state.reset();
+	}
+}
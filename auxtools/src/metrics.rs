@@ -0,0 +1,168 @@
+//! Counts hook calls, hook failures, time spent in hooks and runtimes, and
+//! serves them in the Prometheus text format. Enabled by the `metrics` feature.
+//!
+//! Nothing is served until [serve] is called, but counting starts as soon as
+//! the library is loaded. The server runs on its own thread and only listens on
+//! localhost. It is stopped when the library is fully shut down.
+//!
+//! # Examples
+//! ```ignore
+//! #[init(full)]
+//! fn metrics_init() -> Result<(), String> {
+//!     auxtools::metrics::serve(9100).map(|_| ())
+//! }
+//! ```
+use std::{
+	collections::HashMap,
+	fmt::Write as _,
+	io::{Read, Write},
+	net::{TcpListener, TcpStream},
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Mutex, MutexGuard
+	},
+	thread::JoinHandle,
+	time::Duration
+};
+
+#[derive(Default)]
+struct HookStats {
+	calls: u64,
+	failures: u64,
+	seconds: f64
+}
+
+static HOOKS: Mutex<Option<HashMap<String, HookStats>>> = Mutex::new(None);
+static RUNTIMES: AtomicU64 = AtomicU64::new(0);
+
+static SERVER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) fn record_hook(path: &str, seconds: f64, failed: bool) {
+	let mut hooks = lock(&HOOKS);
+	let hooks = hooks.get_or_insert_with(HashMap::new);
+
+	// Avoid allocating the path on every call
+	let stats = match hooks.get_mut(path) {
+		Some(stats) => stats,
+		None => hooks.entry(path.to_owned()).or_default()
+	};
+
+	stats.calls += 1;
+	stats.failures += failed as u64;
+	stats.seconds += seconds;
+}
+
+pub(crate) fn record_runtime() {
+	RUNTIMES.fetch_add(1, Ordering::Relaxed);
+}
+
+fn escape_label(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_family(out: &mut String, name: &str, help: &str, hooks: &[(&String, &HookStats)], value: fn(&HookStats) -> String) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} counter", name);
+	for (path, stats) in hooks {
+		let _ = writeln!(out, "{}{{proc=\"{}\"}} {}", name, escape_label(path), value(stats));
+	}
+}
+
+/// Returns every metric in the Prometheus text format
+pub fn render() -> String {
+	let mut out = String::new();
+
+	let hooks = lock(&HOOKS);
+	let mut hooks: Vec<_> = hooks.iter().flatten().collect();
+	hooks.sort_by(|a, b| a.0.cmp(b.0));
+
+	write_family(
+		&mut out,
+		"auxtools_hook_calls_total",
+		"Number of times each hook has been called.",
+		&hooks,
+		|x| x.calls.to_string()
+	);
+	write_family(
+		&mut out,
+		"auxtools_hook_failures_total",
+		"Number of times each hook has returned an error.",
+		&hooks,
+		|x| x.failures.to_string()
+	);
+	write_family(&mut out, "auxtools_hook_seconds_total", "Time spent running each hook.", &hooks, |x| {
+		x.seconds.to_string()
+	});
+
+	let _ = writeln!(out, "# HELP auxtools_runtimes_total Number of runtime errors.");
+	let _ = writeln!(out, "# TYPE auxtools_runtimes_total counter");
+	let _ = writeln!(out, "auxtools_runtimes_total {}", RUNTIMES.load(Ordering::Relaxed));
+
+	out
+}
+
+// Every request gets the metrics, whatever its path
+fn respond(mut stream: TcpStream) {
+	let _ = stream.set_nonblocking(false);
+	let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+
+	// Read (and ignore) the request up to the end of its headers
+	let mut request = vec![];
+	let mut buf = [0; 1024];
+	while !request.windows(4).any(|x| x == b"\r\n\r\n") && request.len() < 16 * 1024 {
+		match stream.read(&mut buf) {
+			Ok(0) | Err(_) => break,
+			Ok(n) => request.extend_from_slice(&buf[..n])
+		}
+	}
+
+	let body = render();
+	let _ = write!(
+		stream,
+		"HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		body.len(),
+		body
+	);
+}
+
+/// Starts serving the metrics on `127.0.0.1:port`, replacing any server that
+/// was already running. Returns the port, which is picked by the OS if `port`
+/// is 0.
+pub fn serve(port: u16) -> Result<u16, String> {
+	stop();
+
+	let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("couldn't listen on port {}: {}", port, e))?;
+	let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+
+	// Polled so the thread notices when it should stop
+	listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+	STOP.store(false, Ordering::Relaxed);
+	let thread = std::thread::Builder::new()
+		.name("auxtools-metrics".to_owned())
+		.spawn(move || {
+			while !STOP.load(Ordering::Relaxed) {
+				match listener.accept() {
+					Ok((stream, _)) => respond(stream),
+					Err(_) => std::thread::sleep(Duration::from_millis(100))
+				}
+			}
+		})
+		.map_err(|e| e.to_string())?;
+
+	*lock(&SERVER) = Some(thread);
+	Ok(port)
+}
+
+/// Stops the server started by [serve]. The counts are kept.
+pub fn stop() {
+	if let Some(thread) = lock(&SERVER).take() {
+		STOP.store(true, Ordering::Relaxed);
+		let _ = thread.join();
+	}
+}
@@ -0,0 +1,411 @@
+//! An in-process fake of the parts of BYOND that auxtools talks to, so that
+//! [Value], [List], [StringRef] and proc hooks can be tested with a plain
+//! `cargo test` instead of a Dream Daemon install. Enabled by the `mock`
+//! feature.
+//!
+//! The fake is deliberately simple: datums have no type tree and accept any
+//! variable name, and procs do nothing (returning null) unless they've been
+//! hooked.
+//!
+//! # Examples
+//! ```ignore
+//! let session = auxtools::mock::session();
+//! let proc = session.define_proc("/proc/double");
+//! proc.hook(double).unwrap();
+//!
+//! assert_eq!(proc.call(&[&Value::from(2.0)]).unwrap(), Value::from(4.0));
+//! ```
+use std::{
+	collections::HashMap,
+	ffi::{CStr, CString},
+	sync::{Mutex, MutexGuard}
+};
+
+use crate::{
+	init::{set_init_level, InitLevel},
+	raw_types::{
+		procs::{ProcEntry, ProcId},
+		strings::{StringEntry, StringId},
+		values::{Value as RawValue, ValueData, ValueTag}
+	},
+	*
+};
+
+pub(crate) mod funcs;
+
+// The proc hooks use to report errors
+const STACK_TRACE_PROC: &str = "/proc/auxtools_stack_trace";
+
+type Key = (u8, u32);
+
+const fn key(value: RawValue) -> Key {
+	(value.tag as u8, unsafe { value.data.id })
+}
+
+const NULL: RawValue = RawValue {
+	tag: ValueTag::Null,
+	data: ValueData { id: 0 }
+};
+
+struct MockString {
+	// `entry.data` points into `data`
+	entry: StringEntry,
+	data: CString
+}
+
+struct MockList {
+	elements: Vec<RawValue>,
+	assoc: HashMap<Key, RawValue>,
+	refcount: u32
+}
+
+struct MockDatum {
+	ty: u32,
+	vars: HashMap<u32, RawValue>,
+	refcount: u32
+}
+
+struct MockProc {
+	path: String,
+	entry: Box<ProcEntry>
+}
+
+struct Heap {
+	strings: Vec<Option<Box<MockString>>>,
+	string_ids: HashMap<CString, u32>,
+	lists: Vec<Option<MockList>>,
+	datums: Vec<Option<MockDatum>>,
+	// Vars of `world` and `global`
+	world_vars: [HashMap<u32, RawValue>; 2],
+	types: Vec<String>,
+	// Type id to the id of its `parent_type`
+	parent_types: HashMap<u32, u32>,
+	procs: Vec<MockProc>,
+	stack_traces: Vec<String>
+}
+
+// The raw pointers in here only point at things the heap itself owns, and it's
+// only ever accessed through the mutex
+unsafe impl Send for Heap {}
+
+static HEAP: Mutex<Option<Heap>> = Mutex::new(None);
+
+// Only one session can exist at a time, as the interned strings used by
+// `byond_string!` are shared between threads
+static SESSION: Mutex<()> = Mutex::new(());
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	// A failed test shouldn't take every test after it down too
+	mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Runs `f` against the heap, or returns `default` if there's no session (like
+// when a Value outlives the session it was created in)
+fn with_heap<R, F: FnOnce(&mut Heap) -> R>(default: R, f: F) -> R {
+	match lock(&HEAP).as_mut() {
+		Some(heap) => f(heap),
+		None => default
+	}
+}
+
+impl Heap {
+	fn new() -> Self {
+		Heap {
+			strings: vec![],
+			string_ids: HashMap::new(),
+			lists: vec![],
+			datums: vec![],
+			world_vars: [HashMap::new(), HashMap::new()],
+			types: vec![],
+			parent_types: HashMap::new(),
+			procs: vec![],
+			stack_traces: vec![]
+		}
+	}
+
+	fn string_id(&mut self, data: &CStr) -> u32 {
+		if let Some(id) = self.string_ids.get(data) {
+			return *id;
+		}
+
+		let id = self.strings.len() as u32;
+		let data = data.to_owned();
+
+		let mut string = Box::new(MockString {
+			entry: StringEntry {
+				data: std::ptr::null_mut(),
+				this: StringId(id),
+				left: std::ptr::null_mut(),
+				right: std::ptr::null_mut(),
+				ref_count: 0,
+				unk_1: 0,
+				unk_2: 0
+			},
+			data: data.clone()
+		});
+		string.entry.data = string.data.as_ptr() as *mut _;
+
+		self.strings.push(Some(string));
+		self.string_ids.insert(data, id);
+		id
+	}
+
+	fn string(&self, id: u32) -> Option<&MockString> {
+		self.strings.get(id as usize)?.as_deref()
+	}
+
+	fn type_id(&mut self, path: &str) -> u32 {
+		match self.types.iter().position(|x| x == path) {
+			Some(id) => id as u32,
+			None => {
+				self.types.push(path.to_owned());
+				let id = self.types.len() as u32 - 1;
+
+				// Types inherit from the path they're under unless told otherwise
+				if let Some(parent) = path.rfind('/').map(|i| &path[..i]).filter(|x| !x.is_empty()) {
+					let parent = self.type_id(parent);
+					self.parent_types.insert(id, parent);
+				}
+
+				id
+			}
+		}
+	}
+
+	fn new_list(&mut self, elements: Vec<RawValue>, refcount: u32) -> u32 {
+		self.lists.push(Some(MockList {
+			elements,
+			assoc: HashMap::new(),
+			refcount
+		}));
+
+		self.lists.len() as u32 - 1
+	}
+
+	fn list(&mut self, value: RawValue) -> Option<&mut MockList> {
+		if value.tag != ValueTag::List {
+			return None;
+		}
+
+		self.lists.get_mut(unsafe { value.data.id } as usize)?.as_mut()
+	}
+
+	fn vars(&mut self, value: RawValue) -> Option<&mut HashMap<u32, RawValue>> {
+		let id = unsafe { value.data.id } as usize;
+
+		match value.tag {
+			ValueTag::World => self.world_vars.get_mut(id),
+			ValueTag::Datum => Some(&mut self.datums.get_mut(id)?.as_mut()?.vars),
+			_ => None
+		}
+	}
+
+	fn exists(&self, value: RawValue) -> bool {
+		let id = unsafe { value.data.id } as usize;
+
+		match value.tag {
+			ValueTag::String => self.string(id as u32).is_some(),
+			ValueTag::List => matches!(self.lists.get(id), Some(Some(_))),
+			ValueTag::Datum => matches!(self.datums.get(id), Some(Some(_))),
+			_ => true
+		}
+	}
+
+	fn inc_ref(&mut self, value: RawValue) {
+		let id = unsafe { value.data.id } as usize;
+
+		let refcount = match value.tag {
+			ValueTag::String => self.strings.get_mut(id).and_then(|x| x.as_mut()).map(|x| &mut x.entry.ref_count),
+			ValueTag::List => self.lists.get_mut(id).and_then(|x| x.as_mut()).map(|x| &mut x.refcount),
+			ValueTag::Datum => self.datums.get_mut(id).and_then(|x| x.as_mut()).map(|x| &mut x.refcount),
+			_ => None
+		};
+
+		if let Some(refcount) = refcount {
+			*refcount += 1;
+		}
+	}
+
+	// Deleting something releases everything it holds, so this works through a
+	// queue instead of recursing
+	fn dec_ref(&mut self, value: RawValue) {
+		let mut pending = vec![value];
+
+		while let Some(value) = pending.pop() {
+			let id = unsafe { value.data.id } as usize;
+
+			match value.tag {
+				ValueTag::String => {
+					if let Some(Some(string)) = self.strings.get_mut(id) {
+						string.entry.ref_count = string.entry.ref_count.saturating_sub(1);
+						if string.entry.ref_count == 0 {
+							let string = self.strings[id].take().unwrap();
+							self.string_ids.remove(&string.data);
+						}
+					}
+				}
+
+				ValueTag::List => {
+					if let Some(Some(list)) = self.lists.get_mut(id) {
+						list.refcount = list.refcount.saturating_sub(1);
+						if list.refcount == 0 {
+							let list = self.lists[id].take().unwrap();
+							pending.extend(list.elements);
+							pending.extend(list.assoc.into_values());
+						}
+					}
+				}
+
+				ValueTag::Datum => {
+					if let Some(Some(datum)) = self.datums.get_mut(id) {
+						datum.refcount = datum.refcount.saturating_sub(1);
+						if datum.refcount == 0 {
+							let datum = self.datums[id].take().unwrap();
+							pending.extend(datum.vars.into_values());
+						}
+					}
+				}
+
+				_ => {}
+			}
+		}
+	}
+
+	fn proc_by_path(&self, path: &str) -> Option<ProcId> {
+		self.procs.iter().position(|x| x.path == path).map(|x| ProcId(x as u32))
+	}
+
+	// Finds the proc `name` on the given datum's type or any of its parents
+	fn datum_proc(&self, datum: RawValue, name: &str) -> Option<ProcId> {
+		let mut path = match datum.tag {
+			ValueTag::Datum => {
+				let datum = self.datums.get(unsafe { datum.data.id } as usize)?.as_ref()?;
+				self.types[datum.ty as usize].as_str()
+			}
+			ValueTag::World => "/world",
+			_ => return None
+		};
+
+		// DM replaces spaces in proc names with underscores
+		let name = name.replace(' ', "_");
+
+		loop {
+			if let Some(id) = self.proc_by_path(&format!("{}/{}", path, name)) {
+				return Some(id);
+			}
+
+			path = &path[..path.rfind('/')?];
+		}
+	}
+}
+
+/// Holds the mock BYOND environment open. Dropping it throws away every
+/// string, list, datum, proc and hook that was created during the session.
+pub struct Session {
+	_guard: MutexGuard<'static, ()>
+}
+
+/// Starts a fresh mock BYOND environment. This blocks until any other session
+/// has been dropped, as tests may run in parallel.
+pub fn session() -> Session {
+	let guard = lock(&SESSION);
+	*lock(&HEAP) = Some(Heap::new());
+
+	let session = Session { _guard: guard };
+	session.define_proc(STACK_TRACE_PROC);
+
+	main_thread::set_main_thread();
+	string_intern::setup_interned_strings();
+	set_init_level(InitLevel::None);
+
+	session
+}
+
+impl Session {
+	/// Adds a proc that does nothing until hooked. Paths are written the same way
+	/// they are in DM, like `/proc/foo` or `/datum/thing/proc/bar`.
+	pub fn define_proc(&self, path: &str) -> Proc {
+		with_heap((), |heap| {
+			let path_id = heap.string_id(&CString::new(path).unwrap());
+			heap.inc_ref(RawValue {
+				tag: ValueTag::String,
+				data: ValueData { string: StringId(path_id) }
+			});
+
+			let mut entry: Box<ProcEntry> = Box::new(unsafe { std::mem::zeroed() });
+			entry.path = StringId(path_id);
+			entry.name = StringId(path_id);
+
+			heap.procs.push(MockProc {
+				path: path.replace("/proc/", "/").replace("/verb/", "/"),
+				entry
+			});
+		});
+
+		proc::clear_procs();
+		proc::populate_procs();
+
+		Proc::find(path).unwrap()
+	}
+
+	/// Creates a datum of the given type. The type doesn't need to be declared
+	/// anywhere.
+	pub fn new_datum(&self, type_path: &str) -> Value {
+		let id = with_heap(0, |heap| {
+			let ty = heap.type_id(type_path);
+			heap.datums.push(Some(MockDatum {
+				ty,
+				vars: HashMap::new(),
+				refcount: 1
+			}));
+
+			heap.datums.len() as u32 - 1
+		});
+
+		unsafe {
+			Value::from_raw_owned(RawValue {
+				tag: ValueTag::Datum,
+				data: ValueData { id }
+			})
+		}
+	}
+
+	/// Makes `type_path` inherit from `parent`, like setting `parent_type` in DM.
+	pub fn set_parent_type(&self, type_path: &str, parent: &str) {
+		with_heap((), |heap| {
+			let ty = heap.type_id(type_path);
+			let parent = heap.type_id(parent);
+			heap.parent_types.insert(ty, parent);
+		});
+	}
+
+	/// Returns the reference count of a string, list or datum, or `None` if it
+	/// has been deleted.
+	pub fn refcount(&self, value: &Value) -> Option<u32> {
+		let id = unsafe { value.raw.data.id } as usize;
+
+		with_heap(None, |heap| match value.raw.tag {
+			ValueTag::String => heap.string(id as u32).map(|x| x.entry.ref_count),
+			ValueTag::List => heap.lists.get(id)?.as_ref().map(|x| x.refcount),
+			ValueTag::Datum => heap.datums.get(id)?.as_ref().map(|x| x.refcount),
+			_ => None
+		})
+	}
+
+	/// Returns (and clears) the messages hooks have passed to
+	/// `/proc/auxtools_stack_trace` when they returned an error.
+	pub fn take_stack_traces(&self) -> Vec<String> {
+		with_heap(vec![], |heap| std::mem::take(&mut heap.stack_traces))
+	}
+}
+
+impl Drop for Session {
+	fn drop(&mut self) {
+		set_init_level(InitLevel::Full);
+		main_thread::reset_reloadable_states();
+		hooks::clear_hooks();
+		proc::clear_procs();
+		string_intern::destroy_interned_strings();
+		*lock(&HEAP) = None;
+	}
+}
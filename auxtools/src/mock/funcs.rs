@@ -0,0 +1,411 @@
+//! Stand-ins for the functions `raw_types::funcs` normally gets from our C++,
+//! with the same signatures and reference counting rules. Like the real ones,
+//! they return 0 where BYOND would have thrown a runtime.
+#![allow(non_upper_case_globals)]
+
+use std::{ffi::c_void, os::raw::c_char};
+
+use super::*;
+use crate::raw_types::{lists, misc, procs, strings, values};
+
+// Only assigned during real initialization, which never happens with the mock
+pub static mut call_proc_by_id_byond: *const c_void = std::ptr::null();
+pub static mut call_datum_proc_by_name_byond: *const c_void = std::ptr::null();
+pub static mut get_proc_array_entry_byond: *const c_void = std::ptr::null();
+pub static mut get_string_id_byond: *const c_void = std::ptr::null();
+pub static mut get_variable_byond: *const c_void = std::ptr::null();
+pub static mut set_variable_byond: *const c_void = std::ptr::null();
+pub static mut get_string_table_entry_byond: *const c_void = std::ptr::null();
+pub static mut inc_ref_count_byond: *const c_void = std::ptr::null();
+pub static mut dec_ref_count_byond: *const c_void = std::ptr::null();
+pub static mut get_assoc_element_byond: *const c_void = std::ptr::null();
+pub static mut set_assoc_element_byond: *const c_void = std::ptr::null();
+pub static mut create_list_byond: *const c_void = std::ptr::null();
+pub static mut append_to_list_byond: *const c_void = std::ptr::null();
+pub static mut remove_from_list_byond: *const c_void = std::ptr::null();
+pub static mut get_length_byond: *const c_void = std::ptr::null();
+pub static mut get_misc_by_id_byond: *const c_void = std::ptr::null();
+pub static mut to_string_byond: *const c_void = std::ptr::null();
+pub static mut runtime_byond: *const c_void = std::ptr::null();
+
+// Runs a proc that isn't hooked. As mock procs have no code, this just takes
+// care of the references the caller gave us.
+unsafe fn call_unhooked(out: *mut values::Value, proc_id: procs::ProcId, args: *const values::Value, args_count: usize) -> u8 {
+	let args = std::slice::from_raw_parts(args, args_count);
+
+	let found = with_heap(false, |heap| {
+		let proc = match heap.procs.get(proc_id.0 as usize) {
+			Some(proc) => proc,
+			None => return false
+		};
+
+		if proc.path == STACK_TRACE_PROC.replace("/proc/", "/") {
+			if let Some(message) = args
+				.first()
+				.filter(|x| x.tag == ValueTag::String)
+				.and_then(|x| heap.string(x.data.string.0))
+			{
+				let message = message.data.to_string_lossy().into_owned();
+				heap.stack_traces.push(message);
+			}
+		}
+
+		for arg in args {
+			heap.dec_ref(*arg);
+		}
+
+		true
+	});
+
+	if !found {
+		return 0;
+	}
+
+	*out = NULL;
+	1
+}
+
+pub unsafe extern "C" fn call_proc_by_id(
+	out: *mut values::Value,
+	usr: values::Value,
+	proc_type: u32,
+	proc_id: procs::ProcId,
+	unk_0: u32,
+	src: values::Value,
+	args: *const values::Value,
+	args_count_l: usize,
+	unk_1: u32,
+	unk_2: u32
+) -> u8 {
+	if hooks::call_local_hook(out, usr, proc_type, proc_id, unk_0, src, args as *mut _, args_count_l, unk_1, unk_2) == 1 {
+		return 1;
+	}
+
+	call_unhooked(out, proc_id, args, args_count_l)
+}
+
+pub unsafe extern "C" fn call_datum_proc_by_name(
+	out: *mut values::Value,
+	usr: values::Value,
+	proc_type: u32,
+	proc_name: strings::StringId,
+	src: values::Value,
+	args: *mut values::Value,
+	args_count_l: usize,
+	unk_0: u32,
+	unk_1: u32
+) -> u8 {
+	let proc_id = with_heap(None, |heap| {
+		let name = heap.string(proc_name.0)?.data.to_string_lossy().into_owned();
+		heap.datum_proc(src, &name)
+	});
+
+	match proc_id {
+		Some(proc_id) => call_proc_by_id(out, usr, proc_type, proc_id, 0, src, args, args_count_l, unk_0, unk_1),
+		None => 0
+	}
+}
+
+pub unsafe extern "C" fn get_proc_array_entry(out: *mut *mut procs::ProcEntry, id: procs::ProcId) -> u8 {
+	*out = with_heap(std::ptr::null_mut(), |heap| match heap.procs.get_mut(id.0 as usize) {
+		Some(proc) => &mut *proc.entry as *mut _,
+		None => std::ptr::null_mut()
+	});
+
+	1
+}
+
+pub unsafe extern "C" fn get_string_id(out: *mut strings::StringId, string: *const c_char) -> u8 {
+	let string = CStr::from_ptr(string);
+
+	match with_heap(None, |heap| Some(heap.string_id(string))) {
+		Some(id) => {
+			*out = StringId(id);
+			1
+		}
+		None => 0
+	}
+}
+
+pub unsafe extern "C" fn get_variable(out: *mut values::Value, datum: values::Value, index: strings::StringId) -> u8 {
+	let result = with_heap(None, |heap| {
+		let name = heap.string(index.0)?.data.clone();
+
+		match name.to_bytes() {
+			b"type" if datum.tag == ValueTag::Datum => {
+				let ty = heap.datums.get(datum.data.id as usize)?.as_ref()?.ty;
+				return Some(RawValue {
+					tag: ValueTag::DatumTypepath,
+					data: ValueData { id: ty }
+				});
+			}
+
+			b"parent_type" if matches!(datum.tag, ValueTag::Datum | ValueTag::DatumTypepath) => {
+				let ty = match datum.tag {
+					ValueTag::Datum => heap.datums.get(datum.data.id as usize)?.as_ref()?.ty,
+					_ => datum.data.id
+				};
+
+				return Some(match heap.parent_types.get(&ty) {
+					Some(parent) => RawValue {
+						tag: ValueTag::DatumTypepath,
+						data: ValueData { id: *parent }
+					},
+					None => NULL
+				});
+			}
+
+			// A fresh list of var names that lives until the caller drops it
+			b"vars" => {
+				let names: Vec<u32> = heap.vars(datum)?.keys().copied().collect();
+				let names = names
+					.into_iter()
+					.map(|id| {
+						let name = RawValue {
+							tag: ValueTag::String,
+							data: ValueData { string: StringId(id) }
+						};
+						heap.inc_ref(name);
+						name
+					})
+					.collect();
+
+				let id = heap.new_list(names, 0);
+				return Some(RawValue {
+					tag: ValueTag::List,
+					data: ValueData { id }
+				});
+			}
+
+			_ => {}
+		}
+
+		heap.vars(datum)?.get(&index.0).copied()
+	});
+
+	match result {
+		Some(value) => {
+			*out = value;
+			1
+		}
+		None => 0
+	}
+}
+
+pub unsafe extern "C" fn set_variable(datum: values::Value, index: strings::StringId, value: values::Value) -> u8 {
+	with_heap(0, |heap| {
+		if heap.string(index.0).is_none() || !heap.exists(value) {
+			return 0;
+		}
+
+		// The name has to stay alive for as long as the var exists
+		let name = RawValue {
+			tag: ValueTag::String,
+			data: ValueData { string: index }
+		};
+
+		heap.inc_ref(value);
+		let old = match heap.vars(datum) {
+			Some(vars) => vars.insert(index.0, value),
+			None => {
+				heap.dec_ref(value);
+				return 0;
+			}
+		};
+
+		match old {
+			Some(old) => heap.dec_ref(old),
+			None => heap.inc_ref(name)
+		}
+
+		1
+	})
+}
+
+pub unsafe extern "C" fn get_string_table_entry(out: *mut *mut strings::StringEntry, index: strings::StringId) -> u8 {
+	with_heap(0, |heap| match heap.strings.get_mut(index.0 as usize) {
+		Some(Some(string)) => {
+			*out = &mut string.entry;
+			1
+		}
+		_ => 0
+	})
+}
+
+pub unsafe extern "C" fn inc_ref_count(value: values::Value) -> u8 {
+	with_heap((), |heap| heap.inc_ref(value));
+	1
+}
+
+pub unsafe extern "C" fn dec_ref_count(value: values::Value) -> u8 {
+	with_heap((), |heap| heap.dec_ref(value));
+	1
+}
+
+pub unsafe extern "C" fn get_assoc_element(out: *mut values::Value, datum: values::Value, index: values::Value) -> u8 {
+	let result = with_heap(None, |heap| {
+		let list = heap.list(datum)?;
+
+		let value = match index.tag {
+			ValueTag::Number => {
+				let index = index.data.number as usize;
+				*list.elements.get(index.checked_sub(1)?)?
+			}
+			_ => list.assoc.get(&key(index)).copied().unwrap_or(NULL)
+		};
+
+		// Unlike get_variable, the caller owns the returned reference
+		heap.inc_ref(value);
+		Some(value)
+	});
+
+	match result {
+		Some(value) => {
+			*out = value;
+			1
+		}
+		None => 0
+	}
+}
+
+pub unsafe extern "C" fn set_assoc_element(datum: values::Value, index: values::Value, value: values::Value) -> u8 {
+	with_heap(0, |heap| {
+		if !heap.exists(value) || !heap.exists(index) {
+			return 0;
+		}
+
+		let list = match heap.list(datum) {
+			Some(list) => list,
+			None => return 0
+		};
+
+		let old = match index.tag {
+			ValueTag::Number => {
+				let slot = match (index.data.number as usize).checked_sub(1).and_then(|i| list.elements.get_mut(i)) {
+					Some(slot) => slot,
+					None => return 0
+				};
+
+				std::mem::replace(slot, value)
+			}
+
+			ValueTag::Null => return 0,
+
+			_ => {
+				let old = list.assoc.insert(key(index), value);
+				let is_new_key = !list.elements.iter().any(|x| key(*x) == key(index));
+				if is_new_key {
+					list.elements.push(index);
+					heap.inc_ref(index);
+				}
+
+				old.unwrap_or(NULL)
+			}
+		};
+
+		heap.inc_ref(value);
+		heap.dec_ref(old);
+		1
+	})
+}
+
+pub unsafe extern "C" fn create_list(out: *mut lists::ListId, reserve_capacity: u32) -> u8 {
+	match with_heap(None, |heap| Some(heap.new_list(vec![NULL; reserve_capacity as usize], 1))) {
+		Some(id) => {
+			*out = lists::ListId(id);
+			1
+		}
+		None => 0
+	}
+}
+
+pub unsafe extern "C" fn append_to_list(list: values::Value, value: values::Value) -> u8 {
+	with_heap(0, |heap| {
+		if !heap.exists(value) {
+			return 0;
+		}
+
+		match heap.list(list) {
+			Some(list) => list.elements.push(value),
+			None => return 0
+		}
+
+		heap.inc_ref(value);
+		1
+	})
+}
+
+pub unsafe extern "C" fn remove_from_list(list: values::Value, value: values::Value) -> u8 {
+	with_heap(0, |heap| {
+		let list = match heap.list(list) {
+			Some(list) => list,
+			None => return 0
+		};
+
+		// DM removes the last matching element
+		let index = match list.elements.iter().rposition(|x| key(*x) == key(value)) {
+			Some(index) => index,
+			None => return 1
+		};
+
+		let removed = list.elements.remove(index);
+		let assoc = if list.elements.iter().any(|x| key(*x) == key(value)) {
+			None
+		} else {
+			list.assoc.remove(&key(value))
+		};
+
+		heap.dec_ref(removed);
+		if let Some(assoc) = assoc {
+			heap.dec_ref(assoc);
+		}
+
+		1
+	})
+}
+
+pub unsafe extern "C" fn get_length(out: *mut u32, value: values::Value) -> u8 {
+	let length = with_heap(None, |heap| match value.tag {
+		ValueTag::List => Some(heap.list(value)?.elements.len() as u32),
+		ValueTag::String => Some(heap.string(value.data.string.0)?.data.as_bytes().len() as u32),
+		_ => Some(0)
+	});
+
+	match length {
+		Some(length) => {
+			*out = length;
+			1
+		}
+		None => 0
+	}
+}
+
+pub const unsafe extern "C" fn get_misc_by_id(_out: *mut *mut c_void, _index: misc::MiscId) -> u8 {
+	// Procs in the mock have no bytecode, locals or parameters
+	0
+}
+
+pub unsafe extern "C" fn to_string(out: *mut strings::StringId, value: values::Value) -> u8 {
+	let result = with_heap(None, |heap| {
+		let string = match value.tag {
+			ValueTag::Null => String::new(),
+			ValueTag::Number => value.data.number.to_string(),
+			ValueTag::String => return heap.string(value.data.string.0).map(|_| value.data.string.0),
+			ValueTag::List if heap.list(value).is_some() => "/list".to_owned(),
+			ValueTag::Datum => heap.types[heap.datums.get(value.data.id as usize)?.as_ref()?.ty as usize].clone(),
+			ValueTag::DatumTypepath => heap.types.get(value.data.id as usize)?.clone(),
+			ValueTag::World if value.data.id == 0 => "world".to_owned(),
+			_ => return None
+		};
+
+		Some(heap.string_id(&CString::new(string).ok()?))
+	});
+
+	match result {
+		Some(id) => {
+			*out = StringId(id);
+			1
+		}
+		None => 0
+	}
+}
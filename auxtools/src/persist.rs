@@ -0,0 +1,132 @@
+//! State handoff for replacing the library while the world keeps running.
+//!
+//! Before unloading, DM calls `auxtools_persist` on the old library, which runs
+//! every `#[persist(save = "...")]` function and returns their state as a
+//! string. That string is passed to `auxtools_init` of the new library, which
+//! hands each piece of state to the `#[persist(restore = "...")]` function with
+//! the same key once its own init functions have run. The library stays
+//! initialized if that fails, and `auxtools_init` returns
+//! `SUCCESS (<error>)` instead of a plain `SUCCESS`.
+//!
+//! ```dm
+//! var/state = call_ext(OLD_DLL, "auxtools_persist")()
+//! call_ext(OLD_DLL, "auxtools_full_shutdown")()
+//! // (replace the library here)
+//! call_ext(NEW_DLL, "auxtools_init")(state)
+//! ```
+//!
+//! The old library has to be built with `pin_dll!(false)` for it to actually be
+//! unloaded on Windows. State is opaque bytes to auxtools, so it is up to each
+//! library to keep its format readable by the next build.
+use std::collections::HashMap;
+
+use crate::inventory;
+
+// Marks the input of `auxtools_init` as persisted state rather than anything
+// else a codebase may have been passing it
+const PREFIX: &str = "AUXPERSIST1:";
+
+#[doc(hidden)]
+pub struct PersistSaveFunc {
+	pub key: &'static str,
+	pub func: fn() -> Vec<u8>
+}
+
+#[doc(hidden)]
+pub struct PersistRestoreFunc {
+	pub key: &'static str,
+	pub func: fn(Vec<u8>) -> Result<(), String>
+}
+
+inventory::collect!(PersistSaveFunc);
+inventory::collect!(PersistRestoreFunc);
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+	out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+	out.extend_from_slice(bytes);
+}
+
+fn read_bytes<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+	if data.len() < 4 {
+		return None;
+	}
+
+	let (len, rest) = data.split_at(4);
+	let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+
+	if rest.len() < len {
+		return None;
+	}
+
+	let (bytes, rest) = rest.split_at(len);
+	*data = rest;
+	Some(bytes)
+}
+
+/// Runs every save function, returning their state in the form
+/// `auxtools_init` accepts
+pub fn save() -> String {
+	let mut data = vec![];
+	for func in inventory::iter::<PersistSaveFunc> {
+		write_bytes(&mut data, func.key.as_bytes());
+		write_bytes(&mut data, &(func.func)());
+	}
+
+	let mut out = String::with_capacity(PREFIX.len() + data.len() * 2);
+	out.push_str(PREFIX);
+	for byte in data {
+		out.push_str(&format!("{:02x}", byte));
+	}
+
+	out
+}
+
+fn decode(input: &str) -> Result<HashMap<String, Vec<u8>>, String> {
+	let hex = input.strip_prefix(PREFIX).ok_or("not persisted state")?.as_bytes();
+	if hex.len() % 2 != 0 {
+		return Err("persisted state has an odd length".to_owned());
+	}
+
+	let data = hex
+		.chunks(2)
+		.map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+		.collect::<Option<Vec<u8>>>()
+		.ok_or("persisted state isn't valid hex")?;
+
+	let mut data = data.as_slice();
+	let mut state = HashMap::new();
+	while !data.is_empty() {
+		let (key, value) = read_bytes(&mut data).zip(read_bytes(&mut data)).ok_or("persisted state is truncated")?;
+		let key = String::from_utf8(key.to_vec()).map_err(|_| "persisted state has an invalid key")?;
+
+		state.insert(key, value.to_vec());
+	}
+
+	Ok(state)
+}
+
+/// Returns true if `input` looks like something [save] returned
+pub fn is_persisted_state(input: &str) -> bool {
+	input.starts_with(PREFIX)
+}
+
+/// Passes each piece of state from [save] to the restore function with the
+/// same key. State without a restore function is dropped, as the new library
+/// may have stopped using it.
+pub fn restore(input: &str) -> Result<(), String> {
+	let mut state = decode(input)?;
+
+	let mut errors = vec![];
+	for func in inventory::iter::<PersistRestoreFunc> {
+		if let Some(data) = state.remove(func.key) {
+			if let Err(e) = (func.func)(data) {
+				errors.push(format!("{}: {}", func.key, e));
+			}
+		}
+	}
+
+	match errors.is_empty() {
+		true => Ok(()),
+		false => Err(errors.join(", "))
+	}
+}
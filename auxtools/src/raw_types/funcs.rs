@@ -1,17 +1,22 @@
+// Most of these are only used by the extern declarations
+#[cfg_attr(feature = "mock", allow(unused_imports))]
 use std::{ffi::c_void, os::raw::c_char};
 
+#[cfg_attr(feature = "mock", allow(unused_imports))]
 use super::{lists, misc, procs, strings, values, variables};
 
 // TODO: Doesn't belong here at all
 pub static mut CURRENT_EXECUTION_CONTEXT: *mut *mut procs::ExecutionContext = std::ptr::null_mut();
 pub static mut SUSPENDED_PROCS_BUFFER: *mut procs::SuspendedProcsBuffer = std::ptr::null_mut();
 pub static mut SUSPENDED_PROCS: *mut procs::SuspendedProcs = std::ptr::null_mut();
+pub static mut STRING_TABLE_LENGTH: *const u32 = std::ptr::null();
 
 pub static mut VARIABLE_NAMES: *const variables::VariableNameIdTable = std::ptr::null();
 
 // Function pointers exported by C++ but set by Rust
 // Rust shouldn't call these so we're going to treat them as void ptrs for
 // simplicity
+#[cfg(not(feature = "mock"))]
 extern "C" {
 	pub static mut call_proc_by_id_byond: *const c_void;
 	pub static mut call_datum_proc_by_name_byond: *const c_void;
@@ -34,6 +39,7 @@ extern "C" {
 }
 
 // Functions exported by our C++ for Rust to call.
+#[cfg(not(feature = "mock"))]
 extern "C" {
 	pub fn call_proc_by_id(
 		out: *mut values::Value,
@@ -74,3 +80,7 @@ extern "C" {
 	pub fn get_misc_by_id(out: *mut *mut c_void, index: misc::MiscId) -> u8;
 	pub fn to_string(out: *mut strings::StringId, value: values::Value) -> u8;
 }
+
+// The mock feature swaps all of the above out for a fake BYOND
+#[cfg(feature = "mock")]
+pub use crate::mock::funcs::*;
@@ -0,0 +1,326 @@
+//! Finding everything that holds a reference to a value.
+//!
+//! This is a replacement for the usual DM-side reference scanning code that
+//! loops over every datum's vars looking for something that prevents a datum
+//! from being garbage collected.
+//!
+//! # Examples
+//! ```ignore
+//! for path in refs::find_references(&datum) {
+//!     println!("{}", path);
+//! }
+//! ```
+use std::collections::HashSet;
+
+use crate::{
+	raw_types::values::{ValueData, ValueTag},
+	*
+};
+
+// We don't know where BYOND keeps the lengths of its datum, atom and list
+// tables, so we probe ids until we've seen this many in a row that don't exist.
+const MAX_CONSECUTIVE_MISSING_IDS: u32 = 0x10000;
+
+/// The tables walked by [`for_each_object`]. Between them these hold everything
+/// that can reference something else, including atoms in nullspace and lists
+/// nothing points to.
+pub(crate) const OBJECT_TABLES: [ValueTag; 6] = [
+	ValueTag::Datum,
+	ValueTag::Obj,
+	ValueTag::Mob,
+	ValueTag::Turf,
+	ValueTag::Area,
+	ValueTag::List
+];
+
+// Lists nested deeper than this aren't searched
+const MAX_LIST_DEPTH: usize = 64;
+
+enum Segment {
+	Var(String),
+	Index(u32),
+	AssocValue(Value)
+}
+
+struct Finder<'a> {
+	target: &'a Value,
+	results: Vec<String>,
+	visited_lists: HashSet<Value>,
+	path: Vec<Segment>
+}
+
+impl<'a> Finder<'a> {
+	fn new(target: &'a Value) -> Self {
+		Self {
+			target,
+			results: vec![],
+			visited_lists: HashSet::new(),
+			path: vec![]
+		}
+	}
+
+	fn record(&mut self, holder: &str) {
+		let mut path = holder.to_owned();
+
+		for segment in &self.path {
+			match segment {
+				Segment::Var(name) => {
+					path.push('.');
+					path.push_str(name);
+				}
+				Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+				Segment::AssocValue(key) => path.push_str(&format!("[{}]", describe(key)))
+			}
+		}
+
+		self.results.push(path);
+	}
+
+	fn check_value(&mut self, holder: &str, value: &Value, depth: usize) {
+		if value == self.target {
+			self.record(holder);
+		}
+
+		if List::is_list(value) && depth < MAX_LIST_DEPTH && self.visited_lists.insert(value.clone()) {
+			if let Ok(list) = List::from_value(value) {
+				self.check_list(holder, &list, depth + 1);
+			}
+		}
+	}
+
+	fn check_list(&mut self, holder: &str, list: &List, depth: usize) {
+		for i in 1..=list.len() {
+			let key = match list.get(i) {
+				Ok(key) => key,
+				Err(_) => continue
+			};
+
+			self.path.push(Segment::Index(i));
+			self.check_value(holder, &key, depth);
+			self.path.pop();
+
+			// Numbers can't be assoc keys
+			if key.raw.tag == ValueTag::Number || key.raw.tag == ValueTag::Null {
+				continue;
+			}
+
+			if let Ok(value) = list.get(&key) {
+				if value.raw.tag != ValueTag::Null {
+					self.path.push(Segment::AssocValue(key));
+					self.check_value(holder, &value, depth);
+					self.path.pop();
+				}
+			}
+		}
+	}
+
+	fn check_vars(&mut self, holder: &str, object: &Value, vars: &Value) {
+		let vars = match List::from_value(vars) {
+			Ok(vars) => vars,
+			Err(_) => return
+		};
+
+		for i in 1..=vars.len() {
+			let name = match vars.get(i).and_then(|x| x.as_string()) {
+				Ok(name) => name,
+				Err(_) => continue
+			};
+
+			// These just point back at the object itself
+			if name == "vars" {
+				continue;
+			}
+
+			let value = match StringRef::new(&name).and_then(|x| object.get(x)) {
+				Ok(value) => value,
+				Err(_) => continue
+			};
+
+			self.path.push(Segment::Var(name));
+			self.check_value(holder, &value, 0);
+			self.path.pop();
+		}
+	}
+
+	fn check_object(&mut self, object: &Value) {
+		if object == self.target {
+			return;
+		}
+
+		let vars = match object.get(byond_string!("vars")) {
+			Ok(vars) => vars,
+			Err(_) => return
+		};
+
+		let holder = describe(object);
+		self.check_vars(&holder, object, &vars);
+	}
+
+	fn check_globals(&mut self) {
+		let vars = unsafe { Value::new(ValueTag::GlobalVars, ValueData { id: 0 }) };
+		self.check_vars("global", &Value::GLOBAL, &vars);
+	}
+
+	fn check_objects(&mut self) {
+		for tag in OBJECT_TABLES {
+			if tag != ValueTag::List {
+				for_each_object(tag, |object| self.check_object(&object));
+			}
+		}
+	}
+
+	// Lists are walked last so that ones reachable from something else are
+	// reported through that instead of through their own ref
+	fn check_lists(&mut self) {
+		for_each_object(ValueTag::List, |value| {
+			if !self.visited_lists.insert(value.clone()) {
+				return;
+			}
+
+			if let Ok(list) = List::from_value(&value) {
+				self.check_list(&describe(&value), &list, 1);
+			}
+		});
+	}
+
+	fn check_stacks(&mut self) {
+		let stacks = debug::CallStacks::new();
+
+		for (frame_index, frame) in stacks.active.iter().chain(stacks.suspended.iter().flatten()).enumerate() {
+			let holder = format!("{} (frame {})", frame.proc.path, frame_index);
+
+			self.check_value(&format!("{} src", holder), &frame.src, 0);
+			self.check_value(&format!("{} usr", holder), &frame.usr, 0);
+			self.check_value(&format!("{} .", holder), &frame.dot, 0);
+
+			for (name, value) in &frame.args {
+				let name = match name {
+					Some(name) => String::from(name.clone()),
+					None => "<unnamed arg>".to_owned()
+				};
+
+				self.check_value(&format!("{} arg {}", holder, name), value, 0);
+			}
+
+			for (name, value) in &frame.locals {
+				self.check_value(&format!("{} local {}", holder, String::from(name.clone())), value, 0);
+			}
+		}
+	}
+}
+
+// Returns whether BYOND has something at this id. This deliberately doesn't
+// create a `Value`, as touching the refcount of a free slot corrupts BYOND's
+// free list.
+fn exists(value: raw_types::values::Value) -> bool {
+	let mut string_id = raw_types::strings::StringId(0);
+	unsafe { raw_types::funcs::to_string(&mut string_id, value) == 1 }
+}
+
+// Unlike the other tables, the turf table is exactly as big as the map
+fn turf_count() -> Option<u32> {
+	let world = Value::WORLD;
+	let size = world.get_number(byond_string!("maxx")).ok()?
+		* world.get_number(byond_string!("maxy")).ok()?
+		* world.get_number(byond_string!("maxz")).ok()?;
+
+	Some(size as u32)
+}
+
+/// Calls `f` with everything in the table for `tag` that currently exists.
+pub(crate) fn for_each_object<F: FnMut(Value)>(tag: ValueTag, mut f: F) {
+	let limit = match tag {
+		ValueTag::Turf => turf_count(),
+		_ => None
+	};
+
+	let mut missing = 0;
+	let mut id = 0;
+
+	loop {
+		let done = match limit {
+			Some(limit) => id >= limit,
+			None => missing >= MAX_CONSECUTIVE_MISSING_IDS
+		};
+
+		if done {
+			break;
+		}
+
+		let raw = raw_types::values::Value { tag, data: ValueData { id } };
+		id += 1;
+
+		if exists(raw) {
+			missing = 0;
+			f(unsafe { Value::from_raw(raw) });
+		} else {
+			missing += 1;
+		}
+	}
+}
+
+/// Renders a value the way it appears in reference paths, like `/obj/item
+/// [0x2000001]`.
+fn describe(value: &Value) -> String {
+	match value.raw.tag {
+		ValueTag::Null | ValueTag::Number | ValueTag::String => format!("{:?}", value),
+		_ => {
+			let id = unsafe { value.raw.data.id };
+			let reference = format!("[0x{:x}]", ((value.raw.tag as u32) << 24) | id);
+
+			match value.get_type() {
+				Ok(ty) => format!("{} {}", ty, reference),
+				Err(_) => reference
+			}
+		}
+	}
+}
+
+/// Returns a path to every variable, list entry and proc frame that holds a
+/// reference to `target`. This walks global vars, every datum, atom and list
+/// (including those in nullspace or that nothing points to) and every proc
+/// frame, so it can take a while on big worlds.
+pub fn find_references(target: &Value) -> Vec<String> {
+	let mut finder = Finder::new(target);
+
+	finder.check_globals();
+	finder.check_objects();
+	finder.check_stacks();
+	finder.check_lists();
+
+	finder.results
+}
+
+/// Parses a reference in the format produced by DM's `\ref`, like
+/// `[0x2100000a]`.
+pub fn parse_ref(reference: &str) -> Option<Value> {
+	let hex = reference.trim().strip_prefix("[0x")?.strip_suffix(']')?;
+	let raw = u32::from_str_radix(hex, 16).ok()?;
+
+	let tag = match raw >> 24 {
+		0x01 => ValueTag::Turf,
+		0x02 => ValueTag::Obj,
+		0x03 => ValueTag::Mob,
+		0x04 => ValueTag::Area,
+		0x05 => ValueTag::Client,
+		0x0D => ValueTag::Image,
+		0x0F => ValueTag::List,
+		0x21 => ValueTag::Datum,
+		_ => return None
+	};
+
+	let value = raw_types::values::Value {
+		tag,
+		data: ValueData { id: raw & 0xFFFFFF }
+	};
+
+	// Make sure BYOND thinks this is something real before handing it out
+	unsafe {
+		let mut string_id = raw_types::strings::StringId(0);
+		if raw_types::funcs::to_string(&mut string_id, value) != 1 {
+			return None;
+		}
+
+		Some(Value::from_raw(value))
+	}
+}
@@ -0,0 +1,297 @@
+//! Groups runtime errors so thousands of identical ones can be read as one.
+//!
+//! Every runtime BYOND reports is recorded automatically. Runtimes are grouped
+//! by their message with numbers and quoted text blanked out (so `list index
+//! out of bounds: 12` and `...: 13` are the same) along with the proc, file and
+//! line they happened on. Each group keeps a count, an example of the full
+//! message and when it was first and last seen.
+//!
+//! From DM, `auxtools_runtime_summary` returns the groups as JSON, most
+//! frequent first, and `auxtools_runtime_reset` forgets them:
+//!
+//! ```dm
+//! var/json = call(AUXTOOLS_DLL, "auxtools_runtime_summary")("50")
+//! ```
+//!
+//! [dump_every] writes the same JSON to a file periodically.
+use std::{
+	collections::HashMap,
+	fmt::Write as _,
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Mutex, MutexGuard
+	},
+	thread::JoinHandle,
+	time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use crate::{
+	init::{get_init_level, InitLevel},
+	raw_types, Proc, StringRef
+};
+
+// Past this many groups, new kinds of runtime are only counted
+const MAX_GROUPS: usize = 10_000;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+	template: String,
+	proc: Option<String>,
+	file: Option<String>,
+	line: Option<u32>
+}
+
+/// A group of runtimes with the same message template and location
+#[derive(Clone)]
+pub struct RuntimeGroup {
+	pub template: String,
+	/// The most recent full message
+	pub example: String,
+	pub proc: Option<String>,
+	pub file: Option<String>,
+	pub line: Option<u32>,
+	pub count: u64,
+	pub first_seen: SystemTime,
+	pub last_seen: SystemTime
+}
+
+#[derive(Default)]
+struct Aggregator {
+	groups: HashMap<Key, RuntimeGroup>,
+	// Runtimes that didn't fit once MAX_GROUPS was reached
+	dropped: u64
+}
+
+static AGGREGATOR: Mutex<Option<Aggregator>> = Mutex::new(None);
+
+static DUMP_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static STOP_DUMPING: AtomicBool = AtomicBool::new(false);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Blanks out the parts of a message that change between otherwise identical
+/// runtimes: numbers become `#` and quoted text becomes `"*"`
+pub fn template(message: &str) -> String {
+	let mut out = String::with_capacity(message.len());
+	let mut chars = message.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'0'..='9' => {
+				while chars.next_if(|x| x.is_ascii_alphanumeric()).is_some() {}
+				out.push('#');
+			}
+
+			'"' => {
+				for c in chars.by_ref() {
+					if c == '"' {
+						break;
+					}
+				}
+
+				out.push_str("\"*\"");
+			}
+
+			// Keep names like `item2` intact
+			c if c.is_alphanumeric() || c == '_' => {
+				out.push(c);
+				while let Some(c) = chars.next_if(|x| x.is_alphanumeric() || *x == '_') {
+					out.push(c);
+				}
+			}
+
+			c => out.push(c)
+		}
+	}
+
+	out
+}
+
+// Where the proc that is currently running is, if anything is running
+fn current_location() -> (Option<String>, Option<String>, Option<u32>) {
+	unsafe {
+		if raw_types::funcs::CURRENT_EXECUTION_CONTEXT.is_null() || (*raw_types::funcs::CURRENT_EXECUTION_CONTEXT).is_null() {
+			return (None, None, None);
+		}
+
+		let context = *raw_types::funcs::CURRENT_EXECUTION_CONTEXT;
+		let proc = Proc::from_id((*(*context).proc_instance()).proc).map(|x| x.path);
+
+		if !(*context).filename().valid() {
+			return (proc, None, None);
+		}
+
+		let file = String::from_utf8_lossy(StringRef::from_id((*context).filename()).data()).into_owned();
+		(proc, Some(file), Some((*context).line()))
+	}
+}
+
+/// Adds a runtime to its group. Called for every runtime BYOND reports, but
+/// can be used for errors that are reported in other ways too.
+pub fn record(message: &str) {
+	let (proc, file, line) = match get_init_level() {
+		InitLevel::None => current_location(),
+		_ => (None, None, None)
+	};
+
+	let key = Key {
+		template: template(message),
+		proc,
+		file,
+		line
+	};
+
+	let now = SystemTime::now();
+	let mut aggregator = lock(&AGGREGATOR);
+	let aggregator = aggregator.get_or_insert_with(Aggregator::default);
+
+	if let Some(group) = aggregator.groups.get_mut(&key) {
+		group.count += 1;
+		group.last_seen = now;
+		message.clone_into(&mut group.example);
+		return;
+	}
+
+	if aggregator.groups.len() >= MAX_GROUPS {
+		aggregator.dropped += 1;
+		return;
+	}
+
+	let group = RuntimeGroup {
+		template: key.template.clone(),
+		example: message.to_owned(),
+		proc: key.proc.clone(),
+		file: key.file.clone(),
+		line: key.line,
+		count: 1,
+		first_seen: now,
+		last_seen: now
+	};
+
+	aggregator.groups.insert(key, group);
+}
+
+/// Returns every group, most frequent first
+pub fn groups() -> Vec<RuntimeGroup> {
+	let mut groups: Vec<RuntimeGroup> = lock(&AGGREGATOR).iter().flat_map(|x| x.groups.values().cloned()).collect();
+	groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.first_seen.cmp(&b.first_seen)));
+	groups
+}
+
+/// Forgets every runtime recorded so far
+pub fn reset() {
+	*lock(&AGGREGATOR) = None;
+}
+
+fn json_string(out: &mut String, value: &str) {
+	out.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if (c as u32) < 0x20 => {
+				let _ = write!(out, "\\u{:04x}", c as u32);
+			}
+			c => out.push(c)
+		}
+	}
+	out.push('"');
+}
+
+fn json_optional_string(out: &mut String, value: &Option<String>) {
+	match value {
+		Some(value) => json_string(out, value),
+		None => out.push_str("null")
+	}
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0)
+}
+
+/// Returns up to `limit` of the most frequent groups as JSON, in the form
+/// `{"total": 1234, "dropped": 0, "runtimes": [{"template": ..., "example":
+/// ..., "proc": ..., "file": ..., "line": ..., "count": ..., "first_seen":
+/// ..., "last_seen": ...}]}`. Times are in seconds since the Unix epoch.
+pub fn summary_json(limit: Option<usize>) -> String {
+	let dropped = lock(&AGGREGATOR).as_ref().map_or(0, |x| x.dropped);
+	let groups = groups();
+	let total = groups.iter().map(|x| x.count).sum::<u64>() + dropped;
+
+	let mut out = String::new();
+	let _ = write!(out, "{{\"total\":{},\"dropped\":{},\"runtimes\":[", total, dropped);
+
+	for (index, group) in groups.iter().take(limit.unwrap_or(usize::MAX)).enumerate() {
+		if index != 0 {
+			out.push(',');
+		}
+
+		out.push_str("{\"template\":");
+		json_string(&mut out, &group.template);
+		out.push_str(",\"example\":");
+		json_string(&mut out, &group.example);
+		out.push_str(",\"proc\":");
+		json_optional_string(&mut out, &group.proc);
+		out.push_str(",\"file\":");
+		json_optional_string(&mut out, &group.file);
+
+		match group.line {
+			Some(line) => {
+				let _ = write!(out, ",\"line\":{}", line);
+			}
+			None => out.push_str(",\"line\":null")
+		}
+
+		let _ = write!(
+			out,
+			",\"count\":{},\"first_seen\":{},\"last_seen\":{}}}",
+			group.count,
+			unix_time(group.first_seen),
+			unix_time(group.last_seen)
+		);
+	}
+
+	out.push_str("]}");
+	out
+}
+
+/// Writes [summary_json] to `path` every `interval` from a background thread,
+/// replacing any earlier call. Stopped when the library is fully shut down.
+pub fn dump_every(path: PathBuf, interval: Duration) -> Result<(), String> {
+	stop_dumping();
+	STOP_DUMPING.store(false, Ordering::Relaxed);
+
+	let thread = std::thread::Builder::new()
+		.name("auxtools-runtime-dump".to_owned())
+		.spawn(move || {
+			let mut waited = Duration::ZERO;
+			while !STOP_DUMPING.load(Ordering::Relaxed) {
+				// Sleep in short steps so stopping doesn't take a whole interval
+				std::thread::sleep(Duration::from_millis(100));
+				waited += Duration::from_millis(100);
+
+				if waited >= interval {
+					waited = Duration::ZERO;
+					let _ = std::fs::write(&path, summary_json(None));
+				}
+			}
+		})
+		.map_err(|e| e.to_string())?;
+
+	*lock(&DUMP_THREAD) = Some(thread);
+	Ok(())
+}
+
+/// Stops the thread started by [dump_every]
+pub fn stop_dumping() {
+	if let Some(thread) = lock(&DUMP_THREAD).take() {
+		STOP_DUMPING.store(true, Ordering::Relaxed);
+		let _ = thread.join();
+	}
+}
@@ -3,7 +3,11 @@ use std::{
 	os::raw::{c_char, c_int}
 };
 
-use libc::{dl_iterate_phdr, dl_phdr_info, Elf32_Phdr, PT_LOAD};
+#[cfg(target_pointer_width = "32")]
+use libc::Elf32_Phdr as Elf_Phdr;
+#[cfg(target_pointer_width = "64")]
+use libc::Elf64_Phdr as Elf_Phdr;
+use libc::{dl_iterate_phdr, dl_phdr_info, PT_LOAD};
 
 #[repr(C)]
 struct CallbackData {
@@ -26,7 +30,7 @@ extern "C" fn dl_phdr_callback(info: *mut dl_phdr_info, _size: usize, data: *mut
 		return 0;
 	}
 
-	let headers: &'static [Elf32_Phdr] = unsafe { std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize) };
+	let headers: &'static [Elf_Phdr] = unsafe { std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize) };
 	let elf_header = headers.iter().filter(|p| p.p_type == PT_LOAD).next().unwrap();
 
 	let start = (info.dlpi_addr + elf_header.p_vaddr) as usize;
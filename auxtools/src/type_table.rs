@@ -0,0 +1,101 @@
+//! A cache of every type's path and parent, for `istype()`-like checks that
+//! don't have to ask BYOND for strings each time.
+//!
+//! Parents come from each type's `parent_type`, so types that set it are
+//! handled the same way DM handles them. Types are looked up the first time
+//! they're needed and forgotten when auxtools is shut down.
+use std::collections::HashMap;
+
+use crate::{
+	raw_types::values::{Value as RawValue, ValueTag},
+	List, Proc, ReloadableState, Value
+};
+
+// Deeper than any real type tree, in case `parent_type` makes a loop
+const MAX_DEPTH: usize = 256;
+
+struct TypeInfo {
+	path: String,
+	parent: Option<RawValue>
+}
+
+static TYPES: ReloadableState<HashMap<(u8, u32), TypeInfo>> = ReloadableState::new(HashMap::new);
+
+pub(crate) const fn is_typepath(tag: ValueTag) -> bool {
+	matches!(
+		tag,
+		ValueTag::MobTypepath | ValueTag::ObjTypepath | ValueTag::TurfTypepath | ValueTag::AreaTypepath | ValueTag::DatumTypepath
+	)
+}
+
+/// The path of built-in types that BYOND doesn't give a typepath we can read,
+/// like `/list` for lists
+pub(crate) const fn builtin_path(value: &Value) -> Option<&'static str> {
+	if List::is_list(value) {
+		return Some("/list");
+	}
+
+	match value.raw.tag {
+		ValueTag::Client => Some("/client"),
+		ValueTag::Image => Some("/image"),
+		ValueTag::SaveFile => Some("/savefile"),
+		_ => None
+	}
+}
+
+const fn key(ty: RawValue) -> (u8, u32) {
+	(ty.tag as u8, unsafe { ty.data.id })
+}
+
+fn load(ty: RawValue) -> TypeInfo {
+	let value = unsafe { Value::from_raw(ty) };
+
+	let parent = match value.get(crate::byond_string!("parent_type")) {
+		Ok(parent) if is_typepath(parent.raw.tag) => Some(parent.raw),
+		_ => None
+	};
+
+	TypeInfo {
+		path: value.to_string().unwrap_or_default(),
+		parent
+	}
+}
+
+// Calls `f` with `ty` and each of its ancestors until it returns true
+fn any_ancestor<F: FnMut(RawValue, &TypeInfo) -> bool>(ty: RawValue, mut f: F) -> bool {
+	TYPES.with(|types| {
+		let mut current = Some(ty);
+
+		for _ in 0..MAX_DEPTH {
+			let ty = match current {
+				Some(ty) => ty,
+				None => break
+			};
+
+			// Reading vars from a type doesn't run any DM, so this can't end up back here
+			let info = types.entry(key(ty)).or_insert_with(|| load(ty));
+			if f(ty, info) {
+				return true;
+			}
+
+			current = info.parent;
+		}
+
+		false
+	})
+}
+
+/// Whether `ty` is `ancestor` or inherits from it
+pub(crate) fn inherits(ty: RawValue, ancestor: RawValue) -> bool {
+	any_ancestor(ty, |x, _| key(x) == key(ancestor))
+}
+
+/// Whether `ty` is the type at `path` or inherits from it
+pub(crate) fn inherits_path(ty: RawValue, path: &str) -> bool {
+	any_ancestor(ty, |_, info| info.path == path)
+}
+
+/// Whether `ty` or one of its ancestors defines the proc `name`
+pub(crate) fn has_proc(ty: RawValue, name: &str) -> bool {
+	any_ancestor(ty, |_, info| Proc::find(format!("{}/proc/{}", info.path, name)).is_some())
+}
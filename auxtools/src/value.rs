@@ -1,4 +1,4 @@
-use super::{raw_types, string};
+use super::{raw_types, string, type_table};
 use crate::{list, runtime, runtime::DMResult};
 use std::{ffi::CString, fmt, marker::PhantomData};
 
@@ -306,6 +306,47 @@ impl Value {
 		}
 	}
 
+	/// Gets the type of the Value as a typepath, like DM's `type` var. Typepaths
+	/// are their own type.
+	pub fn typepath(&self) -> DMResult {
+		if type_table::is_typepath(self.raw.tag) {
+			return Ok(self.clone());
+		}
+
+		match self.get(crate::byond_string!("type")) {
+			Ok(ty) if type_table::is_typepath(ty.raw.tag) => Ok(ty),
+			_ => Err(runtime!("{:?} has no type", self.raw.tag))
+		}
+	}
+
+	/// Checks whether this Value's type is `typepath` or one of its subtypes,
+	/// like `istype(src, typepath)` in DM. Also works on typepaths, like
+	/// `ispath()`.
+	///
+	/// Lists, clients, images and savefiles don't have a [typepath](Self::typepath)
+	/// and only match their own built-in path, like `/list`, not its parents or
+	/// subtypes.
+	///
+	/// # Examples:
+	/// ```ignore
+	/// if src.is_type("/obj/item") { ... }
+	/// ```
+	pub fn is_type<S: AsRef<str>>(&self, typepath: S) -> bool {
+		match self.typepath() {
+			Ok(ty) => type_table::inherits_path(ty.raw, typepath.as_ref()),
+			Err(_) => type_table::builtin_path(self) == Some(typepath.as_ref())
+		}
+	}
+
+	/// Checks whether this Value's type is the same as or inherits from
+	/// `other`'s type. Either side can be a typepath or an instance of one.
+	pub fn is_subtype(&self, other: &Value) -> bool {
+		match (self.typepath(), other.typepath()) {
+			(Ok(ty), Ok(ancestor)) => type_table::inherits(ty.raw, ancestor.raw),
+			_ => false
+		}
+	}
+
 	pub fn is_truthy(&self) -> bool {
 		match self.raw.tag {
 			raw_types::values::ValueTag::Null => false,
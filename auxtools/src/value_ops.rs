@@ -0,0 +1,324 @@
+//! DM's operators for [Value]s, so logic ported from DM behaves the same.
+//!
+//! The operators return a [DMResult] as they can fail the same ways they do in
+//! DM, so they're usually followed by a `?`:
+//!
+//! ```ignore
+//! let total = (&health + &bonus)?;
+//! let remaining = (&contents - &removed)?;
+//! ```
+//!
+//! Numbers, strings, `null` and lists are handled here without calling into
+//! BYOND:
+//! - `null` acts as `0` in arithmetic, and `null + x` is just `x` for numbers
+//!   and strings, or a copy of `x` for lists
+//! - `+` concatenates strings
+//! - `+`, `-`, `&` and `|` on a list make a new list with the items added,
+//!   removed, kept or merged, like `list + item` or `list - other_list`
+//! - `&` and `|` on numbers are bitwise and work on 24 bit integers
+//!
+//! Anything else, like `/datum/vector + /datum/vector`, is handed to the
+//! operator overload proc the left side defines (`operator+` and so on), and is
+//! a type mismatch if there isn't one. BYOND's own operators for built-in types
+//! aren't procs and can't be called from here, so `/matrix * /matrix`,
+//! `/matrix * 2` and the like are type mismatches too, even though they work in
+//! DM.
+use std::{
+	cmp::Ordering,
+	ops::{Add, BitAnd, BitOr, Div, Mul, Rem, Sub}
+};
+
+use crate::{raw_types::values::ValueTag, runtime, type_table, DMResult, List, Value};
+
+// BYOND's bitwise operators only keep this many bits
+const BITWISE_MASK: i32 = 0xFF_FFFF;
+
+// Numbers, with null as zero
+const fn as_number(value: &Value) -> Option<f32> {
+	match value.raw.tag {
+		ValueTag::Null => Some(0.0),
+		ValueTag::Number => unsafe { Some(value.raw.data.number) },
+		_ => None
+	}
+}
+
+fn describe(value: &Value) -> String {
+	match value.raw.tag {
+		ValueTag::Null => "null".to_owned(),
+		ValueTag::Number | ValueTag::String => format!("{}", value.raw),
+		_ => value.to_string().unwrap_or_else(|_| format!("{:?}", value.raw.tag))
+	}
+}
+
+fn type_mismatch(op: &str, a: &Value, b: &Value) -> runtime::Runtime {
+	runtime!("type mismatch: {} {} {}", describe(a), op, describe(b))
+}
+
+const fn is_overloadable(value: &Value) -> bool {
+	!matches!(value.raw.tag, ValueTag::Null | ValueTag::Number | ValueTag::String) && !List::is_list(value)
+}
+
+// Whether `value` has a proc called `name`, defined on its type or inherited
+fn has_overload(value: &Value, name: &str) -> bool {
+	is_overloadable(value) && value.typepath().is_ok_and(|ty| type_table::has_proc(ty.raw, name))
+}
+
+// Calls the left side's `operator<op>` proc. Errors from the proc itself are
+// passed on as they are.
+fn overload(op: &str, a: &Value, b: &Value) -> DMResult {
+	let name = format!("operator{}", op);
+	if !has_overload(a, &name) {
+		return Err(type_mismatch(op, a, b));
+	}
+
+	a.call(name, &[b])
+}
+
+fn items(list: &List) -> DMResult<Vec<Value>> {
+	(1..=list.len()).map(|i| list.get(i)).collect()
+}
+
+// Copies `src`'s items and associated values onto the end of `dest`
+fn extend(dest: &List, src: &List) -> DMResult<()> {
+	for item in items(src)? {
+		dest.append(&item);
+
+		if item.raw.tag != ValueTag::Number {
+			let assoc = src.get(&item)?;
+			if assoc != Value::NULL {
+				dest.set(&item, assoc)?;
+			}
+		}
+	}
+
+	Ok(())
+}
+
+fn copy(list: &List) -> DMResult<List> {
+	let copy = List::new();
+	extend(&copy, list)?;
+	Ok(copy)
+}
+
+fn add(a: &Value, b: &Value) -> DMResult {
+	if let Ok(list) = a.as_list() {
+		let result = copy(&list)?;
+		match b.as_list() {
+			Ok(other) => extend(&result, &other)?,
+			Err(_) => result.append(b)
+		}
+		return Ok(result.into());
+	}
+
+	match (a.raw.tag, b.raw.tag) {
+		(ValueTag::Null, _) if List::is_list(b) => Ok(copy(&b.as_list()?)?.into()),
+		(ValueTag::Null, ValueTag::Null | ValueTag::Number | ValueTag::String) => Ok(b.clone()),
+		(ValueTag::Number | ValueTag::String, ValueTag::Null) => Ok(a.clone()),
+		(ValueTag::Number, ValueTag::Number) => Ok(Value::from(a.as_number()? + b.as_number()?)),
+		(ValueTag::String, ValueTag::String) => Value::from_string(a.as_string()? + &b.as_string()?),
+		_ => overload("+", a, b)
+	}
+}
+
+fn sub(a: &Value, b: &Value) -> DMResult {
+	if let Ok(list) = a.as_list() {
+		let result = copy(&list)?;
+		match b.as_list() {
+			Ok(other) => {
+				for item in items(&other)? {
+					result.remove(item);
+				}
+			}
+			Err(_) => result.remove(b)
+		}
+		return Ok(result.into());
+	}
+
+	match (as_number(a), as_number(b)) {
+		(Some(x), Some(y)) => Ok(Value::from(x - y)),
+		_ => overload("-", a, b)
+	}
+}
+
+fn mul(a: &Value, b: &Value) -> DMResult {
+	match (as_number(a), as_number(b)) {
+		(Some(x), Some(y)) => Ok(Value::from(x * y)),
+		_ => overload("*", a, b)
+	}
+}
+
+fn div(a: &Value, b: &Value) -> DMResult {
+	match (as_number(a), as_number(b)) {
+		(Some(x), Some(y)) if y != 0.0 => Ok(Value::from(x / y)),
+		(Some(_), Some(_)) => Err(runtime!("Division by zero")),
+		_ => overload("/", a, b)
+	}
+}
+
+// Like DM's `%`, this works on the integer parts of both sides
+fn rem(a: &Value, b: &Value) -> DMResult {
+	match (as_number(a), as_number(b)) {
+		(Some(_), Some(y)) if y as i32 == 0 => Err(runtime!("Division by zero")),
+		(Some(x), Some(y)) => Ok(Value::from((x as i32).wrapping_rem(y as i32))),
+		_ => overload("%", a, b)
+	}
+}
+
+fn bitand(a: &Value, b: &Value) -> DMResult {
+	if let Ok(list) = a.as_list() {
+		let other = match b.as_list() {
+			Ok(other) => items(&other)?,
+			Err(_) => vec![b.clone()]
+		};
+
+		let result = List::new();
+		for item in items(&list)? {
+			if other.iter().any(|x| x.dm_eq(&item)) {
+				result.append(item);
+			}
+		}
+		return Ok(result.into());
+	}
+
+	match (as_number(a), as_number(b)) {
+		(Some(x), Some(y)) => Ok(Value::from((x as i32 & y as i32) & BITWISE_MASK)),
+		_ => overload("&", a, b)
+	}
+}
+
+fn bitor(a: &Value, b: &Value) -> DMResult {
+	if let Ok(list) = a.as_list() {
+		let result = copy(&list)?;
+		let mut present = items(&result)?;
+
+		let other = match b.as_list() {
+			Ok(other) => items(&other)?,
+			Err(_) => vec![b.clone()]
+		};
+
+		for item in other {
+			if !present.iter().any(|x| x.dm_eq(&item)) {
+				result.append(&item);
+				present.push(item);
+			}
+		}
+		return Ok(result.into());
+	}
+
+	match (as_number(a), as_number(b)) {
+		(Some(x), Some(y)) => Ok(Value::from((x as i32 | y as i32) & BITWISE_MASK)),
+		_ => overload("|", a, b)
+	}
+}
+
+macro_rules! impl_op {
+	($trait:ident, $method:ident) => {
+		impl $trait<&Value> for &Value {
+			type Output = DMResult;
+
+			fn $method(self, other: &Value) -> DMResult {
+				$method(self, other)
+			}
+		}
+
+		impl $trait<Value> for &Value {
+			type Output = DMResult;
+
+			fn $method(self, other: Value) -> DMResult {
+				$method(self, &other)
+			}
+		}
+
+		impl $trait<&Value> for Value {
+			type Output = DMResult;
+
+			fn $method(self, other: &Value) -> DMResult {
+				$method(&self, other)
+			}
+		}
+
+		impl $trait<Value> for Value {
+			type Output = DMResult;
+
+			fn $method(self, other: Value) -> DMResult {
+				$method(&self, &other)
+			}
+		}
+	};
+}
+
+impl_op!(Add, add);
+impl_op!(Sub, sub);
+impl_op!(Mul, mul);
+impl_op!(Div, div);
+impl_op!(Rem, rem);
+impl_op!(BitAnd, bitand);
+impl_op!(BitOr, bitor);
+
+impl Value {
+	/// DM's `==`. Unlike `PartialEq`, which compares values exactly, `0` and `-0`
+	/// are equal here.
+	pub fn dm_eq(&self, other: &Value) -> bool {
+		match (self.raw.tag, other.raw.tag) {
+			(ValueTag::Number, ValueTag::Number) => unsafe { self.raw.data.number == other.raw.data.number },
+			_ => self == other
+		}
+	}
+
+	/// DM's `~=`. Lists are equivalent if they have equal items and associated
+	/// values in the same order, and datums can decide for themselves with an
+	/// `operator~=` proc, whose runtimes are passed on. Everything else is
+	/// compared with `==`.
+	pub fn equivalent(&self, other: &Value) -> DMResult<bool> {
+		if let (Ok(a), Ok(b)) = (self.as_list(), other.as_list()) {
+			if a.len() != b.len() {
+				return Ok(false);
+			}
+
+			for (x, y) in items(&a)?.iter().zip(items(&b)?.iter()) {
+				if !x.dm_eq(y) {
+					return Ok(false);
+				}
+
+				if x.raw.tag != ValueTag::Number && !a.get(x)?.dm_eq(&b.get(y)?) {
+					return Ok(false);
+				}
+			}
+
+			return Ok(true);
+		}
+
+		if has_overload(self, "operator~=") {
+			return Ok(self.call("operator~=", &[other])?.is_truthy());
+		}
+
+		Ok(self.dm_eq(other))
+	}
+
+	/// Orders two values the way DM's `<`, `<=`, `>` and `>=` do. Numbers (and
+	/// null, as zero) are compared numerically and strings alphabetically.
+	/// Datums are compared with their `operator<` and `operator>` procs.
+	pub fn dm_cmp(&self, other: &Value) -> DMResult<Ordering> {
+		if let (Some(x), Some(y)) = (as_number(self), as_number(other)) {
+			return Ok(x.partial_cmp(&y).unwrap_or(Ordering::Equal));
+		}
+
+		if let (ValueTag::String | ValueTag::Null, ValueTag::String | ValueTag::Null) = (self.raw.tag, other.raw.tag) {
+			let string = |x: &Value| match x.raw.tag {
+				ValueTag::Null => Ok(String::new()),
+				_ => x.as_string()
+			};
+			return Ok(string(self)?.cmp(&string(other)?));
+		}
+
+		if overload("<", self, other)?.is_truthy() {
+			return Ok(Ordering::Less);
+		}
+
+		if overload(">", self, other)?.is_truthy() {
+			return Ok(Ordering::Greater);
+		}
+
+		Ok(Ordering::Equal)
+	}
+}
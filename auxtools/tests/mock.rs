@@ -0,0 +1,393 @@
+// Run with `cargo test -p auxtools --features mock`
+#![cfg(feature = "mock")]
+
+use auxtools::*;
+
+#[test]
+fn lists() {
+	let _session = mock::session();
+
+	let list = List::new();
+	assert!(list.is_empty());
+
+	list.append(Value::from(101));
+	list.append(Value::from(102));
+	list.append(Value::from(103));
+	assert_eq!(list.len(), 3);
+
+	list.set(byond_string!("key"), byond_string!("value")).unwrap();
+	assert_eq!(list.get(byond_string!("key")).unwrap().as_string().unwrap(), "value");
+	assert_eq!(list.len(), 4);
+
+	list.remove(Value::from(102));
+	assert_eq!(list.get(2).unwrap().as_number().unwrap(), 103.0);
+
+	assert!(list.get(10).is_err());
+
+	let nulls = List::with_size(6);
+	assert_eq!(nulls.len(), 6);
+	for n in 1..=6 {
+		assert_eq!(nulls.get(n).unwrap(), Value::NULL);
+	}
+}
+
+#[test]
+fn string_refcounts() {
+	let session = mock::session();
+
+	let a = Value::from_string("relatively unique testing string").unwrap();
+	assert_eq!(session.refcount(&a), Some(1));
+
+	let b = a.clone();
+	assert_eq!(session.refcount(&a), Some(2));
+
+	drop(b);
+	assert_eq!(session.refcount(&a), Some(1));
+
+	let list = List::new();
+	list.append(&a);
+	assert_eq!(session.refcount(&a), Some(2));
+
+	drop(list);
+	assert_eq!(session.refcount(&a), Some(1));
+
+	let copy = a.clone();
+	drop(a);
+	drop(copy);
+	assert_eq!(
+		StringRef::new("relatively unique testing string").unwrap().to_string(),
+		"\"relatively unique testing string\""
+	);
+}
+
+#[test]
+fn datum_vars() {
+	let session = mock::session();
+
+	let datum = session.new_datum("/datum/thing");
+	assert_eq!(datum.get_type().unwrap(), "/datum/thing");
+	assert!(datum.get(byond_string!("missing")).is_err());
+
+	datum.set(byond_string!("health"), 50).unwrap();
+	assert_eq!(datum.get_number(byond_string!("health")).unwrap(), 50.0);
+
+	let list: Value = List::new().into();
+	datum.set(byond_string!("contents"), &list).unwrap();
+	assert_eq!(session.refcount(&list), Some(2));
+
+	// Deleting the datum releases its vars
+	drop(datum);
+	assert_eq!(session.refcount(&list), Some(1));
+}
+
+#[hook]
+fn double(num: Value) {
+	Ok(Value::from(num.as_number()? * 2.0))
+}
+
+#[hook]
+fn greet() {
+	Value::from_string(format!("hello from {}", src.get_type()?))
+}
+
+#[hook]
+fn fail() {
+	Err(runtime!("oh no"))
+}
+
+#[test]
+fn hooks() {
+	let session = mock::session();
+
+	let proc = session.define_proc("/proc/double");
+	assert_eq!(proc.call(&[&Value::from(2)]).unwrap(), Value::NULL);
+
+	proc.hook(double).unwrap();
+	assert_eq!(proc.call(&[&Value::from(2)]).unwrap(), Value::from(4));
+	assert!(proc.hook(double).is_err());
+
+	// Datum procs are found on parent types too
+	session.define_proc("/datum/proc/greet").hook(greet).unwrap();
+	let datum = session.new_datum("/datum/thing");
+	assert_eq!(datum.call("greet", &[]).unwrap().as_string().unwrap(), "hello from /datum/thing");
+	assert!(datum.call("missing", &[]).is_err());
+
+	let proc = session.define_proc("/proc/fail");
+	proc.hook(fail).unwrap();
+	assert_eq!(proc.call(&[]).unwrap(), Value::NULL);
+	assert_eq!(session.take_stack_traces(), vec!["oh no HookPath: /fail".to_owned()]);
+}
+
+static mut PERSISTED: u32 = 0;
+
+#[persist(save = "persisted")]
+fn save_persisted() -> Vec<u8> {
+	unsafe { PERSISTED }.to_le_bytes().to_vec()
+}
+
+#[persist(restore = "persisted")]
+fn restore_persisted(data: Vec<u8>) -> Result<(), String> {
+	let bytes = data.try_into().map_err(|_| "wrong size".to_owned())?;
+	unsafe { PERSISTED = u32::from_le_bytes(bytes) };
+	Ok(())
+}
+
+#[test]
+fn persist() {
+	unsafe { PERSISTED = 1234 };
+	let state = persist::save();
+	assert!(persist::is_persisted_state(&state));
+
+	unsafe { PERSISTED = 0 };
+	persist::restore(&state).unwrap();
+	assert_eq!(unsafe { PERSISTED }, 1234);
+
+	assert!(persist::restore("AUXPERSIST1:0500").is_err());
+	assert!(persist::restore("AUXPERSIST1:zz").is_err());
+}
+
+static LOGGED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(vec![]);
+
+#[hook]
+fn capture_log(msg: Value) {
+	LOGGED.lock().unwrap().push(msg.as_string()?);
+	Ok(Value::NULL)
+}
+
+#[test]
+fn log_to_world() {
+	let session = mock::session();
+	session.define_proc("/proc/auxtools_log").hook(capture_log).unwrap();
+	session.define_proc("/proc/fail").hook(fail).unwrap();
+
+	log::init(tracing::Level::DEBUG, vec![log::Target::WorldLog]).unwrap();
+	tracing::info!(answer = 42, "hello");
+	Proc::find("/proc/fail").unwrap().call(&[]).unwrap();
+	log::shutdown();
+	tracing::info!("not logged");
+
+	let logged = std::mem::take(&mut *LOGGED.lock().unwrap());
+	assert_eq!(logged.len(), 2);
+	assert!(logged[0].ends_with(" INFO hello answer=42"));
+	assert!(logged[1].contains("hook{proc=/fail src=null}: hook returned an error: oh no"));
+}
+
+byondapi_fn! { byondapi_add(a, b, ...rest) {
+	let sum = a.as_number()? + b.as_number()?;
+	Ok(Value::from(rest.iter().map(|x| x.as_number().unwrap_or(0.0)).sum::<f32>() + sum))
+} }
+
+#[test]
+fn byondapi() {
+	let session = mock::session();
+
+	let string = Value::from_string("byondapi").unwrap();
+	let converted = unsafe { Value::from_byondapi(&string.as_byondapi()) };
+	assert_eq!(converted, string);
+	assert_eq!(session.refcount(&string), Some(2));
+
+	let args = [Value::from(1), Value::from(2), Value::from(3)];
+	let args: Vec<_> = args.iter().map(|x| x.as_byondapi()).collect();
+	let result = byondapi_add(args.len() as u32, args.as_ptr());
+	assert_eq!(unsafe { Value::from_byondapi(&result) }, Value::from(6));
+
+	let result = byondapi_add(1, args.as_ptr());
+	assert_eq!(unsafe { Value::from_byondapi(&result) }, Value::NULL);
+	assert_eq!(session.take_stack_traces().len(), 1);
+}
+
+// Run with `cargo test -p auxtools --features mock,metrics`
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics() {
+	use std::io::{Read, Write};
+
+	let session = mock::session();
+	let proc = session.define_proc("/proc/metrics_double");
+	proc.hook(double).unwrap();
+	proc.call(&[&Value::from(1)]).unwrap();
+	proc.call(&[&Value::NULL]).unwrap();
+	session.take_stack_traces();
+
+	let rendered = metrics::render();
+	assert!(rendered.contains("auxtools_hook_calls_total{proc=\"/metrics_double\"} 2\n"));
+	assert!(rendered.contains("auxtools_hook_failures_total{proc=\"/metrics_double\"} 1\n"));
+	assert!(rendered.contains("# TYPE auxtools_runtimes_total counter\n"));
+
+	let port = metrics::serve(0).unwrap();
+	let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+	stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+	let mut response = String::new();
+	stream.read_to_string(&mut response).unwrap();
+	metrics::stop();
+
+	assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+	assert!(response.contains("auxtools_hook_calls_total{proc=\"/metrics_double\"} 2\n"));
+}
+
+#[test]
+fn runtime_aggregator() {
+	let _session = mock::session();
+	runtime_aggregator::reset();
+
+	assert_eq!(
+		runtime_aggregator::template("list index out of bounds: 12 (item2 \"sword\") [0x2000001]"),
+		"list index out of bounds: # (item2 \"*\") [#]"
+	);
+
+	runtime_aggregator::record("list index out of bounds: 1");
+	runtime_aggregator::record("list index out of bounds: 2");
+	runtime_aggregator::record("Cannot read null.health");
+
+	let groups = runtime_aggregator::groups();
+	assert_eq!(groups.len(), 2);
+	assert_eq!(groups[0].template, "list index out of bounds: #");
+	assert_eq!(groups[0].example, "list index out of bounds: 2");
+	assert_eq!(groups[0].count, 2);
+
+	let json = runtime_aggregator::summary_json(Some(1));
+	assert!(json.starts_with("{\"total\":3,\"dropped\":0,\"runtimes\":[{\"template\":\"list index out of bounds: #\""));
+	assert!(!json.contains("health"));
+
+	runtime_aggregator::reset();
+	assert!(runtime_aggregator::groups().is_empty());
+}
+
+static RELOADABLE: ReloadableState<u32> = ReloadableState::new(|| 5);
+
+#[test]
+fn reloadable_state() {
+	let session = mock::session();
+	RELOADABLE.with(|x| *x += 1);
+	assert_eq!(RELOADABLE.get(), 6);
+
+	// Only the main thread may touch it
+	assert!(std::thread::spawn(|| RELOADABLE.get()).join().is_err());
+
+	// Re-entering it is refused rather than aliased
+	assert_eq!(RELOADABLE.try_with(|x| *x), Some(6));
+	assert_eq!(RELOADABLE.with(|_| RELOADABLE.try_with(|x| *x)), None);
+	assert!(std::panic::catch_unwind(|| RELOADABLE.with(|_| RELOADABLE.get())).is_err());
+	assert_eq!(RELOADABLE.get(), 6);
+
+	// Shutting down resets it
+	drop(session);
+	let _session = mock::session();
+	assert_eq!(RELOADABLE.get(), 5);
+}
+
+#[hook]
+fn vector_add(other: Value) {
+	Ok(Value::from(src.get_number(byond_string!("x"))? + other.get_number(byond_string!("x"))?))
+}
+
+#[hook]
+fn vector_mul(_other: Value) {
+	Err(runtime!("can't multiply vectors"))
+}
+
+#[hook]
+fn always_true(_other: Value) {
+	Ok(Value::from(true))
+}
+
+#[test]
+fn operators() {
+	let session = mock::session();
+	let num = |x: f32| Value::from(x);
+	let string = |x: &str| Value::from_string(x).unwrap();
+
+	assert_eq!((num(2.0) + num(3.0)).unwrap(), num(5.0));
+	assert_eq!((Value::NULL - num(3.0)).unwrap(), num(-3.0));
+	assert_eq!((num(7.5) % num(2.0)).unwrap(), num(1.0));
+	assert_eq!((num(i32::MIN as f32) % num(-1.0)).unwrap(), num(0.0));
+	assert_eq!((num(6.0) & num(3.0)).unwrap(), num(2.0));
+	assert!((num(1.0) / Value::NULL).is_err());
+	assert_eq!((string("foo") + string("bar")).unwrap().as_string().unwrap(), "foobar");
+	assert_eq!((Value::NULL + string("foo")).unwrap().as_string().unwrap(), "foo");
+	assert!((num(1.0) + string("foo")).is_err());
+
+	let list: List = [1, 2, 3, 2].into_iter().map(Value::from).collect();
+	let twos: List = [2].into_iter().map(Value::from).collect();
+	let list = Value::from(list);
+
+	// The original list is left alone
+	let removed = (&list - Value::from(twos)).unwrap();
+	assert_eq!(removed.as_list().unwrap().len(), 3);
+	assert_eq!(list.as_list().unwrap().len(), 4);
+
+	let added = (&removed + num(2.0)).unwrap();
+	assert!(added.equivalent(&list).unwrap());
+	assert!(!removed.equivalent(&list).unwrap());
+	assert_eq!((&list | num(4.0)).unwrap().as_list().unwrap().len(), 5);
+	assert_eq!((&list & num(2.0)).unwrap().as_list().unwrap().len(), 2);
+
+	assert_eq!(num(1.0).dm_cmp(&Value::NULL).unwrap(), std::cmp::Ordering::Greater);
+	assert_eq!(string("a").dm_cmp(&string("b")).unwrap(), std::cmp::Ordering::Less);
+	assert!(num(0.0).dm_eq(&num(-0.0)));
+
+	// Datums use their operator overloads
+	session.define_proc("/datum/vector/proc/operator+").hook(vector_add).unwrap();
+	let a = session.new_datum("/datum/vector");
+	let b = session.new_datum("/datum/vector");
+	a.set(byond_string!("x"), 1).unwrap();
+	b.set(byond_string!("x"), 2).unwrap();
+	assert_eq!((&a + &b).unwrap(), num(3.0));
+	assert!((&a - &b).unwrap_err().message.starts_with("type mismatch"));
+
+	// BYOND's own operators for built-in types aren't procs, so they can't be
+	// used from here
+	let matrix = session.new_datum("/matrix");
+	assert!((&matrix * &matrix).unwrap_err().message.starts_with("type mismatch"));
+	assert!((&matrix * num(2.0)).unwrap_err().message.starts_with("type mismatch"));
+
+	// Overloads are inherited, and one that runtimes returns null like it does
+	// in DM instead of being reported as missing
+	session.define_proc("/datum/proc/operator*").hook(vector_mul).unwrap();
+	assert_eq!((&a * &b).unwrap(), Value::NULL);
+
+	// null is only skipped for numbers and strings, and lists are still copied
+	session.define_proc("/datum/flag/proc/operator+").hook(always_true).unwrap();
+	let flag = session.new_datum("/datum/flag");
+	assert_eq!((&flag + Value::NULL).unwrap(), num(1.0));
+	assert!((Value::NULL + &flag).unwrap_err().message.starts_with("type mismatch"));
+	let copied = (Value::NULL + &list).unwrap();
+	assert_ne!(copied, list);
+	assert!(copied.equivalent(&list).unwrap());
+
+	// ~= falls back to == without an overload
+	assert!(a.equivalent(&a).unwrap());
+	assert!(!a.equivalent(&b).unwrap());
+	session.define_proc("/datum/vector/proc/operator~=").hook(always_true).unwrap();
+	assert!(a.equivalent(&b).unwrap());
+}
+
+#[test]
+fn types() {
+	let session = mock::session();
+	session.set_parent_type("/datum/gun", "/datum/item");
+
+	let gun = session.new_datum("/datum/gun/laser");
+	let item = session.new_datum("/datum/item");
+	assert!(gun.is_type("/datum/gun/laser"));
+	assert!(gun.is_type("/datum/item"));
+	assert!(gun.is_type("/datum"));
+	assert!(!item.is_type("/datum/gun"));
+
+	let ty = gun.typepath().unwrap();
+	assert_eq!(ty.to_string().unwrap(), "/datum/gun/laser");
+	assert!(ty.is_type("/datum/gun"));
+	assert!(gun.is_subtype(&item));
+	assert!(gun.is_subtype(&item.typepath().unwrap()));
+	assert!(!item.is_subtype(&gun));
+
+	assert!(Value::from(1).typepath().is_err());
+	assert!(!Value::NULL.is_type("/datum"));
+
+	// Lists only match their built-in path
+	let list = Value::from(List::new());
+	assert!(list.typepath().is_err());
+	assert!(list.is_type("/list"));
+	assert!(!list.is_type("/datum"));
+	assert!(!gun.is_type("/list"));
+}
@@ -8,13 +8,19 @@ use winapi::{
 use crate::{server_types::BreakpointReason, DEBUG_SERVER};
 
 extern "system" fn exception_filter(_: *mut EXCEPTION_POINTERS) -> LONG {
-	unsafe {
-		if let Some(dbg) = &mut *DEBUG_SERVER.get() {
-			let ctx = *raw_types::funcs::CURRENT_EXECUTION_CONTEXT;
+	// The debug server can only be used from the main thread
+	if !main_thread::is_main_thread() {
+		return EXCEPTION_EXECUTE_HANDLER;
+	}
+
+	// Nothing to report to if the crash happened inside the server itself
+	DEBUG_SERVER.try_with(|server| {
+		if let Some(dbg) = server {
+			let ctx = unsafe { *raw_types::funcs::CURRENT_EXECUTION_CONTEXT };
 
 			dbg.handle_breakpoint(ctx, BreakpointReason::Runtime("native exception".to_owned()));
 		}
-	}
+	});
 
 	EXCEPTION_EXECUTE_HANDLER
 }
@@ -0,0 +1,719 @@
+//! Debug Adapter Protocol support, so that editors other than VS Code can
+//! debug a world.
+//!
+//! The adapter runs on its own thread and is a client of the main-thread
+//! `Server` in the same way the VS Code extension is: each DAP request is
+//! turned into one or more `Request`s and their `Response`s are turned back
+//! into DAP responses. Breakpoints are set by source line, which the `Server`
+//! looks up with `Request::LineInstructions`.
+//!
+//! DAP ids can't be 0 in some clients, so thread ids are stack ids + 1 and
+//! frame ids are the `Server`'s frame ids + 1.
+use std::{
+	collections::{HashMap, VecDeque},
+	error::Error,
+	io::{self, BufRead, BufReader, Write},
+	net::{Shutdown, TcpListener, TcpStream},
+	path::PathBuf,
+	sync::mpsc,
+	thread,
+	thread::JoinHandle
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{server::Connection, server_types::*};
+
+enum Message {
+	Client(Json),
+	Server(Response),
+	// The client's side of the connection has gone
+	Closed
+}
+
+/// Hands the `Server`'s responses to the adapter thread
+pub struct Responder(mpsc::Sender<Message>);
+
+impl Responder {
+	pub fn send(&self, response: Response) -> Result<(), Box<dyn Error>> {
+		self.0.send(Message::Server(response))?;
+		Ok(())
+	}
+}
+
+struct Adapter<W> {
+	stream: W,
+	seq: u64,
+	requests: mpsc::Sender<Request>,
+	messages: mpsc::Receiver<Message>,
+	// Client messages that arrived while we were waiting on the server
+	pending: VecDeque<Json>,
+	// Where the compiler's file names are relative to, which is normally the
+	// directory of the .dme
+	source_root: PathBuf,
+	// Breakpoints we've set per source path, as setBreakpoints replaces them all
+	breakpoints: HashMap<String, Vec<InstructionRef>>,
+	proc_files: HashMap<ProcRef, Option<String>>
+}
+
+pub fn spawn_listener(listener: TcpListener, requests: mpsc::Sender<Request>, connection_sender: mpsc::Sender<Connection>) -> JoinHandle<()> {
+	thread::spawn(move || {
+		let stream = match listener.accept() {
+			Ok((stream, _)) => stream,
+			Err(e) => {
+				eprintln!("Debug server failed to accept connection: {}", e);
+				return;
+			}
+		};
+
+		let (messages_sender, messages_receiver) = mpsc::channel();
+
+		let reader = stream.try_clone().unwrap();
+		let shutdown = stream.try_clone().unwrap();
+		let client_messages = messages_sender.clone();
+		thread::spawn(move || read_messages(reader, client_messages));
+
+		if let Err(e) = connection_sender.send(Connection::Dap(Responder(messages_sender))) {
+			eprintln!("Debug server thread failed to pass DAP connection: {}", e);
+			return;
+		}
+
+		let adapter = Adapter {
+			stream,
+			seq: 0,
+			requests,
+			messages: messages_receiver,
+			pending: VecDeque::new(),
+			source_root: std::env::current_dir().unwrap_or_default(),
+			breakpoints: HashMap::new(),
+			proc_files: HashMap::new()
+		};
+
+		adapter.run();
+		let _ = shutdown.shutdown(Shutdown::Both);
+	})
+}
+
+// Messages are a Content-Length header, a blank line and then JSON
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Json, Box<dyn Error>> {
+	let mut length = None;
+
+	loop {
+		let mut header = String::new();
+		if reader.read_line(&mut header)? == 0 {
+			return Err("connection closed".into());
+		}
+
+		let header = header.trim_end();
+		if header.is_empty() {
+			break;
+		}
+
+		if let Some(value) = header.strip_prefix("Content-Length:") {
+			length = Some(value.trim().parse::<usize>()?);
+		}
+	}
+
+	let mut buf = vec![0; length.ok_or("message has no Content-Length")?];
+	reader.read_exact(&mut buf)?;
+	Ok(serde_json::from_slice(&buf)?)
+}
+
+fn write_message<W: Write>(stream: &mut W, message: &Json) -> io::Result<()> {
+	let data = serde_json::to_vec(message)?;
+	write!(stream, "Content-Length: {}\r\n\r\n", data.len())?;
+	stream.write_all(&data)?;
+	stream.flush()
+}
+
+fn read_messages(stream: TcpStream, messages: mpsc::Sender<Message>) {
+	let mut reader = BufReader::new(stream);
+
+	loop {
+		match read_message(&mut reader) {
+			Ok(message) => {
+				if messages.send(Message::Client(message)).is_err() {
+					return;
+				}
+			}
+
+			Err(e) => {
+				eprintln!("DAP client read error: {}", e);
+				let _ = messages.send(Message::Closed);
+				return;
+			}
+		}
+	}
+}
+
+fn unexpected(response: Response) -> Box<dyn Error> {
+	format!("unexpected response from debug server: {:?}", response).into()
+}
+
+fn stack_id(args: &Json) -> u32 {
+	args["threadId"].as_u64().unwrap_or(1).saturating_sub(1) as u32
+}
+
+fn frame_id(args: &Json) -> Option<u32> {
+	args["frameId"].as_u64().map(|x| x.saturating_sub(1) as u32)
+}
+
+impl<W: Write> Adapter<W> {
+	fn run(mut self) {
+		match self.serve() {
+			Ok(()) => eprintln!("Debug client disconnected"),
+			Err(e) => eprintln!("DAP adapter stopped: {}", e)
+		}
+	}
+
+	fn serve(&mut self) -> Result<(), Box<dyn Error>> {
+		loop {
+			let message = match self.pending.pop_front() {
+				Some(message) => message,
+				None => match self.messages.recv()? {
+					Message::Client(message) => message,
+					Message::Server(response) => {
+						self.handle_event(response)?;
+						continue;
+					}
+					Message::Closed => return Ok(())
+				}
+			};
+
+			if message["type"] == "request" && !self.handle_request(&message)? {
+				return Ok(());
+			}
+		}
+	}
+
+	fn send(&mut self, mut message: Json) -> io::Result<()> {
+		self.seq += 1;
+		message["seq"] = self.seq.into();
+		write_message(&mut self.stream, &message)
+	}
+
+	fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+		self.send(json!({
+			"type": "event",
+			"event": event,
+			"body": body
+		}))
+	}
+
+	fn respond(&mut self, request: &Json, body: Result<Json, String>) -> io::Result<()> {
+		let mut response = json!({
+			"type": "response",
+			"request_seq": request["seq"],
+			"command": request["command"],
+			"success": body.is_ok()
+		});
+
+		match body {
+			Ok(Json::Null) => {}
+			Ok(body) => response["body"] = body,
+			Err(message) => response["message"] = message.into()
+		}
+
+		self.send(response)
+	}
+
+	// Sends a request to the server and waits for its response, passing on any
+	// events that come before it
+	fn call(&mut self, request: Request) -> Result<Response, Box<dyn Error>> {
+		self.requests.send(request)?;
+
+		loop {
+			match self.messages.recv()? {
+				Message::Client(message) => self.pending.push_back(message),
+				Message::Server(response @ (Response::Disconnect | Response::Notification { .. } | Response::BreakpointHit { .. })) => {
+					self.handle_event(response)?;
+				}
+				Message::Server(response) => return Ok(response),
+				Message::Closed => return Err("client disconnected".into())
+			}
+		}
+	}
+
+	fn handle_event(&mut self, response: Response) -> Result<(), Box<dyn Error>> {
+		match response {
+			Response::Notification { message } => {
+				self.event(
+					"output",
+					json!({
+						"category": "console",
+						"output": message + "\n"
+					})
+				)?;
+			}
+
+			Response::BreakpointHit { reason } => {
+				let (reason, text) = match reason {
+					BreakpointReason::Breakpoint => ("breakpoint", None),
+					BreakpointReason::Step => ("step", None),
+					BreakpointReason::Pause => ("pause", None),
+					BreakpointReason::Runtime(error) => ("exception", Some(error))
+				};
+
+				let mut body = json!({
+					"reason": reason,
+					"threadId": 1,
+					"allThreadsStopped": true
+				});
+
+				if let Some(text) = text {
+					body["text"] = text.into();
+				}
+
+				self.event("stopped", body)?;
+			}
+
+			Response::Disconnect => {
+				self.event("terminated", json!({}))?;
+				return Err("debug server disconnected".into());
+			}
+
+			response => return Err(unexpected(response))
+		}
+
+		Ok(())
+	}
+
+	// returns false if the client has disconnected
+	fn handle_request(&mut self, request: &Json) -> Result<bool, Box<dyn Error>> {
+		let command = request["command"].as_str().unwrap_or_default();
+		let args = &request["arguments"];
+
+		let body = match command {
+			"initialize" => Ok(json!({
+				"supportsConfigurationDoneRequest": true,
+				"supportsConditionalBreakpoints": true,
+				"supportsLogPoints": true,
+				"supportsHitConditionalBreakpoints": true,
+				"supportsEvaluateForHovers": true,
+				"exceptionBreakpointFilters": [{
+					"filter": "runtimes",
+					"label": "Runtime errors",
+					"default": true
+				}]
+			})),
+
+			"launch" | "attach" => {
+				if let Some(root) = args["sourceRoot"].as_str() {
+					self.source_root = PathBuf::from(root);
+				}
+
+				Ok(Json::Null)
+			}
+
+			"configurationDone" => {
+				self.call(Request::Configured)?;
+				Ok(Json::Null)
+			}
+
+			"setBreakpoints" => self.set_breakpoints(args)?,
+
+			"setExceptionBreakpoints" => {
+				let should_catch = args["filters"].as_array().into_iter().flatten().any(|x| x == "runtimes");
+
+				// The server doesn't respond to this one
+				self.requests.send(Request::CatchRuntimes { should_catch })?;
+				Ok(Json::Null)
+			}
+
+			"threads" => Ok(self.threads()?),
+			"stackTrace" => Ok(self.stack_trace(args)?),
+			"scopes" => Ok(self.scopes(args)?),
+			"variables" => Ok(self.variables(args)?),
+			"evaluate" => Ok(self.evaluate(args)?),
+
+			"continue" => {
+				self.call(Request::Continue {
+					kind: ContinueKind::Continue
+				})?;
+				Ok(json!({ "allThreadsContinued": true }))
+			}
+
+			"next" => {
+				let stack_id = stack_id(args);
+				self.call(Request::Continue {
+					kind: ContinueKind::StepOver { stack_id }
+				})?;
+				Ok(Json::Null)
+			}
+
+			"stepIn" => {
+				let stack_id = stack_id(args);
+				self.call(Request::Continue {
+					kind: ContinueKind::StepInto { stack_id }
+				})?;
+				Ok(Json::Null)
+			}
+
+			"stepOut" => {
+				let stack_id = stack_id(args);
+				self.call(Request::Continue {
+					kind: ContinueKind::StepOut { stack_id }
+				})?;
+				Ok(Json::Null)
+			}
+
+			"pause" => {
+				self.call(Request::Pause)?;
+				Ok(Json::Null)
+			}
+
+			"disconnect" => {
+				self.respond(request, Ok(Json::Null))?;
+				return Ok(false);
+			}
+
+			_ => Err(format!("unsupported request: {}", command))
+		};
+
+		self.respond(request, body)?;
+
+		// Tells the client it can send breakpoints, followed by configurationDone
+		if command == "initialize" {
+			self.event("initialized", Json::Null)?;
+		}
+
+		Ok(true)
+	}
+
+	fn set_breakpoints(&mut self, args: &Json) -> Result<Result<Json, String>, Box<dyn Error>> {
+		let path = match args["source"]["path"].as_str() {
+			Some(path) => path.to_owned(),
+			None => return Ok(Err("setBreakpoints needs a source path".to_owned()))
+		};
+
+		for instruction in self.breakpoints.remove(&path).unwrap_or_default() {
+			self.call(Request::BreakpointUnset { instruction })?;
+		}
+
+		let mut set = vec![];
+		let mut results = vec![];
+
+		for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+			let line = breakpoint["line"].as_u64().unwrap_or(0) as u32;
+			let condition = breakpoint["condition"].as_str().filter(|x| !x.is_empty()).map(str::to_owned);
+			let log_message = breakpoint["logMessage"].as_str().filter(|x| !x.is_empty()).map(str::to_owned);
+
+			let hit_condition = match breakpoint["hitCondition"].as_str().filter(|x| !x.is_empty()) {
+				Some(text) => match HitCondition::parse(text) {
+					Some(hit_condition) => Some(hit_condition),
+					None => {
+						results.push(json!({
+							"verified": false,
+							"line": line,
+							"message": "hit conditions look like == N, >= N or % N"
+						}));
+						continue;
+					}
+				},
+
+				None => None
+			};
+
+			let instructions = match self.call(Request::LineInstructions { file: path.clone(), line })? {
+				Response::LineInstructions { instructions } => instructions,
+				response => return Err(unexpected(response))
+			};
+
+			let mut verified = false;
+
+			for instruction in instructions {
+				let result = self.call(Request::BreakpointSetWithOptions {
+					instruction: instruction.clone(),
+					condition: condition.clone(),
+					log_message: log_message.clone(),
+					hit_condition
+				})?;
+
+				if let Response::BreakpointSet {
+					result: BreakpointSetResult::Success { .. }
+				} = result
+				{
+					verified = true;
+					set.push(instruction);
+				}
+			}
+
+			results.push(json!({
+				"verified": verified,
+				"line": line
+			}));
+		}
+
+		self.breakpoints.insert(path, set);
+		Ok(Ok(json!({ "breakpoints": results })))
+	}
+
+	fn threads(&mut self) -> Result<Json, Box<dyn Error>> {
+		let stacks = match self.call(Request::Stacks)? {
+			Response::Stacks { stacks } => stacks,
+			response => return Err(unexpected(response))
+		};
+
+		// Clients expect there to always be a thread, even while the world is running
+		if stacks.is_empty() {
+			return Ok(json!({ "threads": [{ "id": 1, "name": "main" }] }));
+		}
+
+		let threads: Vec<Json> = stacks
+			.into_iter()
+			.map(|stack| json!({ "id": stack.id + 1, "name": stack.name }))
+			.collect();
+
+		Ok(json!({ "threads": threads }))
+	}
+
+	fn source(&mut self, proc: &ProcRef) -> Result<Json, Box<dyn Error>> {
+		if !self.proc_files.contains_key(proc) {
+			let file = match self.call(Request::ProcFile { proc: proc.clone() })? {
+				Response::ProcFile { file } => file,
+				response => return Err(unexpected(response))
+			};
+
+			self.proc_files.insert(proc.clone(), file);
+		}
+
+		Ok(match &self.proc_files[proc] {
+			Some(file) => json!({
+				"name": file,
+				"path": self.source_root.join(file.replace('\\', "/"))
+			}),
+
+			None => Json::Null
+		})
+	}
+
+	fn stack_trace(&mut self, args: &Json) -> Result<Json, Box<dyn Error>> {
+		let response = self.call(Request::StackFrames {
+			stack_id: stack_id(args),
+			start_frame: args["startFrame"].as_u64().map(|x| x as u32),
+			count: args["levels"].as_u64().filter(|x| *x > 0).map(|x| x as u32)
+		})?;
+
+		let (frames, total_count) = match response {
+			Response::StackFrames { frames, total_count } => (frames, total_count),
+			response => return Err(unexpected(response))
+		};
+
+		let mut stack_frames = vec![];
+
+		for frame in frames {
+			let mut stack_frame = json!({
+				"id": frame.id + 1,
+				"name": frame.instruction.proc.path,
+				"line": frame.line.unwrap_or(0),
+				"column": 0
+			});
+
+			let source = self.source(&frame.instruction.proc)?;
+			if !source.is_null() {
+				stack_frame["source"] = source;
+			}
+
+			stack_frames.push(stack_frame);
+		}
+
+		Ok(json!({
+			"stackFrames": stack_frames,
+			"totalFrames": total_count
+		}))
+	}
+
+	fn scopes(&mut self, args: &Json) -> Result<Json, Box<dyn Error>> {
+		let response = self.call(Request::Scopes {
+			frame_id: frame_id(args).unwrap_or(0)
+		})?;
+
+		let (arguments, locals, globals) = match response {
+			Response::Scopes { arguments, locals, globals } => (arguments, locals, globals),
+			response => return Err(unexpected(response))
+		};
+
+		let scopes: Vec<Json> = [
+			("Arguments", "arguments", arguments),
+			("Locals", "locals", locals),
+			("Globals", "globals", globals)
+		]
+		.into_iter()
+		.filter_map(|(name, hint, vars)| {
+			vars.map(|vars| {
+				json!({
+					"name": name,
+					"presentationHint": hint,
+					"variablesReference": vars.0,
+					"expensive": hint == "globals"
+				})
+			})
+		})
+		.collect();
+
+		Ok(json!({ "scopes": scopes }))
+	}
+
+	fn variables(&mut self, args: &Json) -> Result<Json, Box<dyn Error>> {
+		let vars = VariablesRef(args["variablesReference"].as_i64().unwrap_or(0) as i32);
+
+		let vars = match self.call(Request::Variables { vars })? {
+			Response::Variables { vars } => vars,
+			response => return Err(unexpected(response))
+		};
+
+		let variables: Vec<Json> = vars
+			.into_iter()
+			.map(|var| {
+				json!({
+					"name": var.name,
+					"value": var.value,
+					"variablesReference": var.variables.map_or(0, |x| x.0)
+				})
+			})
+			.collect();
+
+		Ok(json!({ "variables": variables }))
+	}
+
+	fn evaluate(&mut self, args: &Json) -> Result<Json, Box<dyn Error>> {
+		let response = self.call(Request::Eval {
+			frame_id: frame_id(args),
+			command: args["expression"].as_str().unwrap_or_default().to_owned(),
+			context: args["context"].as_str().map(str::to_owned)
+		})?;
+
+		match response {
+			Response::Eval(EvalResponse { value, variables }) => Ok(json!({
+				"result": value,
+				"variablesReference": variables.map_or(0, |x| x.0)
+			})),
+
+			response => Err(unexpected(response))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// An adapter whose server replies are queued up front, along with what it
+	// sends to the server
+	fn adapter(replies: Vec<Response>) -> (Adapter<Vec<u8>>, mpsc::Receiver<Request>) {
+		let (requests, requests_receiver) = mpsc::channel();
+		let (messages_sender, messages) = mpsc::channel();
+
+		for reply in replies {
+			messages_sender.send(Message::Server(reply)).unwrap();
+		}
+
+		let adapter = Adapter {
+			stream: vec![],
+			seq: 0,
+			requests,
+			messages,
+			pending: VecDeque::new(),
+			source_root: PathBuf::new(),
+			breakpoints: HashMap::new(),
+			proc_files: HashMap::new()
+		};
+
+		(adapter, requests_receiver)
+	}
+
+	fn instruction(offset: u32) -> InstructionRef {
+		InstructionRef {
+			proc: ProcRef {
+				path: "/proc/foo".to_owned(),
+				override_id: 0
+			},
+			offset
+		}
+	}
+
+	const SET: Response = Response::BreakpointSet {
+		result: BreakpointSetResult::Success { line: None }
+	};
+
+	#[test]
+	fn framing() {
+		let mut data = vec![];
+		write_message(&mut data, &json!({ "seq": 1 })).unwrap();
+		assert_eq!(data, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+		data.extend_from_slice(b"Content-Type: application/json\r\nContent-Length: 2\r\n\r\n{}");
+
+		let mut reader = &data[..];
+		assert_eq!(read_message(&mut reader).unwrap(), json!({ "seq": 1 }));
+		assert_eq!(read_message(&mut reader).unwrap(), json!({}));
+		assert!(read_message(&mut reader).is_err());
+
+		assert!(read_message(&mut &b"Content-Type: x\r\n\r\n{}"[..]).is_err());
+		assert!(read_message(&mut &b"Content-Length: 5\r\n\r\n{}"[..]).is_err());
+	}
+
+	#[test]
+	fn ids() {
+		assert_eq!(stack_id(&json!({ "threadId": 3 })), 2);
+		assert_eq!(stack_id(&json!({})), 0);
+		assert_eq!(stack_id(&json!({ "threadId": 0 })), 0);
+		assert_eq!(frame_id(&json!({ "frameId": 1 })), Some(0));
+		assert_eq!(frame_id(&json!({})), None);
+
+		let (mut adapter, _requests) = adapter(vec![
+			Response::Stacks {
+				stacks: vec![Stack {
+					id: 0,
+					name: "foo".to_owned()
+				}]
+			},
+			Response::Stacks { stacks: vec![] }
+		]);
+
+		assert_eq!(adapter.threads().unwrap(), json!({ "threads": [{ "id": 1, "name": "foo" }] }));
+		assert_eq!(adapter.threads().unwrap(), json!({ "threads": [{ "id": 1, "name": "main" }] }));
+	}
+
+	#[test]
+	fn set_breakpoints_replaces_file() {
+		let (mut adapter, requests) = adapter(vec![
+			Response::LineInstructions {
+				instructions: vec![instruction(1), instruction(2)]
+			},
+			SET,
+			SET,
+			Response::BreakpointUnset { success: true },
+			Response::BreakpointUnset { success: true },
+			Response::LineInstructions { instructions: vec![] }
+		]);
+
+		let first = adapter
+			.set_breakpoints(&json!({
+				"source": { "path": "code/foo.dm" },
+				"breakpoints": [{ "line": 3, "logMessage": "x is {x}" }]
+			}))
+			.unwrap()
+			.unwrap();
+		assert_eq!(first, json!({ "breakpoints": [{ "verified": true, "line": 3 }] }));
+		assert_eq!(adapter.breakpoints["code/foo.dm"], [instruction(1), instruction(2)]);
+
+		let second = adapter
+			.set_breakpoints(&json!({
+				"source": { "path": "code/foo.dm" },
+				"breakpoints": [{ "line": 4 }, { "line": 5, "hitCondition": "sometimes" }]
+			}))
+			.unwrap()
+			.unwrap();
+		assert_eq!(second["breakpoints"][0], json!({ "verified": false, "line": 4 }));
+		assert_eq!(second["breakpoints"][1]["verified"], false);
+		assert!(adapter.breakpoints["code/foo.dm"].is_empty());
+
+		let sent: Vec<Request> = requests.try_iter().collect();
+		assert_eq!(sent.len(), 6);
+		assert!(matches!(&sent[0], Request::LineInstructions { file, line: 3 } if file == "code/foo.dm"));
+		assert!(matches!(&sent[1], Request::BreakpointSetWithOptions { log_message: Some(x), .. } if x == "x is {x}"));
+		assert!(matches!(&sent[3], Request::BreakpointUnset { instruction: x } if *x == instruction(1)));
+		assert!(matches!(&sent[4], Request::BreakpointUnset { instruction: x } if *x == instruction(2)));
+		assert!(matches!(&sent[5], Request::LineInstructions { line: 4, .. }));
+	}
+}
@@ -1,14 +1,10 @@
-use std::{cell::UnsafeCell, collections::HashMap, sync::Mutex};
+use std::collections::HashMap;
 
 use auxtools::*;
-use instruction_hooking::{
-	disassemble_env::{self, DisassembleEnv},
-	InstructionHook
-};
-use lazy_static::lazy_static;
+use instruction_hooking::disassemble_env::{self, DisassembleEnv};
 
 use crate::{
-	server::Server,
+	server,
 	server_types::{BreakpointReason, ContinueKind},
 	DEBUG_SERVER
 };
@@ -18,15 +14,6 @@ const OPCODE_DBGLINE: u32 = 0x85;
 const OPCODE_DEBUG_BREAK: u32 = 0x1337;
 const OPCODE_DEBUG_OPERAND: u32 = 0x1338;
 
-#[shutdown]
-fn instruction_hooking_shutdown() {
-	unsafe {
-		CURRENT_ACTION = DebuggerAction::None;
-		*DEFERRED_INSTRUCTION_REPLACE.get() = None;
-		*ORIGINAL_BYTECODE.lock().unwrap() = HashMap::new();
-	}
-}
-
 #[derive(PartialEq, Eq, Copy, Clone)]
 struct ProcInstanceRef(u16);
 
@@ -57,9 +44,10 @@ enum DebuggerAction {
 	StepOut { target: ProcInstanceRef }
 }
 
-static mut CURRENT_ACTION: DebuggerAction = DebuggerAction::None;
+// These are all reset when auxtools shuts down
+static CURRENT_ACTION: ReloadableState<DebuggerAction> = ReloadableState::new(|| DebuggerAction::None);
 
-static mut DEFERRED_INSTRUCTION_REPLACE: UnsafeCell<Option<(Vec<u32>, *mut u32)>> = UnsafeCell::new(None);
+static DEFERRED_INSTRUCTION_REPLACE: ReloadableState<Option<(Vec<u32>, *mut u32)>> = ReloadableState::new(|| None);
 
 #[derive(PartialEq, Eq, Hash)]
 struct PtrKey(usize);
@@ -70,9 +58,7 @@ impl PtrKey {
 	}
 }
 
-lazy_static! {
-	static ref ORIGINAL_BYTECODE: Mutex<HashMap<PtrKey, Vec<u32>>> = Mutex::new(HashMap::new());
-}
+static ORIGINAL_BYTECODE: ReloadableState<HashMap<PtrKey, Vec<u32>>> = ReloadableState::new(HashMap::new);
 
 fn is_generated_proc(ctx: *mut raw_types::procs::ExecutionContext) -> bool {
 	unsafe {
@@ -108,11 +94,16 @@ fn get_proc_ctx(stack_id: u32) -> *mut raw_types::procs::ExecutionContext {
 }
 
 fn handle_breakpoint(ctx: *mut raw_types::procs::ExecutionContext, reason: BreakpointReason) -> DebuggerAction {
-	let action = unsafe {
-		match &mut *DEBUG_SERVER.get() {
-			Some(server) => server.handle_breakpoint(ctx, reason),
-			None => ContinueKind::Continue
-		}
+	let action = DEBUG_SERVER.try_with(|server| match server {
+		Some(server) => server.handle_breakpoint(ctx, reason),
+		None => ContinueKind::Continue
+	});
+
+	// The server is already paused further up the stack and is running DM
+	// itself, so leave whatever it was doing alone
+	let action = match action {
+		Some(action) => action,
+		None => return CURRENT_ACTION.get()
 	};
 
 	match action {
@@ -185,62 +176,59 @@ fn proc_instance_is_suspended(proc_ref: ProcInstanceRef) -> bool {
 
 #[runtime_handler]
 fn handle_runtime(error: &str) {
-	unsafe {
-		let ctx = *raw_types::funcs::CURRENT_EXECUTION_CONTEXT;
-
-		// If this is eval code, don't catch the breakpoint
-		// TODO: Could try to make this work
-		if let Some(server) = &mut *DEBUG_SERVER.get() {
-			if server.is_in_eval() {
-				server.set_eval_error(error.into());
-				return;
-			}
-		}
+	let ctx = unsafe { *raw_types::funcs::CURRENT_EXECUTION_CONTEXT };
 
-		CURRENT_ACTION = handle_breakpoint(ctx, BreakpointReason::Runtime(error.to_string()));
+	// If this is eval code, don't catch the breakpoint
+	// TODO: Could try to make this work
+	if server::set_eval_error(error) {
+		return;
 	}
+
+	CURRENT_ACTION.set(handle_breakpoint(ctx, BreakpointReason::Runtime(error.to_string())));
 }
 
-impl InstructionHook for Server {
-	fn handle_instruction(&mut self, ctx: *mut raw_types::procs::ExecutionContext) {
-		// Always handle the deferred instruction replacement first - everything else
-		// will depend on it
+// Called for every instruction while the debug server is enabled
+pub(crate) fn handle_instruction(ctx: *mut raw_types::procs::ExecutionContext) {
+	// Always handle the deferred instruction replacement first - everything else
+	// will depend on it
+	if let Some((src, dst)) = DEFERRED_INSTRUCTION_REPLACE.replace(None) {
 		unsafe {
-			let deferred = DEFERRED_INSTRUCTION_REPLACE.get();
-			if let Some((src, dst)) = &*deferred {
-				std::ptr::copy_nonoverlapping(src.as_ptr(), *dst, src.len());
-				*deferred = None;
-			}
+			std::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
 		}
+	}
 
-		let server = self;
-		if server.process() {
-			unsafe {
-				CURRENT_ACTION = DebuggerAction::Pause;
-			}
-		}
+	// The server runs DM itself, like when evaluating expressions. Those
+	// instructions come back through here while it's in use and aren't
+	// debugged, but the breakpoints they hit still have to be patched out.
+	let should_pause = DEBUG_SERVER.try_with(|server| server.as_mut().is_some_and(|server| server.process()));
+	let debugging = should_pause.is_some();
 
-		let opcode_ptr = unsafe { (*ctx).bytecode().add((*ctx).bytecode_offset() as usize) };
-		let opcode = unsafe { *opcode_ptr };
-		let is_dbgline = opcode == OPCODE_DBGLINE;
+	if should_pause == Some(true) {
+		CURRENT_ACTION.set(DebuggerAction::Pause);
+	}
+
+	let opcode_ptr = unsafe { (*ctx).bytecode().add((*ctx).bytecode_offset() as usize) };
+	let opcode = unsafe { *opcode_ptr };
+	let is_dbgline = opcode == OPCODE_DBGLINE;
 
-		// This lets us ignore any actual breakpoints we hit if we've already paused for
-		// another reason
-		let mut did_breakpoint = false;
+	// This lets us ignore any actual breakpoints we hit if we've already paused for
+	// another reason
+	let mut did_breakpoint = false;
 
+	if debugging {
 		unsafe {
-			match CURRENT_ACTION {
+			match CURRENT_ACTION.get() {
 				DebuggerAction::None => {}
 
 				DebuggerAction::Pause => {
-					CURRENT_ACTION = DebuggerAction::None;
-					CURRENT_ACTION = handle_breakpoint(ctx, BreakpointReason::Pause);
+					CURRENT_ACTION.set(DebuggerAction::None);
+					CURRENT_ACTION.set(handle_breakpoint(ctx, BreakpointReason::Pause));
 					did_breakpoint = true;
 				}
 
 				DebuggerAction::BreakOnNext => {
-					CURRENT_ACTION = DebuggerAction::None;
-					CURRENT_ACTION = handle_breakpoint(ctx, BreakpointReason::Step);
+					CURRENT_ACTION.set(DebuggerAction::None);
+					CURRENT_ACTION.set(handle_breakpoint(ctx, BreakpointReason::Step));
 					did_breakpoint = true;
 				}
 
@@ -249,13 +237,13 @@ impl InstructionHook for Server {
 				// 2) We're inside the target context and on a DbgLine instruction
 				DebuggerAction::StepOver { target } => {
 					if is_dbgline && target.is((*ctx).proc_instance()) {
-						CURRENT_ACTION = DebuggerAction::BreakOnNext;
+						CURRENT_ACTION.set(DebuggerAction::BreakOnNext);
 					} else {
 						// If the context isn't in any stacks, it has just returned. Break!
 						// TODO: Don't break if the context's stack is gone (returned to C)
 						if !proc_instance_is_in_stack(ctx, target) && !proc_instance_is_suspended(target) {
-							CURRENT_ACTION = DebuggerAction::None;
-							CURRENT_ACTION = handle_breakpoint(ctx, BreakpointReason::Step);
+							CURRENT_ACTION.set(DebuggerAction::None);
+							CURRENT_ACTION.set(handle_breakpoint(ctx, BreakpointReason::Step));
 							did_breakpoint = true;
 						}
 					}
@@ -270,7 +258,7 @@ impl InstructionHook for Server {
 						let is_in_parent = parent.is((*ctx).proc_instance());
 
 						if is_dbgline && is_in_parent {
-							CURRENT_ACTION = DebuggerAction::BreakOnNext;
+							CURRENT_ACTION.set(DebuggerAction::BreakOnNext);
 						} else if !is_in_parent {
 							let in_stack = proc_instance_is_in_stack(ctx, parent);
 							let is_suspended = proc_instance_is_suspended(parent);
@@ -278,11 +266,11 @@ impl InstructionHook for Server {
 							// If the context isn't in any stacks, it has just returned. Break!
 							// TODO: Don't break if the context's stack is gone (returned to C)
 							if !in_stack && !is_suspended {
-								CURRENT_ACTION = DebuggerAction::None;
-								CURRENT_ACTION = handle_breakpoint(ctx, BreakpointReason::Step);
+								CURRENT_ACTION.set(DebuggerAction::None);
+								CURRENT_ACTION.set(handle_breakpoint(ctx, BreakpointReason::Step));
 								did_breakpoint = true;
 							} else if in_stack && is_dbgline {
-								CURRENT_ACTION = DebuggerAction::BreakOnNext;
+								CURRENT_ACTION.set(DebuggerAction::BreakOnNext);
 							}
 						}
 					}
@@ -292,8 +280,8 @@ impl InstructionHook for Server {
 				DebuggerAction::StepOut { target } => {
 					if !is_generated_proc(ctx) {
 						if target.is((*ctx).proc_instance()) {
-							CURRENT_ACTION = DebuggerAction::None;
-							CURRENT_ACTION = handle_breakpoint(ctx, BreakpointReason::Step);
+							CURRENT_ACTION.set(DebuggerAction::None);
+							CURRENT_ACTION.set(handle_breakpoint(ctx, BreakpointReason::Step));
 							did_breakpoint = true;
 						} else {
 							// If Our context disappeared, just stop the step
@@ -301,35 +289,40 @@ impl InstructionHook for Server {
 							let is_suspended = proc_instance_is_suspended(target);
 
 							if !in_stack && !is_suspended {
-								CURRENT_ACTION = DebuggerAction::None;
+								CURRENT_ACTION.set(DebuggerAction::None);
 							}
 						}
 					}
 				}
 			}
 		}
+	}
 
-		if opcode == OPCODE_DEBUG_BREAK {
-			// We don't want to break twice when stepping on to a breakpoint
-			if !did_breakpoint {
-				unsafe {
-					CURRENT_ACTION = DebuggerAction::None;
-					CURRENT_ACTION = handle_breakpoint(ctx, BreakpointReason::Breakpoint);
+	if opcode == OPCODE_DEBUG_BREAK {
+		// We don't want to break twice when stepping on to a breakpoint, but a
+		// logpoint there still logs
+		if debugging && !did_breakpoint {
+			CURRENT_ACTION.set(DebuggerAction::None);
+			CURRENT_ACTION.set(handle_breakpoint(ctx, BreakpointReason::Breakpoint));
+		} else if debugging {
+			DEBUG_SERVER.try_with(|server| {
+				if let Some(server) = server {
+					server.handle_stepped_breakpoint(ctx);
 				}
-			}
+			});
+		}
 
-			// ORIGINAL_BYTECODE won't contain an entry if this breakpoint has already been
-			// removed
-			let map = ORIGINAL_BYTECODE.lock().unwrap();
+		// ORIGINAL_BYTECODE won't contain an entry if this breakpoint has already been
+		// removed
+		ORIGINAL_BYTECODE.with(|map| {
 			if let Some(original) = map.get(&PtrKey::new(opcode_ptr)) {
 				unsafe {
-					let deferred_replace = DEFERRED_INSTRUCTION_REPLACE.get();
-					assert_eq!(*deferred_replace, None);
-					*deferred_replace = Some((std::slice::from_raw_parts(opcode_ptr, original.len()).to_vec(), opcode_ptr));
+					let current = std::slice::from_raw_parts(opcode_ptr, original.len()).to_vec();
+					assert_eq!(DEFERRED_INSTRUCTION_REPLACE.replace(Some((current, opcode_ptr))), None);
 					std::ptr::copy_nonoverlapping(original.as_ptr(), opcode_ptr, original.len());
 				}
 			}
-		}
+		});
 	}
 }
 
@@ -378,12 +371,8 @@ pub fn hook_instruction(proc: &Proc, offset: u32) -> Result<(), InstructionHookE
 		return Ok(());
 	}
 
-	unsafe {
-		ORIGINAL_BYTECODE.lock().unwrap().insert(
-			PtrKey::new(opcode_ptr),
-			std::slice::from_raw_parts(opcode_ptr, instruction_length).to_vec()
-		);
-	}
+	let original = unsafe { std::slice::from_raw_parts(opcode_ptr, instruction_length).to_vec() };
+	ORIGINAL_BYTECODE.with(|map| map.insert(PtrKey::new(opcode_ptr), original));
 
 	bytecode[offset as usize] = OPCODE_DEBUG_BREAK;
 	for i in (offset + 1)..(offset + instruction_length as u32) {
@@ -412,20 +401,19 @@ pub fn unhook_instruction(proc: &Proc, offset: u32) -> Result<(), InstructionUnh
 
 	// ORIGINAL_BYTECODE won't contain an entry if this breakpoint has already been
 	// removed
-	let mut map = ORIGINAL_BYTECODE.lock().unwrap();
-	if let Some(original) = map.get(&PtrKey::new(opcode_ptr)) {
-		unsafe {
-			let deferred = DEFERRED_INSTRUCTION_REPLACE.get();
-			if let Some((_, dst)) = *deferred {
-				if dst == opcode_ptr {
-					deferred.replace(None);
+	ORIGINAL_BYTECODE.with(|map| {
+		if let Some(original) = map.remove(&PtrKey::new(opcode_ptr)) {
+			DEFERRED_INSTRUCTION_REPLACE.with(|deferred| {
+				if matches!(deferred, Some((_, dst)) if *dst == opcode_ptr) {
+					*deferred = None;
 				}
+			});
+
+			unsafe {
+				std::ptr::copy_nonoverlapping(original.as_ptr(), opcode_ptr, original.len());
 			}
-			std::ptr::copy_nonoverlapping(original.as_ptr(), opcode_ptr, original.len());
 		}
-
-		map.remove(&PtrKey::new(opcode_ptr));
-	}
+	});
 
 	Ok(())
 }
@@ -1,6 +1,10 @@
 mod assemble_env;
 mod ckey_override;
+mod dap;
 mod instruction_hooking;
+mod mem_profiler;
+mod pprof;
+mod sampling_profiler;
 mod server;
 mod server_types;
 mod stddef;
@@ -8,33 +12,14 @@ mod stddef;
 #[cfg(windows)]
 mod crash_handler_windows;
 
-#[cfg(windows)]
-mod mem_profiler;
-
-#[cfg(not(windows))]
-mod mem_profiler_stub;
-
-use std::{
-	cell::UnsafeCell,
-	net::{IpAddr, Ipv4Addr, SocketAddr}
-};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 pub(crate) use ::instruction_hooking::disassemble_env::DisassembleEnv;
 use ::instruction_hooking::{InstructionHook, INSTRUCTION_HOOKS};
 use auxtools::*;
-#[cfg(not(windows))]
-use mem_profiler_stub as mem_profiler;
 
-pub static mut DEBUG_SERVER: UnsafeCell<Option<server::Server>> = UnsafeCell::new(None);
-
-#[shutdown]
-fn debugger_shutdown() {
-	// INSTRUCTION_HOOKS are cleared on shutdown so we don't need to worry about
-	// that.
-	unsafe {
-		DEBUG_SERVER.get_mut().take();
-	}
-}
+// Dropped when auxtools shuts down, along with INSTRUCTION_HOOKS
+pub static DEBUG_SERVER: ReloadableState<Option<server::Server>> = ReloadableState::new(|| None);
 
 fn get_default_mode() -> String {
 	match std::env::var("AUXTOOLS_DEBUG_MODE") {
@@ -50,15 +35,11 @@ fn get_default_port() -> u16 {
 	}
 }
 
-struct DebugServerInstructionHook<'a> {
-	debug_server: &'a mut UnsafeCell<Option<server::Server>>
-}
+struct DebugServerInstructionHook;
 
-impl InstructionHook for DebugServerInstructionHook<'static> {
+impl InstructionHook for DebugServerInstructionHook {
 	fn handle_instruction(&mut self, ctx: *mut raw_types::procs::ExecutionContext) {
-		if let Some(debug_server) = self.debug_server.get_mut() {
-			debug_server.handle_instruction(ctx);
-		}
+		crate::instruction_hooking::handle_instruction(ctx);
 	}
 }
 
@@ -84,20 +65,15 @@ fn enable_debugging(mode: Value, port: Value) {
 			server
 		}
 
+		"DAP" => server::Server::listen_dap(&addr).map_err(|e| runtime!("Couldn't create debug server: {}", e))?,
+
 		_ => {
 			return Err(runtime!("invalid debugging mode: {:?}", mode));
 		}
 	};
 
-	let debug_server_instruction_hook;
-	unsafe {
-		*DEBUG_SERVER.get() = Some(server);
-		debug_server_instruction_hook = DebugServerInstructionHook {
-			debug_server: &mut *std::ptr::addr_of_mut!(DEBUG_SERVER)
-		};
-
-		INSTRUCTION_HOOKS.get_mut().push(Box::new(debug_server_instruction_hook));
-	}
+	DEBUG_SERVER.set(Some(server));
+	INSTRUCTION_HOOKS.with(|hooks| hooks.push(Box::new(DebugServerInstructionHook)));
 
 	Ok(Value::NULL)
 }
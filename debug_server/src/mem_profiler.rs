@@ -1,21 +1,163 @@
+#[cfg(unix)]
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::{
 	cell::UnsafeCell,
-	collections::HashMap,
-	ffi::{c_void, CString},
+	cmp::Reverse,
+	collections::{HashMap, HashSet},
+	ffi::c_void,
 	fs::File,
-	io,
-	os::raw::c_char
+	io::{self, Write},
+	os::raw::c_char,
+	time::{SystemTime, UNIX_EPOCH}
 };
 
 use auxtools::{raw_types::procs::ProcId, *};
 use retour::RawDetour;
+use serde::Serialize;
+
+use crate::pprof;
+
+static mut THREAD_ID: usize = 0;
+
+// Set while we're inside of one of our hooks or otherwise using the state. The
+// profiler's own bookkeeping allocates, and on Linux those allocations come
+// back through the same hooks. Also set by `unrecorded`.
+static mut IN_HOOK: bool = false;
+
+// Runs `f` without recording anything it allocates or frees
+fn unrecorded<R, F: FnOnce() -> R>(f: F) -> R {
+	unsafe {
+		// Only the main thread is profiled, and only it may touch IN_HOOK
+		if THREAD_ID != current_thread_id() {
+			return f();
+		}
+
+		let was_in_hook = IN_HOOK;
+		IN_HOOK = true;
+		let result = f();
+		IN_HOOK = was_in_hook;
+		result
+	}
+}
+
+// On Linux the hooks replace the process-wide malloc, so everything this
+// library allocates would otherwise be charged to whichever proc is running.
+// Rust allocations are ignored by going through here, but allocations made by
+// other libraries loaded into the process (including other auxtools
+// libraries) still count towards the current proc. On Windows only the
+// runtime BYOND uses is hooked, and Rust allocates from the heap directly, so
+// there's nothing to ignore.
+#[cfg(unix)]
+struct OwnAllocator;
+
+#[cfg(unix)]
+#[global_allocator]
+static ALLOCATOR: OwnAllocator = OwnAllocator;
+
+#[cfg(unix)]
+unsafe impl GlobalAlloc for OwnAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		unrecorded(|| System.alloc(layout))
+	}
+
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		unrecorded(|| System.alloc_zeroed(layout))
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		unrecorded(|| System.realloc(ptr, layout, new_size))
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		unrecorded(|| System.dealloc(ptr, layout))
+	}
+}
 
-static mut THREAD_ID: u32 = 0;
 static MALLOC_SYMBOL: &[u8] = b"malloc\0";
 static REALLOC_SYMBOL: &[u8] = b"realloc\0";
 static FREE_SYMBOL: &[u8] = b"free\0";
+#[cfg(windows)]
 static NEW_SYMBOL: &[u8] = b"??2@YAPAXI@Z\0";
+#[cfg(windows)]
 static DELETE_SYMBOL: &[u8] = b"??3@YAXPAX@Z\0";
+#[cfg(windows)]
+static NEW_ARRAY_SYMBOL: &[u8] = b"??_U@YAPAXI@Z\0";
+#[cfg(windows)]
+static DELETE_ARRAY_SYMBOL: &[u8] = b"??_V@YAXPAX@Z\0";
+
+// operator new(unsigned int)
+#[cfg(unix)]
+static NEW_SYMBOL: &[u8] = b"_Znwj\0";
+// operator delete(void*)
+#[cfg(unix)]
+static DELETE_SYMBOL: &[u8] = b"_ZdlPv\0";
+// operator new[](unsigned int)
+#[cfg(unix)]
+static NEW_ARRAY_SYMBOL: &[u8] = b"_Znaj\0";
+// operator delete[](void*)
+#[cfg(unix)]
+static DELETE_ARRAY_SYMBOL: &[u8] = b"_ZdaPv\0";
+
+struct Symbols {
+	malloc: *const c_void,
+	realloc: *const c_void,
+	free: *const c_void,
+	new: *const c_void,
+	delete: *const c_void,
+	new_array: *const c_void,
+	delete_array: *const c_void
+}
+
+#[cfg(windows)]
+fn current_thread_id() -> usize {
+	unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() as usize }
+}
+
+#[cfg(unix)]
+fn current_thread_id() -> usize {
+	unsafe { libc::pthread_self() as usize }
+}
+
+// BYOND links against the MSVC 2013 runtime on Windows
+#[cfg(windows)]
+unsafe fn find_symbols() -> Option<Symbols> {
+	use winapi::um::libloaderapi;
+
+	let mut module = std::ptr::null_mut();
+	let module_path = std::ffi::CString::new("msvcr120.dll").unwrap();
+	if libloaderapi::GetModuleHandleExA(0, module_path.as_ptr(), &mut module) == 0 {
+		return None;
+	}
+
+	let find = |symbol: &[u8]| libloaderapi::GetProcAddress(module, symbol.as_ptr() as *const c_char) as *const c_void;
+
+	Some(Symbols {
+		malloc: find(MALLOC_SYMBOL),
+		realloc: find(REALLOC_SYMBOL),
+		free: find(FREE_SYMBOL),
+		new: find(NEW_SYMBOL),
+		delete: find(DELETE_SYMBOL),
+		new_array: find(NEW_ARRAY_SYMBOL),
+		delete_array: find(DELETE_ARRAY_SYMBOL)
+	})
+}
+
+// libbyond.so gets these from the system's libc and libstdc++, so we can look
+// them up the same way its relocations were resolved
+#[cfg(unix)]
+unsafe fn find_symbols() -> Option<Symbols> {
+	let find = |symbol: &[u8]| libc::dlsym(libc::RTLD_DEFAULT, symbol.as_ptr() as *const c_char) as *const c_void;
+
+	Some(Symbols {
+		malloc: find(MALLOC_SYMBOL),
+		realloc: find(REALLOC_SYMBOL),
+		free: find(FREE_SYMBOL),
+		new: find(NEW_SYMBOL),
+		delete: find(DELETE_SYMBOL),
+		new_array: find(NEW_ARRAY_SYMBOL),
+		delete_array: find(DELETE_ARRAY_SYMBOL)
+	})
+}
 
 fn setup_hooks() {
 	static mut DONE: bool = false;
@@ -28,24 +170,30 @@ fn setup_hooks() {
 		DONE = true;
 
 		{
-			use winapi::um::libloaderapi;
-
-			THREAD_ID = winapi::um::processthreadsapi::GetCurrentThreadId();
-
-			let mut module = std::ptr::null_mut();
-			let module_path = CString::new("msvcr120.dll").unwrap();
-			if libloaderapi::GetModuleHandleExA(0, module_path.as_ptr(), &mut module) == 0 {
-				return;
-			}
-
-			let malloc = libloaderapi::GetProcAddress(module, MALLOC_SYMBOL.as_ptr() as *const c_char);
-			let realloc = libloaderapi::GetProcAddress(module, REALLOC_SYMBOL.as_ptr() as *const c_char);
-			let free = libloaderapi::GetProcAddress(module, FREE_SYMBOL.as_ptr() as *const c_char);
-			let new = libloaderapi::GetProcAddress(module, NEW_SYMBOL.as_ptr() as *const c_char);
-			let delete = libloaderapi::GetProcAddress(module, DELETE_SYMBOL.as_ptr() as *const c_char);
+			THREAD_ID = current_thread_id();
+
+			let Symbols {
+				malloc,
+				realloc,
+				free,
+				new,
+				delete,
+				new_array,
+				delete_array
+			} = match find_symbols() {
+				Some(symbols) => symbols,
+				None => return
+			};
 
 			// ¯\_(ツ)_/¯
-			if malloc.is_null() || realloc.is_null() || free.is_null() || new.is_null() || delete.is_null() {
+			if malloc.is_null()
+				|| realloc.is_null()
+				|| free.is_null()
+				|| new.is_null()
+				|| delete.is_null()
+				|| new_array.is_null()
+				|| delete_array.is_null()
+			{
 				return;
 			}
 
@@ -88,6 +236,22 @@ fn setup_hooks() {
 				DELETE_ORIGINAL = Some(std::mem::transmute(hook.trampoline()));
 				std::mem::forget(hook);
 			}
+
+			{
+				let hook = RawDetour::new(new_array as _, new_array_hook as _).unwrap();
+
+				hook.enable().unwrap();
+				NEW_ARRAY_ORIGINAL = Some(std::mem::transmute(hook.trampoline()));
+				std::mem::forget(hook);
+			}
+
+			{
+				let hook = RawDetour::new(delete_array as _, delete_array_hook as _).unwrap();
+
+				hook.enable().unwrap();
+				DELETE_ARRAY_ORIGINAL = Some(std::mem::transmute(hook.trampoline()));
+				std::mem::forget(hook);
+			}
 		}
 	}
 }
@@ -99,16 +263,13 @@ static mut FREE_ORIGINAL: Option<extern "cdecl" fn(*mut c_void)> = None;
 static mut NEW_ORIGINAL: Option<extern "cdecl" fn(usize) -> *mut c_void> = None;
 static mut DELETE_ORIGINAL: Option<extern "cdecl" fn(*mut c_void)> = None;
 
+static mut NEW_ARRAY_ORIGINAL: Option<extern "cdecl" fn(usize) -> *mut c_void> = None;
+static mut DELETE_ARRAY_ORIGINAL: Option<extern "cdecl" fn(*mut c_void)> = None;
+
 extern "cdecl" fn malloc_hook(size: usize) -> *mut c_void {
 	let ptr = unsafe { (MALLOC_ORIGINAL.unwrap())(size) };
 
-	unsafe {
-		if THREAD_ID == winapi::um::processthreadsapi::GetCurrentThreadId() {
-			if let Some(state) = STATE.get_mut() {
-				state.allocate(ptr, size);
-			}
-		}
-	}
+	with_state(|state| state.allocate(ptr, size));
 
 	ptr
 }
@@ -116,28 +277,19 @@ extern "cdecl" fn malloc_hook(size: usize) -> *mut c_void {
 extern "cdecl" fn realloc_hook(ptr: *mut c_void, size: usize) -> *mut c_void {
 	let new_ptr = unsafe { (REALLOC_ORIGINAL.unwrap())(ptr, size) };
 
-	unsafe {
-		if THREAD_ID == winapi::um::processthreadsapi::GetCurrentThreadId() {
-			if let Some(state) = STATE.get_mut() {
-				state.free(ptr);
-				state.allocate(new_ptr, size);
-			}
-		}
-	}
+	with_state(|state| {
+		state.free(ptr);
+		state.allocate(new_ptr, size);
+	});
 
 	new_ptr
 }
 
 extern "cdecl" fn new_hook(size: usize) -> *mut c_void {
-	let ptr = unsafe { (NEW_ORIGINAL.unwrap())(size) };
+	// operator new gets its memory from malloc, which would record it as well
+	let ptr = unrecorded(|| unsafe { (NEW_ORIGINAL.unwrap())(size) });
 
-	unsafe {
-		if THREAD_ID == winapi::um::processthreadsapi::GetCurrentThreadId() {
-			if let Some(state) = STATE.get_mut() {
-				state.allocate(ptr, size);
-			}
-		}
-	}
+	with_state(|state| state.allocate(ptr, size));
 
 	ptr
 }
@@ -147,77 +299,192 @@ extern "cdecl" fn free_hook(ptr: *mut c_void) {
 		(FREE_ORIGINAL.unwrap())(ptr);
 	}
 
+	with_state(|state| state.free(ptr));
+}
+
+extern "cdecl" fn delete_hook(ptr: *mut c_void) {
+	// And operator delete frees it with free
+	unrecorded(|| unsafe { (DELETE_ORIGINAL.unwrap())(ptr) });
+
+	with_state(|state| state.free(ptr));
+}
+
+extern "cdecl" fn new_array_hook(size: usize) -> *mut c_void {
+	// operator new gets its memory from malloc, which would record it as well
+	let ptr = unrecorded(|| unsafe { (NEW_ARRAY_ORIGINAL.unwrap())(size) });
+
+	with_state(|state| state.allocate(ptr, size));
+
+	ptr
+}
+
+extern "cdecl" fn delete_array_hook(ptr: *mut c_void) {
+	unrecorded(|| unsafe { (DELETE_ARRAY_ORIGINAL.unwrap())(ptr) });
+
+	with_state(|state| state.free(ptr));
+}
+
+// Runs `f` with the profiler state if we're recording and on the main thread
+fn with_state<F: FnOnce(&mut State)>(f: F) {
 	unsafe {
-		if THREAD_ID == winapi::um::processthreadsapi::GetCurrentThreadId() {
-			if let Some(state) = STATE.get_mut() {
-				state.free(ptr);
-			}
+		if IN_HOOK || THREAD_ID != current_thread_id() {
+			return;
+		}
+
+		if let Some(state) = STATE.get_mut() {
+			IN_HOOK = true;
+			f(state);
+			IN_HOOK = false;
 		}
 	}
 }
 
-extern "cdecl" fn delete_hook(ptr: *mut c_void) {
-	unsafe {
-		(DELETE_ORIGINAL.unwrap())(ptr);
+static mut STATE: UnsafeCell<Option<State>> = UnsafeCell::new(None);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	Text,
+	Json,
+	Pprof
+}
+
+impl Format {
+	pub fn parse(name: &str) -> Option<Format> {
+		match name {
+			"text" => Some(Format::Text),
+			"json" => Some(Format::Json),
+			"pprof" => Some(Format::Pprof),
+			_ => None
+		}
 	}
 
-	unsafe {
-		if THREAD_ID == winapi::um::processthreadsapi::GetCurrentThreadId() {
-			if let Some(state) = STATE.get_mut() {
-				state.free(ptr);
-			}
+	fn from_path(path: &str) -> Format {
+		if path.ends_with(".json") {
+			Format::Json
+		} else if path.ends_with(".pb") || path.ends_with(".pprof") {
+			Format::Pprof
+		} else {
+			Format::Text
 		}
 	}
 }
 
-static mut STATE: UnsafeCell<Option<State>> = UnsafeCell::new(None);
+#[derive(Clone, Copy, Default)]
+struct ProcStats {
+	live_bytes: u64,
+	live_allocations: u64,
+	total_bytes: u64,
+	total_allocations: u64,
+	peak_bytes: u64
+}
+
+#[derive(Clone)]
+struct Snapshot {
+	taken_at: SystemTime,
+	procs: HashMap<ProcId, ProcStats>
+}
 
 struct State {
 	file: File,
-	live_allocs: HashMap<*const c_void, Allocation>
+	format: Format,
+	live_allocs: HashMap<*const c_void, Allocation>,
+	procs: HashMap<ProcId, ProcStats>,
+	started_at: SystemTime,
+	snapshots: Vec<(String, Snapshot)>
 }
 
 impl State {
-	fn new(dump_path: &str) -> io::Result<State> {
+	fn new(dump_path: &str, format: Option<Format>) -> io::Result<State> {
 		Ok(State {
 			file: File::create(dump_path)?,
-			live_allocs: HashMap::new()
+			format: format.unwrap_or_else(|| Format::from_path(dump_path)),
+			live_allocs: HashMap::new(),
+			procs: HashMap::new(),
+			started_at: SystemTime::now(),
+			snapshots: vec![]
 		})
 	}
 
 	fn allocate(&mut self, ptr: *const c_void, size: usize) {
 		if let Some(proc) = Self::current_proc_id() {
+			// realloc can hand back the same pointer
+			self.free(ptr);
 			self.live_allocs.insert(ptr, Allocation { proc, size });
+
+			let size = size as u64;
+			let stats = self.procs.entry(proc).or_default();
+			stats.live_bytes += size;
+			stats.live_allocations += 1;
+			stats.total_bytes += size;
+			stats.total_allocations += 1;
+			stats.peak_bytes = stats.peak_bytes.max(stats.live_bytes);
 		}
 	}
 
 	fn free(&mut self, ptr: *const c_void) {
-		self.live_allocs.remove(&ptr);
+		if let Some(Allocation { proc, size }) = self.live_allocs.remove(&ptr) {
+			if let Some(stats) = self.procs.get_mut(&proc) {
+				stats.live_bytes -= size as u64;
+				stats.live_allocations -= 1;
+			}
+		}
 	}
 
-	fn dump(mut self) {
-		use std::io::prelude::*;
-
-		let mut totals: Vec<(ProcId, u64)> = vec![];
+	fn snapshot(&self) -> Snapshot {
+		Snapshot {
+			taken_at: SystemTime::now(),
+			procs: self.procs.clone()
+		}
+	}
 
-		for (_ptr, Allocation { proc, size }) in self.live_allocs {
-			let proc_idx = proc.0 as usize;
+	fn find_snapshot(&self, name: &str) -> Option<&Snapshot> {
+		self.snapshots.iter().find(|(x, _)| x == name).map(|(_, snapshot)| snapshot)
+	}
 
-			if totals.len() <= proc_idx {
-				totals.resize(proc_idx + 1, (ProcId(0), 0));
+	fn dump(mut self) -> io::Result<()> {
+		let snapshot = self.snapshot();
+		let reports = snapshot.reports();
+
+		match self.format {
+			Format::Text => {
+				for report in &reports {
+					writeln!(
+						self.file,
+						"{} = {} (allocations: {}, peak: {}, total: {})",
+						report.path, report.live_bytes, report.live_allocations, report.peak_bytes, report.total_bytes
+					)?;
+				}
 			}
 
-			totals[proc_idx].0 = proc;
-			totals[proc_idx].1 += size as u64;
-		}
-
-		totals.sort_by(|x, y| x.1.cmp(&y.1));
+			Format::Json => {
+				serde_json::to_writer_pretty(&mut self.file, &reports)?;
+			}
 
-		for (proc, total) in totals {
-			if let Some(proc) = Proc::from_id(proc) {
-				writeln!(self.file, "{} = {}", proc.path, total).unwrap();
+			Format::Pprof => {
+				let mut profile = pprof::ProfileBuilder::new(&[
+					("alloc_objects", "count"),
+					("alloc_space", "bytes"),
+					("inuse_objects", "count"),
+					("inuse_space", "bytes")
+				]);
+
+				for report in &reports {
+					let location = profile.add_function(&report.path, report.file_name.as_deref().unwrap_or(""));
+					profile.add_sample(location, vec![
+						report.total_allocations as i64,
+						report.total_bytes as i64,
+						report.live_allocations as i64,
+						report.live_bytes as i64,
+					]);
+				}
+
+				let time_nanos = nanos_since_epoch(self.started_at);
+				let duration_nanos = nanos_since_epoch(snapshot.taken_at) - time_nanos;
+				self.file.write_all(&profile.encode(time_nanos, duration_nanos))?;
 			}
 		}
+
+		self.file.flush()
 	}
 
 	fn current_proc_id() -> Option<ProcId> {
@@ -237,29 +504,167 @@ impl State {
 	}
 }
 
+#[derive(Serialize)]
+struct ProcReport {
+	path: String,
+	file_name: Option<String>,
+	live_bytes: u64,
+	live_allocations: u64,
+	peak_bytes: u64,
+	total_bytes: u64,
+	total_allocations: u64
+}
+
+impl Snapshot {
+	// Sorted by live bytes, largest first
+	fn reports(&self) -> Vec<ProcReport> {
+		let mut reports: Vec<ProcReport> = self
+			.procs
+			.iter()
+			.filter_map(|(id, stats)| {
+				let proc = Proc::from_id(*id)?;
+				let file_name = unsafe { proc.file_name() }.map(|x| {
+					let quoted = x.to_string();
+					quoted[1..quoted.len() - 1].to_owned()
+				});
+
+				Some(ProcReport {
+					path: proc.path,
+					file_name,
+					live_bytes: stats.live_bytes,
+					live_allocations: stats.live_allocations,
+					peak_bytes: stats.peak_bytes,
+					total_bytes: stats.total_bytes,
+					total_allocations: stats.total_allocations
+				})
+			})
+			.collect();
+
+		reports.sort_by_key(|x| Reverse(x.live_bytes));
+		reports
+	}
+
+	// Per-proc change in live memory from `self` to `other`, biggest growth first
+	fn diff(&self, other: &Snapshot) -> Vec<(ProcId, i64, i64)> {
+		let mut diff: Vec<(ProcId, i64, i64)> = self
+			.procs
+			.keys()
+			.chain(other.procs.keys())
+			.collect::<HashSet<_>>()
+			.into_iter()
+			.map(|id| {
+				let before = self.procs.get(id).copied().unwrap_or_default();
+				let after = other.procs.get(id).copied().unwrap_or_default();
+
+				(
+					*id,
+					after.live_bytes as i64 - before.live_bytes as i64,
+					after.live_allocations as i64 - before.live_allocations as i64
+				)
+			})
+			.filter(|(_, bytes, count)| *bytes != 0 || *count != 0)
+			.collect();
+
+		diff.sort_by_key(|x| Reverse(x.1));
+		diff
+	}
+}
+
+fn nanos_since_epoch(time: SystemTime) -> i64 {
+	time.duration_since(UNIX_EPOCH).map(|x| x.as_nanos() as i64).unwrap_or(0)
+}
+
 struct Allocation {
 	proc: ProcId,
 	size: usize
 }
 
-pub fn begin(path: &str) -> io::Result<()> {
+// Only this many procs are listed in diff output
+const MAX_DIFF_ENTRIES: usize = 25;
+
+pub fn begin(path: &str, format: Option<Format>) -> io::Result<()> {
 	setup_hooks();
 
 	unsafe {
-		*STATE.get_mut() = Some(State::new(path)?);
+		*STATE.get_mut() = Some(State::new(path, format)?);
 	}
 
 	Ok(())
 }
 
-pub fn end() {
+pub fn end() -> io::Result<()> {
 	let state = unsafe { STATE.get_mut().take() };
 
-	if let Some(state) = state {
-		State::dump(state);
+	match state {
+		Some(state) => state.dump(),
+		None => Ok(())
+	}
+}
+
+// Like with_state, but for use outside of the allocation hooks
+fn with_state_paused<R, F: FnOnce(&mut State) -> Result<R, String>>(f: F) -> Result<R, String> {
+	unsafe {
+		let state = STATE.get_mut().as_mut().ok_or("memory profiler is not running")?;
+
+		IN_HOOK = true;
+		let result = f(state);
+		IN_HOOK = false;
+		result
 	}
 }
 
+pub fn snapshot(name: Option<&str>) -> Result<String, String> {
+	with_state_paused(|state| {
+		let name = match name {
+			Some(name) => name.to_owned(),
+			None => format!("{}", state.snapshots.len() + 1)
+		};
+
+		if state.find_snapshot(&name).is_some() {
+			return Err(format!("snapshot {} already exists", name));
+		}
+
+		let snapshot = state.snapshot();
+		let live_bytes: u64 = snapshot.procs.values().map(|x| x.live_bytes).sum();
+		let live_allocations: u64 = snapshot.procs.values().map(|x| x.live_allocations).sum();
+		state.snapshots.push((name.clone(), snapshot));
+
+		Ok(format!("Took snapshot {} ({} bytes live in {} allocations)", name, live_bytes, live_allocations))
+	})
+}
+
+/// Compares two snapshots by name. If `to` isn't given, `from` is compared
+/// against the current state.
+pub fn diff(from: &str, to: Option<&str>) -> Result<String, String> {
+	with_state_paused(|state| {
+		let from_snapshot = state.find_snapshot(from).ok_or_else(|| format!("no snapshot named {}", from))?;
+		let current;
+		let to_snapshot = match to {
+			Some(to) => state.find_snapshot(to).ok_or_else(|| format!("no snapshot named {}", to))?,
+			None => {
+				current = state.snapshot();
+				&current
+			}
+		};
+
+		let diff = from_snapshot.diff(to_snapshot);
+		let total: i64 = diff.iter().map(|x| x.1).sum();
+
+		let mut out = format!("{} -> {}: {:+} bytes\n", from, to.unwrap_or("now"), total);
+
+		for (id, bytes, count) in diff.iter().take(MAX_DIFF_ENTRIES) {
+			let path = Proc::from_id(*id).map(|x| x.path).unwrap_or_else(|| format!("<proc {}>", id.0));
+			out.push_str(&format!("{:+} bytes ({:+} allocations)\t{}\n", bytes, count, path));
+		}
+
+		if diff.len() > MAX_DIFF_ENTRIES {
+			out.push_str(&format!("... and {} more\n", diff.len() - MAX_DIFF_ENTRIES));
+		}
+
+		Ok(out)
+	})
+}
+
 #[shutdown]
 fn shutdown() {
 	unsafe {
@@ -267,3 +672,82 @@ fn shutdown() {
 		STATE.get_mut().take();
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use raw_types::procs::{ExecutionContext, ProcInstance};
+
+	use super::*;
+
+	static mut ARENA: [u64; 64] = [0; 64];
+	static mut ARENA_USED: usize = 0;
+
+	// Stand-ins for the C runtime, where operator new and delete go through
+	// malloc and free like they do in BYOND's
+	extern "cdecl" fn fake_malloc(size: usize) -> *mut c_void {
+		unsafe {
+			let ptr = std::ptr::addr_of_mut!(ARENA).cast::<u64>().add(ARENA_USED);
+			ARENA_USED += size.div_ceil(8);
+			ptr.cast()
+		}
+	}
+
+	extern "cdecl" fn fake_free(_ptr: *mut c_void) {}
+
+	extern "cdecl" fn fake_new(size: usize) -> *mut c_void {
+		malloc_hook(size)
+	}
+
+	extern "cdecl" fn fake_delete(ptr: *mut c_void) {
+		free_hook(ptr)
+	}
+
+	fn stats() -> ProcStats {
+		unsafe { STATE.get_mut().as_ref().unwrap().procs[&ProcId(7)] }
+	}
+
+	#[test]
+	fn new_and_delete() {
+		unsafe {
+			// Running in /proc 7
+			let mut instance: ProcInstance = std::mem::zeroed();
+			instance.proc = ProcId(7);
+			let mut ctx: ExecutionContext = std::mem::zeroed();
+			ctx.pre1668.proc_instance = &mut instance;
+			let mut current = std::ptr::addr_of_mut!(ctx);
+			raw_types::funcs::CURRENT_EXECUTION_CONTEXT = &mut current;
+
+			MALLOC_ORIGINAL = Some(fake_malloc);
+			FREE_ORIGINAL = Some(fake_free);
+			NEW_ORIGINAL = Some(fake_new);
+			DELETE_ORIGINAL = Some(fake_delete);
+			NEW_ARRAY_ORIGINAL = Some(fake_new);
+			DELETE_ARRAY_ORIGINAL = Some(fake_delete);
+
+			THREAD_ID = current_thread_id();
+			let path = std::env::temp_dir().join("auxtools_mem_profiler_test.txt");
+			*STATE.get_mut() = Some(State::new(path.to_str().unwrap(), None).unwrap());
+
+			// Each allocation counts once, even though malloc sees it too
+			let object = new_hook(24);
+			let array = new_array_hook(40);
+			let allocated = malloc_hook(8);
+			let stats_before = stats();
+			assert_eq!((stats_before.total_allocations, stats_before.total_bytes), (3, 72));
+			assert_eq!((stats_before.live_allocations, stats_before.live_bytes), (3, 72));
+
+			delete_hook(object);
+			delete_array_hook(array);
+			free_hook(allocated);
+			let stats_after = stats();
+			assert_eq!((stats_after.total_allocations, stats_after.total_bytes), (3, 72));
+			assert_eq!((stats_after.live_allocations, stats_after.live_bytes), (0, 0));
+			assert_eq!(stats_after.peak_bytes, 72);
+
+			STATE.get_mut().take();
+			THREAD_ID = 0;
+			raw_types::funcs::CURRENT_EXECUTION_CONTEXT = std::ptr::null_mut();
+			let _ = std::fs::remove_file(path);
+		}
+	}
+}
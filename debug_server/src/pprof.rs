@@ -0,0 +1,219 @@
+//! A minimal encoder for the pprof `Profile` protobuf message.
+//! See https://github.com/google/pprof/blob/main/proto/profile.proto
+//!
+//! Only the bits we need to describe flat per-proc profiles are implemented,
+//! which isn't enough to justify pulling in a protobuf code generator.
+
+use std::collections::HashMap;
+
+pub struct ProfileBuilder {
+	sample_types: Vec<(i64, i64)>,
+	samples: Vec<(u64, Vec<i64>)>,
+	functions: Vec<(i64, i64)>,
+	strings: Vec<String>,
+	string_indices: HashMap<String, i64>
+}
+
+impl ProfileBuilder {
+	/// `sample_types` is a list of (type, unit) pairs, like `("inuse_space",
+	/// "bytes")`. Every sample must provide one value for each.
+	pub fn new(sample_types: &[(&str, &str)]) -> Self {
+		let mut builder = Self {
+			sample_types: vec![],
+			samples: vec![],
+			functions: vec![],
+			strings: vec![],
+			string_indices: HashMap::new()
+		};
+
+		// The string table must start with an empty string
+		builder.string("");
+
+		for (ty, unit) in sample_types {
+			let ty = builder.string(ty);
+			let unit = builder.string(unit);
+			builder.sample_types.push((ty, unit));
+		}
+
+		builder
+	}
+
+	fn string(&mut self, value: &str) -> i64 {
+		if let Some(index) = self.string_indices.get(value) {
+			return *index;
+		}
+
+		let index = self.strings.len() as i64;
+		self.strings.push(value.to_owned());
+		self.string_indices.insert(value.to_owned(), index);
+		index
+	}
+
+	/// Adds a function with a single location and returns the location's id.
+	pub fn add_function(&mut self, name: &str, file_name: &str) -> u64 {
+		let name = self.string(name);
+		let file_name = self.string(file_name);
+		self.functions.push((name, file_name));
+
+		// Locations and functions share ids as we only ever have one of each per proc
+		self.functions.len() as u64
+	}
+
+	pub fn add_sample(&mut self, location: u64, values: Vec<i64>) {
+		debug_assert_eq!(values.len(), self.sample_types.len());
+		self.samples.push((location, values));
+	}
+
+	pub fn encode(&self, time_nanos: i64, duration_nanos: i64) -> Vec<u8> {
+		let mut out = vec![];
+
+		// sample_type = 1
+		for (ty, unit) in &self.sample_types {
+			let mut value_type = vec![];
+			write_varint_field(&mut value_type, 1, *ty as u64);
+			write_varint_field(&mut value_type, 2, *unit as u64);
+			write_bytes_field(&mut out, 1, &value_type);
+		}
+
+		// sample = 2
+		for (location, values) in &self.samples {
+			let mut sample = vec![];
+			write_packed_field(&mut sample, 1, std::iter::once(*location));
+			write_packed_field(&mut sample, 2, values.iter().map(|x| *x as u64));
+			write_bytes_field(&mut out, 2, &sample);
+		}
+
+		// location = 4
+		for id in 1..=self.functions.len() as u64 {
+			let mut line = vec![];
+			write_varint_field(&mut line, 1, id);
+
+			let mut location = vec![];
+			write_varint_field(&mut location, 1, id);
+			write_bytes_field(&mut location, 4, &line);
+			write_bytes_field(&mut out, 4, &location);
+		}
+
+		// function = 5
+		for (id, (name, file_name)) in self.functions.iter().enumerate() {
+			let mut function = vec![];
+			write_varint_field(&mut function, 1, id as u64 + 1);
+			write_varint_field(&mut function, 2, *name as u64);
+			write_varint_field(&mut function, 3, *name as u64);
+			write_varint_field(&mut function, 4, *file_name as u64);
+			write_bytes_field(&mut out, 5, &function);
+		}
+
+		// string_table = 6
+		for string in &self.strings {
+			write_bytes_field(&mut out, 6, string.as_bytes());
+		}
+
+		// time_nanos = 9, duration_nanos = 10
+		write_varint_field(&mut out, 9, time_nanos as u64);
+		write_varint_field(&mut out, 10, duration_nanos as u64);
+
+		out
+	}
+}
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+
+	out.push(value as u8);
+}
+
+fn write_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+	// Zero is the default and doesn't need to be encoded
+	if value == 0 {
+		return;
+	}
+
+	write_varint(out, (field << 3) | WIRE_VARINT);
+	write_varint(out, value);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+	write_varint(out, (field << 3) | WIRE_LENGTH_DELIMITED);
+	write_varint(out, bytes.len() as u64);
+	out.extend_from_slice(bytes);
+}
+
+fn write_packed_field<I: Iterator<Item = u64>>(out: &mut Vec<u8>, field: u64, values: I) {
+	let mut packed = vec![];
+	for value in values {
+		write_varint(&mut packed, value);
+	}
+
+	write_bytes_field(out, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn varints() {
+		let cases: &[(u64, &[u8])] = &[
+			(0, &[0x00]),
+			(1, &[0x01]),
+			(127, &[0x7f]),
+			(128, &[0x80, 0x01]),
+			(300, &[0xac, 0x02]),
+			(u64::MAX, &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01])
+		];
+
+		for (value, expected) in cases {
+			let mut out = vec![];
+			write_varint(&mut out, *value);
+			assert_eq!(out, *expected, "encoding {}", value);
+		}
+	}
+
+	#[test]
+	fn packed_fields() {
+		let mut out = vec![];
+		write_packed_field(&mut out, 2, [1, 300].into_iter());
+		assert_eq!(out, [0x12, 0x03, 0x01, 0xac, 0x02]);
+	}
+
+	#[test]
+	fn profile() {
+		let mut builder = ProfileBuilder::new(&[("alloc_space", "bytes")]);
+		let location = builder.add_function("/proc/foo", "code.dm");
+		builder.add_sample(location, vec![300]);
+
+		// Strings are added again as duplicates, so these don't change the table
+		builder.string("bytes");
+		builder.string("/proc/foo");
+
+		#[rustfmt::skip]
+		let expected: Vec<u8> = [
+			// sample_type { type: 1, unit: 2 }
+			&[0x0a, 0x04, 0x08, 0x01, 0x10, 0x02][..],
+			// sample { location_id: [1], value: [300] }
+			&[0x12, 0x07, 0x0a, 0x01, 0x01, 0x12, 0x02, 0xac, 0x02],
+			// location { id: 1, line { function_id: 1 } }
+			&[0x22, 0x06, 0x08, 0x01, 0x22, 0x02, 0x08, 0x01],
+			// function { id: 1, name: 3, system_name: 3, filename: 4 }
+			&[0x2a, 0x08, 0x08, 0x01, 0x10, 0x03, 0x18, 0x03, 0x20, 0x04],
+			// string_table, which has to start with ""
+			&[0x32, 0x00],
+			&[0x32, 0x0b], b"alloc_space",
+			&[0x32, 0x05], b"bytes",
+			&[0x32, 0x09], b"/proc/foo",
+			&[0x32, 0x07], b"code.dm",
+			// time_nanos, duration_nanos
+			&[0x48, 0x01, 0x50, 0x02]
+		]
+		.concat();
+
+		assert_eq!(builder.encode(1, 2), expected);
+	}
+}
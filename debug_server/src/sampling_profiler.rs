@@ -0,0 +1,242 @@
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{self, Write},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc
+	},
+	thread::{self, JoinHandle},
+	time::Duration
+};
+
+use auxtools::{raw_types::procs::ProcId, *};
+use instruction_hooking::{disassemble_env::DisassembleEnv, InstructionHook, INSTRUCTION_HOOKS};
+
+// Deeper stacks than this are truncated at the root end
+const MAX_STACK_DEPTH: usize = 256;
+
+pub const DEFAULT_INTERVAL_MS: u64 = 5;
+
+// Anything shorter has the sampling thread spinning a whole core
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Frame {
+	proc: ProcId,
+	offset: u16
+}
+
+struct Shared {
+	requested: AtomicBool,
+	stop: AtomicBool
+}
+
+// A thread that asks for a sample every interval. The stack is only ever read
+// on the main thread, by SamplingProfilerHook, as the contexts can be freed at
+// any moment while DM is running.
+struct Sampler {
+	shared: Arc<Shared>,
+	thread: Option<JoinHandle<()>>
+}
+
+impl Sampler {
+	fn spawn(interval: Duration) -> Sampler {
+		let shared = Arc::new(Shared {
+			requested: AtomicBool::new(false),
+			stop: AtomicBool::new(false)
+		});
+
+		let thread_shared = shared.clone();
+		let thread = thread::spawn(move || {
+			while !thread_shared.stop.load(Ordering::Relaxed) {
+				thread::sleep(interval);
+				thread_shared.requested.store(true, Ordering::Relaxed);
+			}
+		});
+
+		Sampler {
+			shared,
+			thread: Some(thread)
+		}
+	}
+
+	fn stop(&mut self) {
+		self.shared.stop.store(true, Ordering::Relaxed);
+
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+impl Drop for Sampler {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
+
+struct State {
+	file: File,
+	sampler: Sampler,
+	folded: HashMap<Vec<Frame>, u64>,
+	total_samples: u64
+}
+
+// Dropping the state stops the sampling thread, so it is reset when auxtools
+// shuts down
+static STATE: ReloadableState<Option<State>> = ReloadableState::new(|| None);
+
+impl State {
+	// Called on the main thread, with the instruction that's about to run
+	fn sample(&mut self, mut ctx: *mut raw_types::procs::ExecutionContext) {
+		// Leaf first, as that's the order we walk them in
+		let mut frames = vec![];
+
+		unsafe {
+			while !ctx.is_null() && frames.len() < MAX_STACK_DEPTH {
+				let instance = (*ctx).proc_instance();
+				if instance.is_null() {
+					break;
+				}
+
+				frames.push(Frame {
+					proc: (*instance).proc,
+					offset: (*ctx).bytecode_offset()
+				});
+
+				ctx = (*ctx).parent_context();
+			}
+		}
+
+		if frames.is_empty() {
+			return;
+		}
+
+		// Collapsed stacks are written root-first
+		frames.reverse();
+		*self.folded.entry(frames).or_insert(0) += 1;
+		self.total_samples += 1;
+	}
+
+	fn dump(mut self) -> io::Result<String> {
+		self.sampler.stop();
+
+		let mut lines = LineResolver::default();
+
+		let mut stacks: Vec<(String, u64)> = self
+			.folded
+			.iter()
+			.map(|(frames, count)| {
+				let names: Vec<String> = frames.iter().map(|frame| lines.frame_name(*frame)).collect();
+				(names.join(";"), *count)
+			})
+			.collect();
+
+		stacks.sort();
+
+		for (stack, count) in &stacks {
+			writeln!(self.file, "{} {}", stack, count)?;
+		}
+
+		self.file.flush()?;
+
+		Ok(format!(
+			"Sampling profiler disabled ({} samples, {} unique stacks)",
+			self.total_samples,
+			stacks.len()
+		))
+	}
+}
+
+#[derive(Default)]
+struct LineResolver {
+	// (offset, line) pairs in bytecode order
+	procs: HashMap<ProcId, Option<(String, Vec<(u32, u32)>)>>
+}
+
+impl LineResolver {
+	fn frame_name(&mut self, frame: Frame) -> String {
+		let entry = self.procs.entry(frame.proc).or_insert_with(|| {
+			let proc = Proc::from_id(frame.proc)?;
+
+			let bytecode = unsafe { proc.bytecode() };
+			let mut env = DisassembleEnv;
+			let (nodes, _error) = dmasm::disassembler::disassemble(bytecode, &mut env);
+
+			let lines = nodes
+				.into_iter()
+				.filter_map(|node| match node {
+					dmasm::Node::Instruction(dmasm::Instruction::DbgLine(line), debug) => Some((debug.offset, line)),
+					_ => None
+				})
+				.collect();
+
+			Some((proc.path, lines))
+		});
+
+		match entry {
+			Some((path, lines)) => {
+				let line = lines.iter().take_while(|(offset, _)| *offset <= frame.offset as u32).last();
+
+				match line {
+					Some((_, line)) => format!("{}:{}", path, line),
+					None => path.clone()
+				}
+			}
+
+			None => format!("<unknown proc {}>", frame.proc.0)
+		}
+	}
+}
+
+// Registered in INSTRUCTION_HOOKS while profiling. Takes a sample whenever
+// the sampling thread has asked for one.
+struct SamplingProfilerHook;
+
+impl InstructionHook for SamplingProfilerHook {
+	fn handle_instruction(&mut self, ctx: *mut raw_types::procs::ExecutionContext) {
+		STATE.with(|state| {
+			if let Some(state) = state {
+				if state.sampler.shared.requested.swap(false, Ordering::Relaxed) {
+					state.sample(ctx);
+				}
+			}
+		});
+	}
+}
+
+pub fn begin(path: &str, interval: Duration) -> io::Result<()> {
+	if interval < MIN_INTERVAL {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "interval must be at least 1ms"));
+	}
+
+	// Checked before creating the file so that we don't truncate the output of
+	// the profiler that's already running
+	if STATE.with(|state| state.is_some()) {
+		return Err(io::Error::other("sampling profiler is already running"));
+	}
+
+	let file = File::create(path)?;
+
+	STATE.set(Some(State {
+		file,
+		sampler: Sampler::spawn(interval),
+		folded: HashMap::new(),
+		total_samples: 0
+	}));
+
+	INSTRUCTION_HOOKS.with(|hooks| hooks.push(Box::new(SamplingProfilerHook)));
+
+	Ok(())
+}
+
+pub fn end() -> io::Result<String> {
+	INSTRUCTION_HOOKS.with(|hooks| hooks.retain_mut(|hook| !hook.as_any().is::<SamplingProfilerHook>()));
+	let state = STATE.replace(None);
+
+	match state {
+		Some(state) => state.dump(),
+		None => Ok("Sampling profiler is not running".to_owned())
+	}
+}
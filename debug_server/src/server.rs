@@ -2,11 +2,13 @@ use std::{
 	cell::RefCell,
 	collections::HashMap,
 	error::Error,
-	io::{Read, Write},
+	fs::{File, OpenOptions},
+	io::{BufWriter, Read, Write},
 	net::{SocketAddr, TcpListener, TcpStream},
 	sync::mpsc,
 	thread,
-	thread::JoinHandle
+	thread::JoinHandle,
+	time::Duration
 };
 
 use auxtools::{
@@ -20,7 +22,7 @@ use super::{
 	instruction_hooking::{get_hooked_offsets, hook_instruction, unhook_instruction},
 	server_types::*
 };
-use crate::mem_profiler;
+use crate::{dap, mem_profiler, sampling_profiler};
 
 #[derive(Clone, Hash, PartialEq, Eq)]
 enum Variables {
@@ -31,6 +33,18 @@ enum Variables {
 	ListPair { key: Value, value: Value }
 }
 
+// What a breakpoint does besides pausing every time it's hit
+#[derive(Clone)]
+struct BreakpointOptions {
+	condition: Option<String>,
+	// Logpoints send this message instead of pausing, with each {expression} in
+	// it replaced by its value
+	log_message: Option<String>,
+	hit_condition: Option<HitCondition>,
+	// How many times the breakpoint has been hit with its condition passing
+	hits: u32
+}
+
 struct State {
 	stacks: debug::CallStacks,
 	variables: RefCell<Vec<Variables>>,
@@ -71,18 +85,27 @@ impl State {
 // ServerThread = networking-thread code
 //
 // We've got a couple of channels going on between Server/ServerThread
-// connection: a TcpStream sent from the ServerThread for the Server to send
+// connection: a Connection sent from the ServerThread for the Server to send
 // responses on requests: requests from the debug-client for the Server to
 // handle
 //
+// In DAP mode the dap::Adapter thread takes the place of ServerThread
+//
 // Limitations: only ever accepts one connection
 //
 
+pub enum Connection {
+	Tcp(TcpStream),
+
+	// Responses go to the DAP adapter to be translated
+	Dap(dap::Responder)
+}
+
 enum ServerStream {
-	// The server is waiting for a Stream to be sent on the connection channel
-	Waiting(mpsc::Receiver<TcpStream>),
+	// The server is waiting for a Connection to be sent on the connection channel
+	Waiting(mpsc::Receiver<Connection>),
 
-	Connected(TcpStream),
+	Connected(Connection),
 
 	// The server has finished being used
 	Disconnected
@@ -94,12 +117,80 @@ pub struct Server {
 	_thread: JoinHandle<()>,
 	should_catch_runtimes: bool,
 	state: Option<State>,
-	in_eval: bool,
-	eval_error: Option<String>,
-	conditional_breakpoints: HashMap<(raw_types::procs::ProcId, u16), String>,
+	breakpoint_options: HashMap<(raw_types::procs::ProcId, u16), BreakpointOptions>,
+	// Where logpoint messages go instead of the debug client, if anywhere
+	logpoint_file: Option<File>,
+	source_lines: Option<SourceLines>,
 	app: Command<'static>
 }
 
+// file -> line -> the first instruction of that line in every proc it appears in
+type SourceLines = HashMap<String, HashMap<u32, Vec<InstructionRef>>>;
+
+// Set while eval_expr is running DM. The server is in use then, so the runtime
+// handler records errors here instead.
+#[derive(Default)]
+struct EvalState {
+	running: bool,
+	error: Option<String>
+}
+
+static EVAL_STATE: ReloadableState<EvalState> = ReloadableState::new(EvalState::default);
+
+/// Records a runtime hit by an expression the server is evaluating. Returns
+/// false if it isn't evaluating one.
+pub fn set_eval_error(err: &str) -> bool {
+	EVAL_STATE.with(|eval| {
+		if eval.running {
+			eval.error = Some(err.to_owned());
+		}
+		eval.running
+	})
+}
+
+// Replaces each {expression} in a logpoint's message with what `eval` returns
+// for it, or <no value> if it returns None. {{ and }} are literal braces, and a
+// { that's never closed is left as it is.
+fn format_log_message<F: FnMut(&str) -> Option<String>>(template: &str, mut eval: F) -> String {
+	let mut message = String::new();
+	let mut rest = template;
+
+	while let Some(index) = rest.find(['{', '}']) {
+		message.push_str(&rest[..index]);
+		let c = rest.as_bytes()[index] as char;
+		rest = &rest[index + 1..];
+
+		if let Some(after) = rest.strip_prefix(c) {
+			message.push(c);
+			rest = after;
+			continue;
+		}
+
+		if c == '}' {
+			message.push(c);
+			continue;
+		}
+
+		match rest.find('}') {
+			Some(end) => {
+				match eval(&rest[..end]) {
+					Some(value) => message.push_str(&value),
+					None => message.push_str("<no value>")
+				}
+				rest = &rest[end + 1..];
+			}
+
+			None => {
+				message.push(c);
+				break;
+			}
+		}
+	}
+
+	message.push_str(rest);
+	message
+}
+
 struct ServerThread {
 	requests: mpsc::Sender<Request>
 }
@@ -142,8 +233,59 @@ impl Server {
 						Command::new("begin")
 							.about("Begins memory profiling. Output goes to the specified file path")
 							.arg(Arg::new("path").help("Where to output memory profiler results").takes_value(true))
+							.arg(
+								Arg::new("format")
+									.help("Output format: text, json or pprof (defaults to the file extension)")
+									.takes_value(true)
+							)
 					)
 					.subcommand(Command::new("end").about("Finishes current memory profiler."))
+					.subcommand(
+						Command::new("snapshot")
+							.about("Records the current per-proc memory usage for later comparison")
+							.arg(Arg::new("name").help("Name of the snapshot (defaults to a number)").takes_value(true))
+					)
+					.subcommand(
+						Command::new("diff")
+							.about("Shows the change in per-proc memory usage between two snapshots")
+							.arg(Arg::new("from").help("Name of the older snapshot").takes_value(true))
+							.arg(
+								Arg::new("to")
+									.help("Name of the newer snapshot (defaults to the current memory usage)")
+									.takes_value(true)
+							)
+					)
+			)
+			.subcommand(
+				Command::new("sampling_profiler")
+					.about("Sampling profiler")
+					.subcommand(
+						Command::new("begin")
+							.about("Begins sampling the active call stack. Output goes to the specified file path in collapsed stack format")
+							.arg(Arg::new("path").help("Where to output sampling profiler results").takes_value(true))
+							.arg(
+								Arg::new("interval")
+									.help("Milliseconds between samples (defaults to 5)")
+									.takes_value(true)
+							)
+					)
+					.subcommand(Command::new("end").about("Finishes current sampling profiler."))
+			)
+			.subcommand(
+				Command::new("find_refs")
+					.about("Lists everything holding a reference to the given value")
+					.arg(Arg::new("ref").help("Reference to search for, like [0x2100000a]").takes_value(true))
+			)
+			.subcommand(
+				Command::new("heap_snapshot")
+					.about("Writes every live datum, list and string to a file that can be compared with heapdiff")
+					.arg(Arg::new("path").help("Where to write the snapshot").takes_value(true))
+			)
+			.subcommand(
+				Command::new("logpoints")
+					.about("Appends logpoint messages to a file instead of sending them to the debug console")
+					.after_help("If no path is provided, logpoint messages go back to the debug console")
+					.arg(Arg::new("path").help("File to append logpoint messages to").takes_value(true))
 			)
 	}
 
@@ -158,17 +300,7 @@ impl Server {
 			server_thread.run(cloned_stream);
 		});
 
-		let mut server = Server {
-			requests: requests_receiver,
-			stream: ServerStream::Connected(stream),
-			_thread: thread,
-			should_catch_runtimes: true,
-			state: None,
-			in_eval: false,
-			eval_error: None,
-			conditional_breakpoints: HashMap::new(),
-			app: Self::setup_app()
-		};
+		let mut server = Self::new(requests_receiver, ServerStream::Connected(Connection::Tcp(stream)), thread);
 
 		server.process_until_configured();
 		Ok(server)
@@ -180,25 +312,31 @@ impl Server {
 
 		let thread = ServerThread { requests: requests_sender }.spawn_listener(TcpListener::bind(addr)?, connection_sender);
 
-		Ok(Server {
-			requests: requests_receiver,
-			stream: ServerStream::Waiting(connection_receiver),
-			_thread: thread,
-			should_catch_runtimes: true,
-			state: None,
-			in_eval: false,
-			eval_error: None,
-			conditional_breakpoints: HashMap::new(),
-			app: Self::setup_app()
-		})
+		Ok(Self::new(requests_receiver, ServerStream::Waiting(connection_receiver), thread))
 	}
 
-	pub const fn is_in_eval(&self) -> bool {
-		self.in_eval
+	/// Like `listen`, but the client speaks the Debug Adapter Protocol
+	pub fn listen_dap(addr: &SocketAddr) -> std::io::Result<Server> {
+		let (connection_sender, connection_receiver) = mpsc::channel();
+		let (requests_sender, requests_receiver) = mpsc::channel();
+
+		let thread = dap::spawn_listener(TcpListener::bind(addr)?, requests_sender, connection_sender);
+
+		Ok(Self::new(requests_receiver, ServerStream::Waiting(connection_receiver), thread))
 	}
 
-	pub fn set_eval_error(&mut self, err: String) {
-		self.eval_error = Some(err);
+	fn new(requests: mpsc::Receiver<Request>, stream: ServerStream, thread: JoinHandle<()>) -> Server {
+		Server {
+			requests,
+			stream,
+			_thread: thread,
+			should_catch_runtimes: true,
+			state: None,
+			breakpoint_options: HashMap::new(),
+			logpoint_file: None,
+			source_lines: None,
+			app: Self::setup_app()
+		}
 	}
 
 	fn get_line_number(&self, proc: ProcRef, offset: u32) -> Option<u32> {
@@ -269,6 +407,76 @@ impl Server {
 		offset
 	}
 
+	// Disassembles every proc in the world, so this is done once, the first time
+	// a client asks for breakpoints by line. The world is stalled until it's
+	// done, which can take a few seconds on large codebases.
+	fn index_source_lines() -> SourceLines {
+		let mut source_lines = SourceLines::new();
+		let mut id = 0;
+
+		while let Some(proc) = auxtools::Proc::from_id(raw_types::procs::ProcId(id)) {
+			id += 1;
+
+			let proc_ref = ProcRef {
+				path: proc.path.clone(),
+				override_id: proc.override_id()
+			};
+
+			let bytecode = unsafe { proc.bytecode() };
+
+			let mut env = disassemble_env::DisassembleEnv;
+			let (nodes, _error) = dmasm::disassembler::disassemble(bytecode, &mut env);
+
+			let mut file: Option<String> = None;
+			let mut line = None;
+
+			for node in nodes {
+				if let dmasm::Node::Instruction(ins, debug) = node {
+					// Same as get_offset, the instruction after the DbgLine is where the line starts
+					if let (Some(file), Some(line)) = (&file, line.take()) {
+						let instructions = source_lines.entry(file.clone()).or_default().entry(line).or_default();
+
+						if !instructions.iter().any(|x| x.proc == proc_ref) {
+							instructions.push(InstructionRef {
+								proc: proc_ref.clone(),
+								offset: debug.offset
+							});
+						}
+					}
+
+					match ins {
+						dmasm::Instruction::DbgFile(name) => file = Some(String::from_utf8_lossy(&name.0).into_owned()),
+						dmasm::Instruction::DbgLine(current_line) => line = Some(current_line),
+						_ => {}
+					}
+				}
+			}
+		}
+
+		source_lines
+	}
+
+	// The client's path is probably absolute, while the compiler's are relative to
+	// the .dme
+	fn is_same_file(client_path: &str, file: &str) -> bool {
+		let client_path = client_path.replace('\\', "/").to_ascii_lowercase();
+		let file = file.replace('\\', "/").to_ascii_lowercase();
+
+		client_path == file || client_path.ends_with(&format!("/{}", file))
+	}
+
+	fn get_line_instructions(&mut self, file: &str, line: u32) -> Vec<InstructionRef> {
+		let source_lines = self.source_lines.get_or_insert_with(Self::index_source_lines);
+
+		source_lines
+			.iter()
+			.filter(|(name, _)| Self::is_same_file(file, name))
+			.filter_map(|(_, lines)| lines.get(&line))
+			.flatten()
+			.cloned()
+			.collect()
+	}
+
 	fn is_object(value: &Value) -> bool {
 		// Hack for globals
 		if value.raw.tag == ValueTag::World && unsafe { value.raw.data.id == 1 } {
@@ -487,7 +695,13 @@ impl Server {
 		}
 	}
 
-	fn handle_breakpoint_set(&mut self, instruction: InstructionRef, condition: Option<String>) {
+	fn handle_breakpoint_set(
+		&mut self,
+		instruction: InstructionRef,
+		condition: Option<String>,
+		log_message: Option<String>,
+		hit_condition: Option<HitCondition>
+	) {
 		let line = self.get_line_number(instruction.proc.clone(), instruction.offset);
 
 		let proc = match auxtools::Proc::find_override(instruction.proc.path, instruction.proc.override_id) {
@@ -502,8 +716,20 @@ impl Server {
 
 		match hook_instruction(&proc, instruction.offset) {
 			Ok(()) => {
-				if let Some(condition) = condition {
-					self.conditional_breakpoints.insert((proc.id, instruction.offset as u16), condition);
+				let key = (proc.id, instruction.offset as u16);
+
+				if condition.is_some() || log_message.is_some() || hit_condition.is_some() {
+					self.breakpoint_options.insert(
+						key,
+						BreakpointOptions {
+							condition,
+							log_message,
+							hit_condition,
+							hits: 0
+						}
+					);
+				} else {
+					self.breakpoint_options.remove(&key);
 				}
 
 				self.send_or_disconnect(Response::BreakpointSet {
@@ -530,7 +756,7 @@ impl Server {
 			}
 		};
 
-		self.conditional_breakpoints.remove(&(proc.id, instruction.offset as u16));
+		self.breakpoint_options.remove(&(proc.id, instruction.offset as u16));
 
 		match unhook_instruction(&proc, instruction.offset) {
 			Ok(()) => {
@@ -731,20 +957,102 @@ impl Server {
 					},
 
 					Some(("mem_profiler", matches)) => match matches.subcommand() {
-						Some(("begin", matches)) => match matches.value_of("path") {
-							Some(path) => mem_profiler::begin(path)
+						Some(("begin", matches)) => match (matches.value_of("path"), matches.value_of("format")) {
+							(Some(path), None) => mem_profiler::begin(path, None)
 								.map(|_| "Memory profiler enabled".to_owned())
 								.unwrap_or_else(|e| format!("Failed: {}", e)),
 
+							(Some(path), Some(format)) => match mem_profiler::Format::parse(format) {
+								Some(format) => mem_profiler::begin(path, Some(format))
+									.map(|_| "Memory profiler enabled".to_owned())
+									.unwrap_or_else(|e| format!("Failed: {}", e)),
+
+								None => format!("unknown format: {}", format)
+							},
+
+							(None, _) => "no path provided".to_owned()
+						},
+
+						Some(("end", _)) => match mem_profiler::end() {
+							Ok(()) => "Memory profiler disabled".to_owned(),
+							Err(e) => format!("Memory profiler disabled, but writing results failed: {}", e)
+						},
+
+						Some(("snapshot", matches)) => mem_profiler::snapshot(matches.value_of("name")).unwrap_or_else(|e| format!("Failed: {}", e)),
+
+						Some(("diff", matches)) => match matches.value_of("from") {
+							Some(from) => mem_profiler::diff(from, matches.value_of("to")).unwrap_or_else(|e| format!("Failed: {}", e)),
+
+							None => "no snapshot provided".to_owned()
+						},
+
+						_ => "unknown memory profiler sub-command".to_owned()
+					},
+
+					Some(("sampling_profiler", matches)) => match matches.subcommand() {
+						Some(("begin", matches)) => match matches.value_of("path") {
+							Some(path) => {
+								let interval = matches
+									.value_of("interval")
+									.and_then(|x| x.parse::<u64>().ok())
+									.unwrap_or(sampling_profiler::DEFAULT_INTERVAL_MS);
+
+								sampling_profiler::begin(path, Duration::from_millis(interval))
+									.map(|_| "Sampling profiler enabled".to_owned())
+									.unwrap_or_else(|e| format!("Failed: {}", e))
+							}
+
 							None => "no path provided".to_owned()
 						},
 
-						Some(("end", _)) => {
-							mem_profiler::end();
-							"Memory profiler disabled".to_owned()
+						Some(("end", _)) => sampling_profiler::end().unwrap_or_else(|e| format!("Failed: {}", e)),
+
+						_ => "unknown sampling profiler sub-command".to_owned()
+					},
+
+					Some(("find_refs", matches)) => match matches.value_of("ref") {
+						Some(reference) => match refs::parse_ref(reference) {
+							Some(target) => {
+								let found = refs::find_references(&target);
+								if found.is_empty() {
+									"no references found".to_owned()
+								} else {
+									found.join("\n")
+								}
+							}
+
+							None => format!("invalid reference: {}", reference)
+						},
+
+						None => "no reference provided".to_owned()
+					},
+
+					Some(("heap_snapshot", matches)) => match matches.value_of("path") {
+						Some(path) => {
+							let snapshot = heap::snapshot();
+							match File::create(path).and_then(|file| snapshot.write(BufWriter::new(file))) {
+								Ok(()) => format!("Wrote {} objects ({} bytes) to {}", snapshot.len(), snapshot.total_size(), path),
+								Err(e) => format!("Failed: {}", e)
+							}
 						}
 
-						_ => "unknown memory profiler sub-command".to_owned()
+						None => "no path provided".to_owned()
+					},
+
+					Some(("logpoints", matches)) => match matches.value_of("path") {
+						Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+							Ok(file) => {
+								self.logpoint_file = Some(file);
+								format!("Writing logpoint messages to {}", path)
+							}
+
+							Err(e) => format!("Failed: {}", e)
+						},
+
+						None => {
+							self.logpoint_file = None;
+							"Sending logpoint messages to the debug console".to_owned()
+						}
 					},
 
 					_ => "unknown command".to_owned()
@@ -827,8 +1135,7 @@ impl Server {
 
 		proc.set_bytecode(assembly);
 
-		self.in_eval = true;
-		self.eval_error = None;
+		EVAL_STATE.set(EvalState { running: true, error: None });
 
 		let result = match proc.call(&arg_values) {
 			Ok(res) => {
@@ -885,9 +1192,7 @@ impl Server {
 			}
 		};
 
-		self.in_eval = false;
-
-		if let Some(err) = self.eval_error.take() {
+		if let Some(err) = EVAL_STATE.replace(EvalState::default()).error {
 			self.notify(format!("runtime occured when executing expression: {}", err));
 		}
 
@@ -966,7 +1271,13 @@ impl Server {
 		match request {
 			Request::Disconnect => unreachable!(),
 			Request::CatchRuntimes { should_catch } => self.should_catch_runtimes = should_catch,
-			Request::BreakpointSet { instruction, condition } => self.handle_breakpoint_set(instruction, condition),
+			Request::BreakpointSet { instruction, condition } => self.handle_breakpoint_set(instruction, condition, None, None),
+			Request::BreakpointSetWithOptions {
+				instruction,
+				condition,
+				log_message,
+				hit_condition
+			} => self.handle_breakpoint_set(instruction, condition, log_message, hit_condition),
 			Request::BreakpointUnset { instruction } => self.handle_breakpoint_unset(instruction),
 			Request::Stacks => self.handle_stacks(),
 			Request::Scopes { frame_id } => self.handle_scopes(frame_id),
@@ -1001,6 +1312,19 @@ impl Server {
 				self.send_or_disconnect(Response::StdDef(stddef));
 			}
 
+			Request::LineInstructions { file, line } => {
+				let instructions = self.get_line_instructions(&file, line);
+				self.send_or_disconnect(Response::LineInstructions { instructions });
+			}
+
+			Request::ProcFile { proc } => {
+				let file = auxtools::Proc::find_override(proc.path, proc.override_id)
+					.and_then(|proc| unsafe { proc.file_name() })
+					.map(|file| String::from(&file));
+
+				self.send_or_disconnect(Response::ProcFile { file });
+			}
+
 			Request::CurrentInstruction { frame_id } => {
 				let response = self.get_stack_frame(frame_id).map(|frame| InstructionRef {
 					proc: ProcRef {
@@ -1064,24 +1388,9 @@ impl Server {
 
 		self.state = Some(State::new());
 
-		// Exit now if this is a conditional breakpoint and the condition doesn't pass!
-		if reason == BreakpointReason::Breakpoint {
-			let proc = unsafe { (*(*_ctx).proc_instance()).proc };
-			let offset = unsafe { (*_ctx).bytecode_offset() };
-			let condition = self.conditional_breakpoints.get(&(proc, offset)).cloned();
-
-			if let Some(condition) = condition {
-				if let Some(result) = self.eval_expr(Some(0), &condition) {
-					if !result.is_truthy() {
-						self.state = None;
-						return ContinueKind::Continue;
-					}
-				}
-
-				// We might have just executed some code so invalidate the stacks we already
-				// fetched
-				self.state.as_mut().unwrap().invalidate_stacks();
-			}
+		if reason == BreakpointReason::Breakpoint && !self.breakpoint_should_pause(_ctx) {
+			self.state = None;
+			return ContinueKind::Continue;
 		}
 
 		self.notify(format!("Pausing execution (reason: {:?})", reason));
@@ -1111,6 +1420,82 @@ impl Server {
 		ContinueKind::Continue
 	}
 
+	// Checks the condition and hit condition of the breakpoint `ctx` is on, and
+	// logs its message if it's a logpoint. Returns true if it should pause.
+	fn breakpoint_should_pause(&mut self, ctx: *mut raw_types::procs::ExecutionContext) -> bool {
+		let proc = unsafe { (*(*ctx).proc_instance()).proc };
+		let offset = unsafe { (*ctx).bytecode_offset() };
+		let key = (proc, offset);
+		let options = self.breakpoint_options.get(&key).cloned();
+
+		// Exit now if this is a conditional breakpoint and the condition doesn't pass!
+		if let Some(condition) = options.as_ref().and_then(|x| x.condition.as_ref()) {
+			if let Some(result) = self.eval_expr(Some(0), condition) {
+				if !result.is_truthy() {
+					return false;
+				}
+			}
+
+			// We might have just executed some code so invalidate the stacks we already
+			// fetched
+			self.state.as_mut().unwrap().invalidate_stacks();
+		}
+
+		if let Some(options) = self.breakpoint_options.get_mut(&key) {
+			options.hits = options.hits.saturating_add(1);
+
+			if options.hit_condition.is_some_and(|x| !x.matches(options.hits)) {
+				return false;
+			}
+		}
+
+		// Logpoints never pause
+		if let Some(log_message) = options.as_ref().and_then(|x| x.log_message.as_ref()) {
+			let message = format_log_message(log_message, |expression| {
+				let value = self.eval_expr(Some(0), expression);
+				self.state.as_mut().unwrap().invalidate_stacks();
+				value.map(|value| Self::stringify(&value))
+			});
+			self.log(message);
+			return false;
+		}
+
+		true
+	}
+
+	/// Called when a step pauses on an instruction that has a breakpoint, which
+	/// the step has already paused for. Logpoints are still logged.
+	pub fn handle_stepped_breakpoint(&mut self, ctx: *mut raw_types::procs::ExecutionContext) {
+		if !self.check_connected() {
+			return;
+		}
+
+		let proc = unsafe { (*(*ctx).proc_instance()).proc };
+		let offset = unsafe { (*ctx).bytecode_offset() };
+		let is_logpoint = self.breakpoint_options.get(&(proc, offset)).is_some_and(|x| x.log_message.is_some());
+
+		if is_logpoint {
+			self.state = Some(State::new());
+			self.breakpoint_should_pause(ctx);
+			self.state = None;
+		}
+	}
+
+	fn log(&mut self, message: String) {
+		if let Some(file) = &mut self.logpoint_file {
+			match writeln!(file, "{}", message) {
+				Ok(()) => return,
+				Err(e) => {
+					self.logpoint_file = None;
+					self.notify(format!("failed to write logpoint, sending them here instead: {}", e));
+				}
+			}
+		}
+
+		// Not notify, as that'd also print every message to stderr
+		self.send_or_disconnect(Response::Notification { message });
+	}
+
 	// returns true if we need to pause
 	pub fn process(&mut self) -> bool {
 		// Don't do anything until we're connected
@@ -1159,28 +1544,41 @@ impl Server {
 	}
 
 	fn disconnect(&mut self) {
-		if let ServerStream::Connected(stream) = &mut self.stream {
-			eprintln!("Debug server disconnecting");
-			let data = bincode::serialize(&Response::Disconnect).unwrap();
-			let _ = stream.write_all(&(data.len() as u32).to_le_bytes());
-			let _ = stream.write_all(&data[..]);
-			let _ = stream.flush();
-			let _ = stream.shutdown(std::net::Shutdown::Both);
+		match &mut self.stream {
+			ServerStream::Connected(Connection::Tcp(stream)) => {
+				eprintln!("Debug server disconnecting");
+				let data = bincode::serialize(&Response::Disconnect).unwrap();
+				let _ = stream.write_all(&(data.len() as u32).to_le_bytes());
+				let _ = stream.write_all(&data[..]);
+				let _ = stream.flush();
+				let _ = stream.shutdown(std::net::Shutdown::Both);
+			}
+
+			ServerStream::Connected(Connection::Dap(responder)) => {
+				eprintln!("Debug server disconnecting");
+				let _ = responder.send(Response::Disconnect);
+			}
+
+			ServerStream::Waiting(_) | ServerStream::Disconnected => {}
 		}
 
 		self.stream = ServerStream::Disconnected;
 	}
 
 	fn send(&mut self, response: Response) -> Result<(), Box<dyn std::error::Error>> {
-		if let ServerStream::Connected(stream) = &mut self.stream {
-			let data = bincode::serialize(&response)?;
-			stream.write_all(&(data.len() as u32).to_le_bytes())?;
-			stream.write_all(&data[..])?;
-			stream.flush()?;
-			return Ok(());
-		}
+		match &mut self.stream {
+			ServerStream::Connected(Connection::Tcp(stream)) => {
+				let data = bincode::serialize(&response)?;
+				stream.write_all(&(data.len() as u32).to_le_bytes())?;
+				stream.write_all(&data[..])?;
+				stream.flush()?;
+				Ok(())
+			}
+
+			ServerStream::Connected(Connection::Dap(responder)) => responder.send(response),
 
-		unreachable!();
+			ServerStream::Waiting(_) | ServerStream::Disconnected => unreachable!()
+		}
 	}
 }
 
@@ -1191,10 +1589,10 @@ impl Drop for Server {
 }
 
 impl ServerThread {
-	fn spawn_listener(self, listener: TcpListener, connection_sender: mpsc::Sender<TcpStream>) -> JoinHandle<()> {
+	fn spawn_listener(self, listener: TcpListener, connection_sender: mpsc::Sender<Connection>) -> JoinHandle<()> {
 		thread::spawn(move || match listener.accept() {
 			Ok((stream, _)) => {
-				match connection_sender.send(stream.try_clone().unwrap()) {
+				match connection_sender.send(Connection::Tcp(stream.try_clone().unwrap())) {
 					Ok(_) => {}
 					Err(e) => {
 						eprintln!("Debug server thread failed to pass cloned TcpStream: {}", e);
@@ -1266,3 +1664,37 @@ impl ServerThread {
 		eprintln!("Debug server thread finished");
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn format(template: &str) -> (String, Vec<String>) {
+		let mut expressions = vec![];
+		let message = format_log_message(template, |expression| {
+			expressions.push(expression.to_owned());
+			match expression {
+				"missing" => None,
+				expression => Some(expression.to_uppercase())
+			}
+		});
+		(message, expressions)
+	}
+
+	#[test]
+	fn log_messages() {
+		assert_eq!(
+			format("x is {x}, y is {y}"),
+			("x is X, y is Y".to_owned(), vec!["x".to_owned(), "y".to_owned()])
+		);
+		assert_eq!(format("{{x}} {{{x}}}").0, "{x} {X}");
+		assert_eq!(format("a } b").0, "a } b");
+		assert_eq!(format("{missing}!").0, "<no value>!");
+		assert_eq!(format("{}").1, [""]);
+		assert_eq!(format("ünïcode {x} ✓").0, "ünïcode X ✓");
+
+		// Nothing is evaluated for a { that isn't closed
+		assert_eq!(format("x is {x"), ("x is {x".to_owned(), vec![]));
+		assert_eq!(format("{x} {").0, "X {");
+	}
+}
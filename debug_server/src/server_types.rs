@@ -52,7 +52,26 @@ pub enum Request {
 	Continue {
 		kind: ContinueKind
 	},
-	Pause
+	Pause,
+
+	// Used by the DAP adapter, which only knows about source files
+	LineInstructions {
+		file: String,
+		line: u32
+	},
+	ProcFile {
+		proc: ProcRef
+	},
+
+	// Added after the others so that their bincode encoding doesn't change.
+	// Answered with a Response::BreakpointSet, like BreakpointSet.
+	BreakpointSetWithOptions {
+		instruction: InstructionRef,
+		condition: Option<String>,
+		// Makes this a logpoint, see Server::format_log_message
+		log_message: Option<String>,
+		hit_condition: Option<HitCondition>
+	}
 }
 
 // Message from server -> client
@@ -98,6 +117,14 @@ pub enum Response {
 	},
 	BreakpointHit {
 		reason: BreakpointReason
+	},
+
+	// Added after the others so that their bincode encoding doesn't change
+	LineInstructions {
+		instructions: Vec<InstructionRef>
+	},
+	ProcFile {
+		file: Option<String>
 	}
 }
 
@@ -121,6 +148,44 @@ pub enum BreakpointReason {
 	Runtime(String)
 }
 
+// Which hits of a breakpoint it takes effect on, counting from 1
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitCondition {
+	Equal(u32),
+	AtLeast(u32),
+	Multiple(u32)
+}
+
+impl HitCondition {
+	/// Parses `== N`, `>= N`, `% N` or just `N` (which is the same as `== N`)
+	pub fn parse(text: &str) -> Option<Self> {
+		let text = text.trim();
+
+		let (condition, count): (fn(u32) -> Self, &str) = if let Some(count) = text.strip_prefix("==") {
+			(Self::Equal, count)
+		} else if let Some(count) = text.strip_prefix(">=") {
+			(Self::AtLeast, count)
+		} else if let Some(count) = text.strip_prefix('%') {
+			(Self::Multiple, count)
+		} else {
+			(Self::Equal, text)
+		};
+
+		match count.trim().parse::<u32>() {
+			Ok(0) | Err(_) => None,
+			Ok(count) => Some(condition(count))
+		}
+	}
+
+	pub const fn matches(self, hits: u32) -> bool {
+		match self {
+			Self::Equal(count) => hits == count,
+			Self::AtLeast(count) => hits >= count,
+			Self::Multiple(count) => count != 0 && hits % count == 0
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum ContinueKind {
 	Continue,
@@ -163,3 +228,105 @@ pub struct EvalResponse {
 	pub value: String,
 	pub variables: Option<VariablesRef>
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A BreakpointSet request from a client built before breakpoints had options
+	const OLD_BREAKPOINT_SET: &[u8] = &[
+		0x05, 0x00, 0x00, 0x00, // variant
+		0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, b'/', b'f', b'o', b'o', // proc path
+		0x01, 0x00, 0x00, 0x00, // override id
+		0x0A, 0x00, 0x00, 0x00, // offset
+		// condition
+		0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, b'x', b'>', b'1'
+	];
+
+	fn instruction() -> InstructionRef {
+		InstructionRef {
+			proc: ProcRef {
+				path: "/foo".to_owned(),
+				override_id: 1
+			},
+			offset: 10
+		}
+	}
+
+	#[test]
+	fn old_breakpoint_set() {
+		match bincode::deserialize::<Request>(OLD_BREAKPOINT_SET).unwrap() {
+			Request::BreakpointSet { instruction: x, condition } => {
+				assert_eq!(x, instruction());
+				assert_eq!(condition.as_deref(), Some("x>1"));
+			}
+			request => panic!("decoded as {:?}", request)
+		}
+
+		let request = Request::BreakpointSet {
+			instruction: instruction(),
+			condition: Some("x>1".to_owned())
+		};
+		assert_eq!(bincode::serialize(&request).unwrap(), OLD_BREAKPOINT_SET);
+	}
+
+	#[test]
+	fn breakpoint_set_with_options() {
+		let request = Request::BreakpointSetWithOptions {
+			instruction: instruction(),
+			condition: None,
+			log_message: Some("hit {x}".to_owned()),
+			hit_condition: Some(HitCondition::AtLeast(3))
+		};
+
+		// After every variant older clients know about
+		let data = bincode::serialize(&request).unwrap();
+		assert_eq!(data[..4], [18, 0, 0, 0]);
+
+		match bincode::deserialize::<Request>(&data).unwrap() {
+			Request::BreakpointSetWithOptions {
+				instruction: x,
+				condition,
+				log_message,
+				hit_condition
+			} => {
+				assert_eq!(x, instruction());
+				assert_eq!(condition, None);
+				assert_eq!(log_message.as_deref(), Some("hit {x}"));
+				assert_eq!(hit_condition, Some(HitCondition::AtLeast(3)));
+			}
+			request => panic!("decoded as {:?}", request)
+		}
+	}
+
+	#[test]
+	fn hit_condition_parse() {
+		assert_eq!(HitCondition::parse("== 3"), Some(HitCondition::Equal(3)));
+		assert_eq!(HitCondition::parse(">=5"), Some(HitCondition::AtLeast(5)));
+		assert_eq!(HitCondition::parse(" % 2 "), Some(HitCondition::Multiple(2)));
+		assert_eq!(HitCondition::parse("4"), Some(HitCondition::Equal(4)));
+
+		// Nothing would ever be hit 0 times
+		for text in ["0", "== 0", ">= 0", "% 0"] {
+			assert_eq!(HitCondition::parse(text), None, "{}", text);
+		}
+
+		for text in ["", "==", "> 3", "three", "% -2", "== 1.5", "3 4", "99999999999"] {
+			assert_eq!(HitCondition::parse(text), None, "{}", text);
+		}
+	}
+
+	#[test]
+	fn hit_condition_matches() {
+		let hits = |condition: HitCondition| (1..=7).filter(|x| condition.matches(*x)).collect::<Vec<_>>();
+
+		assert_eq!(hits(HitCondition::Equal(3)), [3]);
+		assert_eq!(hits(HitCondition::AtLeast(5)), [5, 6, 7]);
+		assert_eq!(hits(HitCondition::Multiple(2)), [2, 4, 6]);
+		assert_eq!(hits(HitCondition::Multiple(1)), [1, 2, 3, 4, 5, 6, 7]);
+
+		// The server stops counting at u32::MAX, which still matches >= conditions
+		assert!(HitCondition::AtLeast(5).matches(u32::MAX));
+		assert!(!HitCondition::Equal(1).matches(u32::MAX));
+	}
+}
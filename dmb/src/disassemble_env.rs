@@ -0,0 +1,32 @@
+use crate::Dmb;
+
+/// Lets `dmasm` look things up in a [Dmb], the same way
+/// `instruction_hooking::disassemble_env::DisassembleEnv` does in a running
+/// world
+pub struct DisassembleEnv<'a> {
+	dmb: &'a Dmb
+}
+
+impl<'a> DisassembleEnv<'a> {
+	pub const fn new(dmb: &'a Dmb) -> Self {
+		Self { dmb }
+	}
+}
+
+impl dmasm::disassembler::DisassembleEnv for DisassembleEnv<'_> {
+	fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>> {
+		self.dmb.string(index).map(|x| x.to_vec())
+	}
+
+	fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>> {
+		self.dmb.variable_name(index).map(|x| x.to_vec())
+	}
+
+	fn get_proc_name(&mut self, index: u32) -> Option<String> {
+		self.dmb.procs.get(index as usize).map(|x| x.path.clone())
+	}
+
+	fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>> {
+		self.dmb.value_to_string(tag, data).map(String::into_bytes)
+	}
+}
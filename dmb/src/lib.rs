@@ -0,0 +1,581 @@
+//! Reads compiled `.dmb` files, for tools that need a world's procs, types and
+//! bytecode without running it.
+//!
+//! Only dmbs compiled by BYOND 500 and up are supported, and only the tables
+//! up to the var table are read. The parts of the file that are read look like
+//! this:
+//!
+//! ```text
+//! header       an optional `#!` line and a `world bin v<version>` line, then
+//!              version, min version, flags: u32, and extended flags: u32 if
+//!              the top bit of flags is set
+//! grid         maxx, maxy, maxz: u16, then runs of (turf, area, extra: id,
+//!              count: u8) until every turf is covered
+//! string size  u32
+//! types        count, then each type (see `read_type`)
+//! mobs         count, then (type, key: id, sight: u8, and sight flags: u32,
+//!              see in dark, see invisible: u8 if the top bit of sight is set)
+//! strings      count, then each string (see `Reader::string`)
+//! data         count, then (length: word, followed by that many words)
+//! procs        count, then (path, name, desc, category: id, range, access,
+//!              flags: u8, and extended flags: u32, invisibility: u8 if the top
+//!              bit of flags is set, then bytecode, locals, parameters: id)
+//! vars         count, then (tag: u8, data: u32, name: id)
+//! ```
+//!
+//! Counts, ids and words are u16s, or u32s in worlds that set bit 30 of the
+//! flags because they have more than 65535 of something. Ids that point at
+//! nothing are all ones. Bytecode, the locals and parameters of procs, and the
+//! procs and vars of types are stored as entries of the data table.
+//!
+//! # Examples
+//! ```ignore
+//! let dmb = dmb::Dmb::read(Path::new("tgstation.dmb"))?;
+//! let proc = dmb.find_proc("/mob/Login").unwrap();
+//! let (nodes, _error) = dmb.disassemble(proc);
+//! println!("{}", dmasm::format_disassembly(&nodes, None));
+//! ```
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+mod disassemble_env;
+mod reader;
+
+pub use disassemble_env::DisassembleEnv;
+use reader::{invalid, Reader};
+
+const MIN_VERSION: u32 = 500;
+
+// Flags in the header
+const EXTENDED_FLAGS: u32 = 0x8000_0000;
+const LARGE_IDS: u32 = 0x4000_0000;
+
+// Value tags, as in `auxtools::raw_types::values::ValueTag`
+const TAG_NULL: u8 = 0x00;
+const TAG_STRING: u8 = 0x06;
+const TAG_NUMBER: u8 = 0x2A;
+const TYPEPATH_TAGS: [u8; 5] = [0x08, 0x09, 0x0A, 0x0B, 0x20];
+
+pub struct Type {
+	/// Like `/obj/item`
+	pub path: String,
+	/// Index of the parent type in [Dmb::types]
+	pub parent: Option<u32>,
+	/// Ids of the procs defined (or overridden) on this type
+	pub procs: Vec<u32>
+}
+
+pub struct Proc {
+	pub id: u32,
+	/// The path the same way `auxtools::Proc` has it, like `/mob/Login`
+	pub path: String,
+	/// The path as it's written in the proc table, like `/mob/proc/Login`
+	pub raw_path: String,
+	/// Which definition of `path` this is, counting from 0 in the order they
+	/// appear in the proc table. Matches `auxtools::Proc::override_id`.
+	pub override_id: u32,
+	pub name: String,
+	pub bytecode: Vec<u32>,
+	/// Var ids of the proc's local variables
+	pub locals: Vec<u32>,
+	/// Var ids of the proc's parameters
+	pub parameters: Vec<u32>
+}
+
+/// An entry of the var table, which is what variable ids in bytecode index
+pub struct Var {
+	pub tag: u8,
+	pub data: u32,
+	/// Index of the var's name in [Dmb::strings]
+	pub name: Option<u32>
+}
+
+pub struct Dmb {
+	/// Version of BYOND that compiled the world
+	pub version: u32,
+	/// Oldest version of BYOND that can run the world
+	pub min_version: u32,
+	pub flags: u32,
+	pub strings: Vec<Vec<u8>>,
+	pub types: Vec<Type>,
+	pub procs: Vec<Proc>,
+	pub vars: Vec<Var>,
+	pub data: Vec<Vec<u32>>,
+	procs_by_path: HashMap<String, Vec<u32>>,
+	types_by_path: HashMap<String, u32>
+}
+
+// A type as it is stored, before its strings and data entries are looked up
+struct RawType {
+	path: Option<u32>,
+	parent: Option<u32>,
+	procs: Option<u32>
+}
+
+fn strip_path(p: String) -> String {
+	p.replace("/proc/", "/").replace("/verb/", "/")
+}
+
+fn read_header(reader: &mut Reader) -> io::Result<(u32, u32, u32)> {
+	if reader.peek(b"#!") {
+		reader.line()?;
+	}
+
+	let line = reader.line()?;
+	if !line.starts_with(b"world bin v") {
+		return Err(invalid("not a dmb"));
+	}
+
+	let version = reader.u32()?;
+	let min_version = reader.u32()?;
+	let flags = reader.u32()?;
+
+	if flags & EXTENDED_FLAGS != 0 {
+		reader.u32()?;
+	}
+
+	Ok((version, min_version, flags))
+}
+
+// Skips the map, which we don't care about
+fn skip_grid(reader: &mut Reader) -> io::Result<()> {
+	let max_x = reader.u16()? as u64;
+	let max_y = reader.u16()? as u64;
+	let max_z = reader.u16()? as u64;
+
+	let mut remaining = max_x * max_y * max_z;
+	while remaining > 0 {
+		reader.id()?;
+		reader.id()?;
+		reader.id()?;
+
+		let count = reader.u8()? as u64;
+		if count == 0 {
+			return Err(invalid("empty run of turfs"));
+		}
+
+		remaining = remaining.saturating_sub(count);
+	}
+
+	Ok(())
+}
+
+// path, parent, name, desc: id, icon: id, icon state: id, dir: u8, dmi flags:
+// u8, text: id, maptext: id, maptext width, maptext height: u16, (maptext x,
+// maptext y: u16 from 508), suffix: id, flags: u32, verbs, procs, init proc,
+// initializations, vars: id, layer: f32, has transform: u8 (and a 6 float
+// transform if it isn't 0), builtin vars: id
+fn read_type(reader: &mut Reader, version: u32) -> io::Result<RawType> {
+	let path = reader.id()?;
+	let parent = reader.id()?;
+	reader.id()?;
+	reader.id()?;
+	reader.id()?;
+	reader.id()?;
+	reader.u8()?;
+	reader.u8()?;
+	reader.id()?;
+
+	reader.id()?;
+	reader.u16()?;
+	reader.u16()?;
+	if version >= 508 {
+		reader.u16()?;
+		reader.u16()?;
+	}
+
+	reader.id()?;
+	reader.u32()?;
+	reader.id()?;
+	let procs = reader.id()?;
+	reader.id()?;
+	reader.id()?;
+	reader.id()?;
+	reader.f32()?;
+
+	if reader.u8()? != 0 {
+		for _ in 0..6 {
+			reader.f32()?;
+		}
+	}
+
+	reader.id()?;
+
+	Ok(RawType { path, parent, procs })
+}
+
+fn skip_mob(reader: &mut Reader) -> io::Result<()> {
+	reader.id()?;
+	reader.id()?;
+
+	if reader.u8()? & 0x80 != 0 {
+		reader.u32()?;
+		reader.u8()?;
+		reader.u8()?;
+	}
+
+	Ok(())
+}
+
+// Looks an id up in one of the tables, failing if it points past the end
+fn lookup<'a, T>(table: &'a [T], id: Option<u32>, what: &str) -> io::Result<Option<&'a T>> {
+	match id {
+		Some(id) => match table.get(id as usize) {
+			Some(entry) => Ok(Some(entry)),
+			None => Err(invalid(&format!("{} id {} is out of range", what, id)))
+		},
+		None => Ok(None)
+	}
+}
+
+impl Dmb {
+	pub fn read(path: &Path) -> io::Result<Dmb> {
+		Self::parse(&fs::read(path)?)
+	}
+
+	pub fn parse(data: &[u8]) -> io::Result<Dmb> {
+		let mut reader = Reader::new(data);
+
+		let (version, min_version, flags) = read_header(&mut reader)?;
+		if version < MIN_VERSION {
+			return Err(invalid(&format!("dmbs compiled by BYOND {} aren't supported", version)));
+		}
+
+		reader.large_ids = flags & LARGE_IDS != 0;
+
+		skip_grid(&mut reader)?;
+		reader.u32()?;
+
+		let type_count = reader.count()?;
+		let mut raw_types = Vec::with_capacity(type_count);
+		for _ in 0..type_count {
+			raw_types.push(read_type(&mut reader, version)?);
+		}
+
+		for _ in 0..reader.count()? {
+			skip_mob(&mut reader)?;
+		}
+
+		let string_count = reader.count()?;
+		let mut strings = Vec::with_capacity(string_count);
+		for _ in 0..string_count {
+			strings.push(reader.string()?);
+		}
+
+		let data_count = reader.count()?;
+		let mut data = Vec::with_capacity(data_count);
+		for _ in 0..data_count {
+			let len = reader.count()?;
+			let mut entry = Vec::with_capacity(len);
+			for _ in 0..len {
+				entry.push(reader.word()?);
+			}
+			data.push(entry);
+		}
+
+		let string = |id: Option<u32>| -> io::Result<String> {
+			Ok(lookup(&strings, id, "string")?.map(|x| String::from_utf8_lossy(x).into_owned()).unwrap_or_default())
+		};
+		let entry = |id: Option<u32>| -> io::Result<Vec<u32>> { Ok(lookup(&data, id, "data")?.cloned().unwrap_or_default()) };
+
+		let proc_count = reader.count()?;
+		let mut procs = Vec::with_capacity(proc_count);
+		let mut procs_by_path: HashMap<String, Vec<u32>> = HashMap::new();
+		for id in 0..proc_count as u32 {
+			let raw_path = string(reader.id()?)?;
+			let path = strip_path(raw_path.clone());
+			let name = string(reader.id()?)?;
+			reader.id()?;
+			reader.id()?;
+			reader.u8()?;
+			reader.u8()?;
+
+			if reader.u8()? & 0x80 != 0 {
+				reader.u32()?;
+				reader.u8()?;
+			}
+
+			let bytecode = entry(reader.id()?)?;
+			let locals = entry(reader.id()?)?;
+
+			// Each parameter takes up 4 words, the third being its name
+			let parameters = entry(reader.id()?)?.chunks_exact(4).map(|x| x[2]).collect();
+
+			let overrides = procs_by_path.entry(path.clone()).or_default();
+			let override_id = overrides.len() as u32;
+			overrides.push(id);
+
+			procs.push(Proc {
+				id,
+				path,
+				raw_path,
+				override_id,
+				name,
+				bytecode,
+				locals,
+				parameters
+			});
+		}
+
+		let var_count = reader.count()?;
+		let mut vars = Vec::with_capacity(var_count);
+		for _ in 0..var_count {
+			let tag = reader.u8()?;
+			let data = reader.u32()?;
+			let name = reader.id()?;
+			lookup(&strings, name, "string")?;
+			vars.push(Var { tag, data, name });
+		}
+
+		let mut types = Vec::with_capacity(raw_types.len());
+		let mut types_by_path = HashMap::new();
+		for (index, raw) in raw_types.into_iter().enumerate() {
+			let path = string(raw.path)?;
+			if raw.parent.is_some_and(|x| x as usize >= type_count) {
+				return Err(invalid("type has an unknown parent"));
+			}
+
+			let procs = entry(raw.procs)?;
+			if procs.iter().any(|x| *x as usize >= proc_count) {
+				return Err(invalid("type has an unknown proc"));
+			}
+
+			types_by_path.insert(path.clone(), index as u32);
+			types.push(Type {
+				path,
+				parent: raw.parent,
+				procs
+			});
+		}
+
+		Ok(Dmb {
+			version,
+			min_version,
+			flags,
+			strings,
+			types,
+			procs,
+			vars,
+			data,
+			procs_by_path,
+			types_by_path
+		})
+	}
+
+	/// Finds the first proc with the given path. Like `auxtools::Proc::find`,
+	/// `/proc/` and `/verb/` can be left out.
+	pub fn find_proc(&self, path: &str) -> Option<&Proc> {
+		self.find_proc_override(path, 0)
+	}
+
+	/// Finds the n'th definition of the proc with the given path
+	pub fn find_proc_override(&self, path: &str, override_id: u32) -> Option<&Proc> {
+		let id = *self.procs_by_path.get(&strip_path(path.to_owned()))?.get(override_id as usize)?;
+		self.procs.get(id as usize)
+	}
+
+	pub fn find_type(&self, path: &str) -> Option<&Type> {
+		self.types.get(*self.types_by_path.get(path)? as usize)
+	}
+
+	pub fn parent(&self, ty: &Type) -> Option<&Type> {
+		self.types.get(ty.parent? as usize)
+	}
+
+	/// Whether `ty` is `ancestor` or one of its subtypes
+	pub fn is_subtype(&self, ty: &Type, ancestor: &Type) -> bool {
+		let mut current = Some(ty);
+
+		// Bounded in case a broken file has a loop
+		for _ in 0..self.types.len() {
+			match current {
+				Some(ty) if std::ptr::eq(ty, ancestor) => return true,
+				Some(ty) => current = self.parent(ty),
+				None => break
+			}
+		}
+
+		false
+	}
+
+	pub fn string(&self, id: u32) -> Option<&[u8]> {
+		self.strings.get(id as usize).map(|x| &x[..])
+	}
+
+	/// The name of the var with the given id, which is how bytecode refers to
+	/// vars
+	pub fn variable_name(&self, id: u32) -> Option<&[u8]> {
+		self.string(self.vars.get(id as usize)?.name?)
+	}
+
+	/// Formats a value the way BYOND's `to_string` would, for the values a
+	/// dmb can contain
+	pub fn value_to_string(&self, tag: u32, data: u32) -> Option<String> {
+		match tag as u8 {
+			TAG_NULL => Some("null".to_owned()),
+			TAG_NUMBER => Some(format!("{}", f32::from_bits(data))),
+			TAG_STRING => Some(format!("{:?}", String::from_utf8_lossy(self.string(data)?))),
+			tag if TYPEPATH_TAGS.contains(&tag) => Some(self.types.get(data as usize)?.path.clone()),
+			_ => None
+		}
+	}
+
+	/// Disassembles a proc with `dmasm`, resolving strings, vars and procs from
+	/// this file
+	pub fn disassemble<'a>(&self, proc: &'a Proc) -> (Vec<dmasm::Node<'a>>, Option<dmasm::disassembler::DisassembleError>) {
+		dmasm::disassembler::disassemble(&proc.bytecode, &mut DisassembleEnv::new(self))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::reader::tests::Writer;
+
+	const STRINGS: [&str; 7] = ["/datum", "/obj", "/obj/item", "/obj/item/proc/use", "use", "user", "force"];
+
+	fn write_type(writer: &mut Writer, path: u32, parent: Option<u32>, procs: Option<u32>) {
+		writer.id(Some(path));
+		writer.id(parent);
+		for _ in 0..4 {
+			writer.id(None);
+		}
+		writer.u8(2);
+		writer.u8(0);
+		writer.id(None);
+		writer.id(None);
+		for _ in 0..4 {
+			writer.u16(0);
+		}
+		writer.id(None);
+		writer.u32(0);
+		writer.id(None);
+		writer.id(procs);
+		writer.id(None);
+		writer.id(None);
+		writer.id(None);
+		writer.u32(1.0f32.to_bits());
+		writer.u8(0);
+		writer.id(None);
+	}
+
+	fn write_proc(writer: &mut Writer, bytecode: u32) {
+		writer.id(Some(3));
+		writer.id(Some(4));
+		writer.id(None);
+		writer.id(None);
+		writer.u8(0);
+		writer.u8(0);
+		writer.u8(0);
+		writer.id(Some(bytecode));
+		writer.id(Some(3));
+		writer.id(Some(4));
+	}
+
+	// /datum, /obj and /obj/item, which defines /obj/item/proc/use twice
+	fn write_dmb(large_ids: bool) -> Vec<u8> {
+		let mut writer = Writer::new(large_ids);
+		writer.data.extend_from_slice(b"#!/usr/bin/env DreamDaemon\nworld bin v514\n");
+		writer.u32(514);
+		writer.u32(500);
+		writer.u32(if large_ids { LARGE_IDS } else { 0 });
+
+		// A 2x1x1 map in one run
+		writer.u16(2);
+		writer.u16(1);
+		writer.u16(1);
+		writer.id(Some(0));
+		writer.id(Some(0));
+		writer.id(None);
+		writer.u8(2);
+		writer.u32(0);
+
+		writer.word(3);
+		write_type(&mut writer, 0, None, None);
+		write_type(&mut writer, 1, Some(0), None);
+		write_type(&mut writer, 2, Some(1), Some(0));
+
+		writer.word(0);
+
+		writer.word(STRINGS.len() as u32);
+		for string in STRINGS {
+			writer.string(string.as_bytes());
+		}
+
+		let data: [&[u32]; 5] = [&[0, 1], &[0x01, 0x02], &[0x03], &[1], &[0, 0, 0, 0]];
+		writer.word(data.len() as u32);
+		for entry in data {
+			writer.word(entry.len() as u32);
+			for x in entry {
+				writer.word(*x);
+			}
+		}
+
+		writer.word(2);
+		write_proc(&mut writer, 1);
+		write_proc(&mut writer, 2);
+
+		writer.word(2);
+		writer.u8(0x2A);
+		writer.u32(1.5f32.to_bits());
+		writer.id(Some(6));
+		writer.u8(0x00);
+		writer.u32(0);
+		writer.id(Some(5));
+
+		writer.data
+	}
+
+	#[test]
+	fn parse() {
+		for large_ids in [false, true] {
+			let dmb = Dmb::parse(&write_dmb(large_ids)).unwrap();
+			assert_eq!((dmb.version, dmb.min_version), (514, 500));
+			assert_eq!(dmb.strings.len(), STRINGS.len());
+			assert_eq!(dmb.string(5), Some(&b"user"[..]));
+
+			let paths: Vec<_> = dmb.types.iter().map(|x| x.path.as_str()).collect();
+			assert_eq!(paths, ["/datum", "/obj", "/obj/item"]);
+
+			let datum = dmb.find_type("/datum").unwrap();
+			let obj = dmb.find_type("/obj").unwrap();
+			let item = dmb.find_type("/obj/item").unwrap();
+			assert!(dmb.parent(datum).is_none());
+			assert_eq!(dmb.parent(item).unwrap().path, "/obj");
+			assert!(dmb.is_subtype(item, datum));
+			assert!(!dmb.is_subtype(obj, item));
+			assert_eq!(item.procs, [0, 1]);
+
+			let first = dmb.find_proc("/obj/item/use").unwrap();
+			let second = dmb.find_proc_override("/obj/item/proc/use", 1).unwrap();
+			assert_eq!((first.id, first.override_id, first.name.as_str()), (0, 0, "use"));
+			assert_eq!((second.id, second.override_id), (1, 1));
+			assert_eq!((first.path.as_str(), first.raw_path.as_str()), ("/obj/item/use", "/obj/item/proc/use"));
+			assert_eq!(first.bytecode, [0x01, 0x02]);
+			assert_eq!(second.bytecode, [0x03]);
+			assert_eq!(first.locals, [1]);
+			assert_eq!(first.parameters, [0]);
+
+			assert_eq!(dmb.variable_name(0), Some(&b"force"[..]));
+			assert_eq!(dmb.variable_name(1), Some(&b"user"[..]));
+			assert_eq!(dmb.value_to_string(0x2A, dmb.vars[0].data).unwrap(), "1.5");
+			assert_eq!(dmb.value_to_string(0x09, 2).unwrap(), "/obj/item");
+		}
+	}
+
+	#[test]
+	fn invalid() {
+		let dmb = write_dmb(false);
+		for len in 0..dmb.len() {
+			assert!(Dmb::parse(&dmb[..len]).is_err());
+		}
+
+		// Clearing the large ids flag misreads everything after the header. The
+		// flags come after 42 bytes of header lines and the two versions.
+		let mut large = write_dmb(true);
+		assert_eq!(large[53], 0x40);
+		large[53] = 0;
+		assert!(Dmb::parse(&large).is_err());
+		assert!(Dmb::parse(b"world bin v400\n\x90\x01\x00\x00\x90\x01\x00\x00\x00\x00\x00\x00").is_err());
+	}
+}
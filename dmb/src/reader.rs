@@ -0,0 +1,245 @@
+use std::io::{self, ErrorKind};
+
+pub fn invalid(message: &str) -> io::Error {
+	io::Error::new(ErrorKind::InvalidData, message)
+}
+
+pub struct Reader<'a> {
+	data: &'a [u8],
+	pub position: usize,
+	// Set by the header, after which ids are read as u32s instead of u16s
+	pub large_ids: bool
+}
+
+impl<'a> Reader<'a> {
+	pub const fn new(data: &'a [u8]) -> Self {
+		Reader {
+			data,
+			position: 0,
+			large_ids: false
+		}
+	}
+
+	pub fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+		let end = self.position.checked_add(len).filter(|end| *end <= self.data.len());
+
+		match end {
+			Some(end) => {
+				let bytes = &self.data[self.position..end];
+				self.position = end;
+				Ok(bytes)
+			}
+			None => Err(invalid("unexpected end of dmb"))
+		}
+	}
+
+	pub fn u8(&mut self) -> io::Result<u8> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	pub fn u16(&mut self) -> io::Result<u16> {
+		let bytes = self.bytes(2)?;
+		Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+	}
+
+	pub fn u32(&mut self) -> io::Result<u32> {
+		let bytes = self.bytes(4)?;
+		Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	}
+
+	pub fn f32(&mut self) -> io::Result<f32> {
+		Ok(f32::from_bits(self.u32()?))
+	}
+
+	/// Reads up to and including the next `\n`, returning the line without it
+	pub fn line(&mut self) -> io::Result<&'a [u8]> {
+		let len = self.data[self.position..]
+			.iter()
+			.position(|x| *x == b'\n')
+			.ok_or_else(|| invalid("unexpected end of dmb"))?;
+
+		let line = self.bytes(len)?;
+		self.position += 1;
+		Ok(line)
+	}
+
+	pub fn peek(&self, prefix: &[u8]) -> bool {
+		self.data[self.position..].starts_with(prefix)
+	}
+
+	/// An index into one of the tables, which is 0xFFFF (or 0xFFFFFFFF) when
+	/// there's nothing there
+	pub fn id(&mut self) -> io::Result<Option<u32>> {
+		if self.large_ids {
+			return Ok(Some(self.u32()?).filter(|x| *x != u32::MAX));
+		}
+
+		Ok(Some(self.u16()?).filter(|x| *x != u16::MAX).map(u32::from))
+	}
+
+	pub fn word(&mut self) -> io::Result<u32> {
+		match self.large_ids {
+			true => self.u32(),
+			false => self.u16().map(u32::from)
+		}
+	}
+
+	// Reads a count of things that each take up at least one byte, so that a
+	// corrupt count can't make us allocate a huge amount of memory
+	pub fn count(&mut self) -> io::Result<usize> {
+		let count = self.word()? as usize;
+		if count > self.data.len() - self.position {
+			return Err(invalid("count is larger than the dmb"));
+		}
+
+		Ok(count)
+	}
+
+	/// Reads an entry of the string table. The length and contents are XORed
+	/// with a key that starts at the entry's position in the file and goes up
+	/// by 9 for every byte.
+	pub fn string(&mut self) -> io::Result<Vec<u8>> {
+		let mut key = self.position as u32;
+
+		let mut len = (self.u16()? ^ key as u16) as usize;
+		// Longer strings continue the length in a u32
+		if len == 0xFFFF {
+			key = key.wrapping_add(2);
+			len = (self.u32()? ^ key) as usize;
+		}
+
+		key = self.position as u32;
+		let data = self
+			.bytes(len)?
+			.iter()
+			.map(|x| {
+				let byte = x ^ key as u8;
+				key = key.wrapping_add(9);
+				byte
+			})
+			.collect();
+
+		Ok(data)
+	}
+}
+
+// Writes dmbs for tests, the inverse of Reader
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	pub struct Writer {
+		pub data: Vec<u8>,
+		pub large_ids: bool
+	}
+
+	impl Writer {
+		pub const fn new(large_ids: bool) -> Self {
+			Writer { data: Vec::new(), large_ids }
+		}
+
+		pub fn u8(&mut self, x: u8) {
+			self.data.push(x);
+		}
+
+		pub fn u16(&mut self, x: u16) {
+			self.data.extend_from_slice(&x.to_le_bytes());
+		}
+
+		pub fn u32(&mut self, x: u32) {
+			self.data.extend_from_slice(&x.to_le_bytes());
+		}
+
+		pub fn id(&mut self, id: Option<u32>) {
+			match self.large_ids {
+				true => self.u32(id.unwrap_or(u32::MAX)),
+				false => self.u16(id.map_or(u16::MAX, |x| x as u16))
+			}
+		}
+
+		pub fn word(&mut self, x: u32) {
+			match self.large_ids {
+				true => self.u32(x),
+				false => self.u16(x as u16)
+			}
+		}
+
+		pub fn string(&mut self, s: &[u8]) {
+			let key = self.data.len() as u32;
+			if s.len() < 0xFFFF {
+				self.u16(s.len() as u16 ^ key as u16);
+			} else {
+				self.u16(0xFFFF ^ key as u16);
+				self.u32(s.len() as u32 ^ key.wrapping_add(2));
+			}
+
+			let mut key = self.data.len() as u32;
+			for x in s {
+				self.u8(x ^ key as u8);
+				key = key.wrapping_add(9);
+			}
+		}
+	}
+
+	#[test]
+	fn string() {
+		let long = vec![b'x'; 0x10000];
+
+		let mut writer = Writer::new(false);
+		writer.u8(0);
+		writer.string(b"hello");
+		writer.string(b"");
+		writer.string(&long);
+		writer.string(b"world");
+
+		// Each key depends on where the string is, so they don't all start at 0
+		let encoded = &writer.data[1..];
+		assert_ne!(&encoded[2..7], b"hello");
+		assert_eq!(encoded[..2], [5 ^ 1, 0]);
+
+		let mut reader = Reader::new(&writer.data);
+		reader.u8().unwrap();
+		assert_eq!(reader.string().unwrap(), b"hello");
+		assert_eq!(reader.string().unwrap(), b"");
+		assert_eq!(reader.string().unwrap(), long);
+		assert_eq!(reader.string().unwrap(), b"world");
+		assert_eq!(reader.position, writer.data.len());
+
+		// A length past the end of the file
+		let mut reader = Reader::new(&writer.data[..5]);
+		reader.position = 1;
+		assert!(reader.string().is_err());
+	}
+
+	#[test]
+	fn large_ids() {
+		for large_ids in [false, true] {
+			let mut writer = Writer::new(large_ids);
+			writer.id(Some(7));
+			writer.id(None);
+			writer.word(0x1234);
+			writer.word(3);
+			writer.u8(0);
+			writer.u8(0);
+			writer.u8(0);
+
+			let mut reader = Reader::new(&writer.data);
+			reader.large_ids = large_ids;
+			assert_eq!(reader.id().unwrap(), Some(7));
+			assert_eq!(reader.id().unwrap(), None);
+			assert_eq!(reader.word().unwrap(), 0x1234);
+			assert_eq!(reader.count().unwrap(), 3);
+			assert_eq!(reader.position, writer.data.len() - 3);
+		}
+
+		// Ids over 0xFFFF only fit in large ids, and a u16 all ones is a real id there
+		let mut reader = Reader::new(&[0x00, 0x00, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x00]);
+		reader.large_ids = true;
+		assert_eq!(reader.id().unwrap(), Some(0x10000));
+		assert_eq!(reader.id().unwrap(), Some(0xFFFF));
+
+		// Counts can't be more than the bytes left
+		let mut reader = Reader::new(&[0x05, 0x00, 0x00]);
+		assert!(reader.count().is_err());
+	}
+}
@@ -0,0 +1,177 @@
+use std::{cmp::Reverse, collections::HashMap, path::Path, process::exit};
+
+use clap::{Arg, Command};
+
+mod retained;
+mod snapshot;
+
+use snapshot::Snapshot;
+
+#[derive(Default, Clone, Copy)]
+struct TypeStats {
+	count: u64,
+	size: u64,
+	retained: u64
+}
+
+// Objects dominated by another object of the same type are already counted in
+// that object's retained size, so they're left out of their type's total.
+fn type_stats(snapshot: &Snapshot) -> HashMap<&str, TypeStats> {
+	let retained = retained::calculate(snapshot);
+
+	let mut children = vec![vec![]; snapshot.objects.len()];
+	let mut tops = vec![];
+	for (index, dominator) in retained.dominators.iter().enumerate() {
+		match dominator {
+			Some(dominator) => children[*dominator].push(index),
+			None => tops.push(index)
+		}
+	}
+
+	let mut stats: HashMap<&str, TypeStats> = HashMap::new();
+	let mut open_types = vec![0u32; snapshot.types.len()];
+
+	// (object, entering)
+	let mut stack: Vec<(usize, bool)> = tops.into_iter().map(|x| (x, true)).collect();
+	while let Some((index, entering)) = stack.pop() {
+		let object = &snapshot.objects[index];
+		let ty = object.ty as usize;
+
+		if !entering {
+			open_types[ty] -= 1;
+			continue;
+		}
+
+		let entry = stats.entry(snapshot.type_name(object)).or_default();
+		entry.count += 1;
+		entry.size += object.size;
+		if open_types[ty] == 0 {
+			entry.retained += retained.sizes[index];
+		}
+
+		open_types[ty] += 1;
+		stack.push((index, false));
+		stack.extend(children[index].iter().map(|x| (*x, true)));
+	}
+
+	stats
+}
+
+fn read(path: &str) -> Snapshot {
+	match Snapshot::read(Path::new(path)) {
+		Ok(snapshot) => snapshot,
+		Err(e) => {
+			eprintln!("couldn't read {}: {}", path, e);
+			exit(1);
+		}
+	}
+}
+
+fn print_summary(snapshot: &Snapshot, limit: usize) {
+	let mut rows: Vec<(&str, TypeStats)> = type_stats(snapshot).into_iter().collect();
+	rows.sort_by_key(|(ty, stats)| (Reverse(stats.retained), *ty));
+
+	println!("{:>10} {:>14} {:>14}  type", "count", "size", "retained");
+	for (ty, stats) in rows.iter().take(limit) {
+		println!("{:>10} {:>14} {:>14}  {}", stats.count, stats.size, stats.retained, ty);
+	}
+
+	let total: u64 = snapshot.objects.iter().map(|x| x.size).sum();
+	println!();
+	println!("{} objects, {} bytes, {} types", snapshot.objects.len(), total, rows.len());
+}
+
+fn print_diff(old: &Snapshot, new: &Snapshot, limit: usize) {
+	let old_stats = type_stats(old);
+	let new_stats = type_stats(new);
+
+	let mut rows: Vec<(&str, i64, i64, i64)> = vec![];
+	for ty in old_stats.keys().chain(new_stats.keys().filter(|ty| !old_stats.contains_key(*ty))) {
+		let before = old_stats.get(ty).copied().unwrap_or_default();
+		let after = new_stats.get(ty).copied().unwrap_or_default();
+
+		let row = (
+			*ty,
+			after.count as i64 - before.count as i64,
+			after.size as i64 - before.size as i64,
+			after.retained as i64 - before.retained as i64
+		);
+
+		if row.1 != 0 || row.2 != 0 || row.3 != 0 {
+			rows.push(row);
+		}
+	}
+
+	rows.sort_by_key(|(ty, _, _, retained)| (Reverse(retained.abs()), *ty));
+
+	println!("{:>10} {:>14} {:>14}  type", "count", "size", "retained");
+	for (ty, count, size, retained) in rows.iter().take(limit) {
+		println!("{:>+10} {:>+14} {:>+14}  {}", count, size, retained, ty);
+	}
+
+	println!();
+	println!(
+		"{:+} objects, {:+} bytes, {} types changed",
+		new.objects.len() as i64 - old.objects.len() as i64,
+		new.objects.iter().map(|x| x.size as i64).sum::<i64>() - old.objects.iter().map(|x| x.size as i64).sum::<i64>(),
+		rows.len()
+	);
+}
+
+fn print_instances(snapshot: &Snapshot, ty: &str, limit: usize) {
+	let retained = retained::calculate(snapshot);
+
+	let mut rows: Vec<usize> = (0..snapshot.objects.len())
+		.filter(|index| snapshot.type_name(&snapshot.objects[*index]) == ty)
+		.collect();
+	rows.sort_by_key(|index| Reverse(retained.sizes[*index]));
+
+	println!("{:>12} {:>10} {:>14} {:>14}", "ref", "refcount", "size", "retained");
+	for index in rows.iter().take(limit) {
+		let object = &snapshot.objects[*index];
+		println!(
+			"{:>12} {:>10} {:>14} {:>14}",
+			format!("[0x{:x}]", ((object.tag as u32) << 24) | object.id),
+			object.refcount,
+			object.size,
+			retained.sizes[*index]
+		);
+	}
+
+	println!();
+	println!("{} instances of {}", rows.len(), ty);
+}
+
+fn main() {
+	let matches = Command::new("heapdiff")
+		.about("Summarises auxtools heap snapshots, or compares two of them")
+		.disable_version_flag(true)
+		.arg(Arg::new("old").help("snapshot to summarise, or the older snapshot when comparing").required(true).takes_value(true))
+		.arg(Arg::new("new").help("newer snapshot to compare against").takes_value(true))
+		.arg(
+			Arg::new("limit")
+				.long("limit")
+				.short('n')
+				.help("number of types to show")
+				.default_value("50")
+				.takes_value(true)
+		)
+		.arg(
+			Arg::new("instances")
+				.long("instances")
+				.help("list the largest instances of a type in the newest snapshot instead")
+				.takes_value(true)
+		)
+		.get_matches();
+
+	let limit = matches.value_of("limit").unwrap().parse::<usize>().expect("limit must be an integer");
+	let old = read(matches.value_of("old").unwrap());
+
+	let new = matches.value_of("new").map(read);
+
+	match (matches.value_of("instances"), new) {
+		(Some(ty), new) => print_instances(new.as_ref().unwrap_or(&old), ty, limit),
+		(None, Some(new)) => print_diff(&old, &new, limit),
+		(None, None) => print_summary(&old, limit)
+	}
+}
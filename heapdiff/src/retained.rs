@@ -0,0 +1,237 @@
+//! Retained sizes, calculated from the dominator tree of the object graph.
+//!
+//! An object's retained size is its own size plus the size of everything that
+//! would be freed along with it, i.e. everything it dominates. Objects that
+//! can't be reached from any root (because whatever holds them isn't visible to
+//! auxtools) are treated as roots themselves.
+
+use crate::snapshot::Snapshot;
+
+const UNDEFINED: usize = usize::MAX;
+
+// Node 0 is a virtual root with edges to every real root. Object `i` is node
+// `i + 1`.
+struct Graph {
+	successors: Vec<Vec<usize>>,
+	predecessors: Vec<Vec<usize>>
+}
+
+impl Graph {
+	fn new(snapshot: &Snapshot) -> Graph {
+		let node_count = snapshot.objects.len() + 1;
+		let mut successors = vec![vec![]; node_count];
+		let mut predecessors = vec![vec![]; node_count];
+
+		successors[0] = snapshot.roots.iter().map(|root| *root as usize + 1).collect();
+
+		for (index, object) in snapshot.objects.iter().enumerate() {
+			successors[index + 1] = object.references.iter().map(|reference| *reference as usize + 1).collect();
+		}
+
+		for (node, targets) in successors.iter().enumerate() {
+			for target in targets {
+				predecessors[*target].push(node);
+			}
+		}
+
+		Graph { successors, predecessors }
+	}
+
+	fn add_root(&mut self, node: usize) {
+		self.successors[0].push(node);
+		self.predecessors[node].push(0);
+	}
+
+	fn search(&self, start: usize, visited: &mut [bool], order: &mut Vec<usize>) {
+		let mut stack = vec![(start, 0)];
+		visited[start] = true;
+
+		while let Some((node, next)) = stack.last_mut() {
+			match self.successors[*node].get(*next) {
+				Some(successor) => {
+					*next += 1;
+					if !visited[*successor] {
+						visited[*successor] = true;
+						stack.push((*successor, 0));
+					}
+				}
+
+				None => {
+					order.push(*node);
+					stack.pop();
+				}
+			}
+		}
+	}
+
+	// Returns the nodes in post-order, attaching anything unreachable to the
+	// virtual root first
+	fn post_order(&mut self) -> Vec<usize> {
+		let node_count = self.successors.len();
+		let mut visited = vec![false; node_count];
+		let mut order = Vec::with_capacity(node_count);
+
+		self.search(0, &mut visited, &mut order);
+
+		for node in 1..node_count {
+			if !visited[node] {
+				self.add_root(node);
+				self.search(node, &mut visited, &mut order);
+			}
+		}
+
+		visited.iter_mut().for_each(|x| *x = false);
+		order.clear();
+		self.search(0, &mut visited, &mut order);
+		order
+	}
+}
+
+pub struct Retained {
+	/// Retained size of each object
+	pub sizes: Vec<u64>,
+	/// Immediate dominator of each object, or `None` if it is only dominated by
+	/// the virtual root
+	pub dominators: Vec<Option<usize>>
+}
+
+pub fn calculate(snapshot: &Snapshot) -> Retained {
+	let mut graph = Graph::new(snapshot);
+	let post_order = graph.post_order();
+
+	let mut post_order_index = vec![UNDEFINED; post_order.len()];
+	for (index, node) in post_order.iter().enumerate() {
+		post_order_index[*node] = index;
+	}
+
+	// "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy
+	let mut idom = vec![UNDEFINED; post_order.len()];
+	idom[0] = 0;
+
+	let intersect = |idom: &[usize], mut a: usize, mut b: usize| {
+		while a != b {
+			while post_order_index[a] < post_order_index[b] {
+				a = idom[a];
+			}
+
+			while post_order_index[b] < post_order_index[a] {
+				b = idom[b];
+			}
+		}
+
+		a
+	};
+
+	let mut changed = true;
+	while changed {
+		changed = false;
+
+		for node in post_order.iter().rev().skip(1) {
+			let mut new_idom = UNDEFINED;
+
+			for predecessor in &graph.predecessors[*node] {
+				if idom[*predecessor] == UNDEFINED {
+					continue;
+				}
+
+				new_idom = match new_idom {
+					UNDEFINED => *predecessor,
+					current => intersect(&idom, *predecessor, current)
+				};
+			}
+
+			if idom[*node] != new_idom {
+				idom[*node] = new_idom;
+				changed = true;
+			}
+		}
+	}
+
+	// Children always come before their dominators in post-order
+	let mut sizes = vec![0u64; post_order.len()];
+	for node in &post_order {
+		if *node == 0 {
+			continue;
+		}
+
+		sizes[*node] += snapshot.objects[*node - 1].size;
+		let dominator = idom[*node];
+		sizes[dominator] += sizes[*node];
+	}
+
+	Retained {
+		sizes: sizes[1..].to_vec(),
+		dominators: idom[1..].iter().map(|x| x.checked_sub(1)).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::snapshot::Object;
+
+	// Builds a snapshot where object `i` has size `10 * (i + 1)`
+	fn snapshot(references: &[&[u32]], roots: &[u32]) -> Snapshot {
+		let objects = references
+			.iter()
+			.enumerate()
+			.map(|(index, references)| Object {
+				tag: 0x21,
+				id: index as u32,
+				ty: 0,
+				refcount: 0,
+				size: 10 * (index as u64 + 1),
+				references: references.to_vec()
+			})
+			.collect();
+
+		Snapshot {
+			types: vec!["/datum".to_owned()],
+			objects,
+			roots: roots.to_vec()
+		}
+	}
+
+	#[test]
+	fn chain() {
+		let retained = calculate(&snapshot(&[&[1], &[2], &[]], &[0]));
+
+		assert_eq!(retained.dominators, [None, Some(0), Some(1)]);
+		assert_eq!(retained.sizes, [60, 50, 30]);
+	}
+
+	#[test]
+	fn diamond() {
+		// 0 holds 1 and 2, which both hold 3
+		let retained = calculate(&snapshot(&[&[1, 2], &[3], &[3], &[]], &[0]));
+
+		assert_eq!(retained.dominators, [None, Some(0), Some(0), Some(0)]);
+		assert_eq!(retained.sizes, [100, 20, 30, 40]);
+	}
+
+	#[test]
+	fn shared_between_roots() {
+		// Freeing either root alone wouldn't free 2
+		let retained = calculate(&snapshot(&[&[2], &[2], &[]], &[0, 1]));
+
+		assert_eq!(retained.dominators, [None, None, None]);
+		assert_eq!(retained.sizes, [10, 20, 30]);
+	}
+
+	#[test]
+	fn cycle() {
+		let retained = calculate(&snapshot(&[&[1], &[2], &[1]], &[0]));
+
+		assert_eq!(retained.dominators, [None, Some(0), Some(1)]);
+		assert_eq!(retained.sizes, [60, 50, 30]);
+	}
+
+	#[test]
+	fn unreachable() {
+		// Nothing we can see holds 1, so it's treated as a root of its own
+		let retained = calculate(&snapshot(&[&[], &[2], &[]], &[0]));
+
+		assert_eq!(retained.dominators, [None, None, Some(1)]);
+		assert_eq!(retained.sizes, [10, 50, 30]);
+	}
+}
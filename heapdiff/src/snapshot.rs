@@ -0,0 +1,215 @@
+//! Reader for the heap snapshots written by `auxtools::heap`. See that module
+//! for a description of the format.
+
+use std::{
+	fs,
+	io::{self, ErrorKind},
+	path::Path
+};
+
+const MAGIC: &[u8] = b"AUXHEAP1";
+
+pub struct Object {
+	pub tag: u8,
+	pub id: u32,
+	pub ty: u32,
+	pub refcount: u32,
+	pub size: u64,
+	pub references: Vec<u32>
+}
+
+pub struct Snapshot {
+	pub types: Vec<String>,
+	pub objects: Vec<Object>,
+	pub roots: Vec<u32>
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+	position: usize
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl<'a> Reader<'a> {
+	fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+		let end = self.position.checked_add(len).filter(|end| *end <= self.data.len());
+
+		match end {
+			Some(end) => {
+				let bytes = &self.data[self.position..end];
+				self.position = end;
+				Ok(bytes)
+			}
+			None => Err(invalid("unexpected end of snapshot"))
+		}
+	}
+
+	fn byte(&mut self) -> io::Result<u8> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	fn varint(&mut self) -> io::Result<u64> {
+		let mut value = 0u64;
+
+		for shift in (0..64).step_by(7) {
+			let byte = self.byte()?;
+			value |= ((byte & 0x7F) as u64) << shift;
+
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+
+		Err(invalid("varint is too long"))
+	}
+
+	fn u32(&mut self) -> io::Result<u32> {
+		u32::try_from(self.varint()?).map_err(|_| invalid("value out of range"))
+	}
+
+	// Reads a count of things that each take up at least one byte, so that a
+	// corrupt count can't make us allocate a huge amount of memory
+	fn count(&mut self) -> io::Result<usize> {
+		let count = self.varint()?;
+		if count > (self.data.len() - self.position) as u64 {
+			return Err(invalid("count is larger than the snapshot"));
+		}
+
+		Ok(count as usize)
+	}
+}
+
+impl Snapshot {
+	pub fn read(path: &Path) -> io::Result<Snapshot> {
+		Self::parse(&fs::read(path)?)
+	}
+
+	pub fn parse(data: &[u8]) -> io::Result<Snapshot> {
+		let mut reader = Reader { data, position: 0 };
+
+		if reader.bytes(MAGIC.len())? != MAGIC {
+			return Err(invalid("not a heap snapshot"));
+		}
+
+		let type_count = reader.count()?;
+		let mut types = Vec::with_capacity(type_count);
+		for _ in 0..type_count {
+			let len = reader.count()?;
+			let name = std::str::from_utf8(reader.bytes(len)?).map_err(|_| invalid("type name is not valid utf-8"))?;
+			types.push(name.to_owned());
+		}
+
+		let object_count = reader.count()?;
+		let mut objects = Vec::with_capacity(object_count);
+		for _ in 0..object_count {
+			let tag = reader.byte()?;
+			let id = reader.u32()?;
+			let ty = reader.u32()?;
+			let refcount = reader.u32()?;
+			let size = reader.varint()?;
+
+			let reference_count = reader.count()?;
+			let mut references = Vec::with_capacity(reference_count);
+			for _ in 0..reference_count {
+				references.push(reader.u32()?);
+			}
+
+			if ty as usize >= types.len() {
+				return Err(invalid("object has an unknown type"));
+			}
+
+			objects.push(Object {
+				tag,
+				id,
+				ty,
+				refcount,
+				size,
+				references
+			});
+		}
+
+		let root_count = reader.count()?;
+		let mut roots = Vec::with_capacity(root_count);
+		for _ in 0..root_count {
+			roots.push(reader.u32()?);
+		}
+
+		let in_range = |index: &u32| (*index as usize) < objects.len();
+		if !roots.iter().all(in_range) || !objects.iter().all(|object| object.references.iter().all(in_range)) {
+			return Err(invalid("reference to an object that isn't in the snapshot"));
+		}
+
+		Ok(Snapshot { types, objects, roots })
+	}
+
+	pub fn type_name(&self, object: &Object) -> &str {
+		&self.types[object.ty as usize]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// The same snapshot `auxtools::heap` is tested to write: a datum at the root
+	// holding a list.
+	const SNAPSHOT: &[u8] = &[
+		b'A', b'U', b'X', b'H', b'E', b'A', b'P', b'1', // magic
+		0x02, // type count
+		0x05, b'/', b'l', b'i', b's', b't', // "/list"
+		0x0C, b'/', b'd', b'a', b't', b'u', b'm', b'/', b't', b'h', b'i', b'n', b'g', // "/datum/thing"
+		0x02, // object count
+		0x21, 0x05, 0x01, 0x01, 0xC8, 0x01, 0x01, 0x01, // datum 5, size 200, references the list
+		0x0F, 0x03, 0x00, 0x01, 0x18, 0x00, // list 3, size 24
+		// roots
+		0x01, 0x00
+	];
+
+	#[test]
+	fn parse() {
+		let snapshot = Snapshot::parse(SNAPSHOT).unwrap();
+
+		assert_eq!(snapshot.types, ["/list", "/datum/thing"]);
+		assert_eq!(snapshot.roots, [0]);
+		assert_eq!(snapshot.objects.len(), 2);
+
+		let datum = &snapshot.objects[0];
+		assert_eq!((datum.tag, datum.id, datum.refcount, datum.size), (0x21, 5, 1, 200));
+		assert_eq!(snapshot.type_name(datum), "/datum/thing");
+		assert_eq!(datum.references, [1]);
+
+		let list = &snapshot.objects[1];
+		assert_eq!((list.tag, list.id, list.refcount, list.size), (0x0F, 3, 1, 24));
+		assert_eq!(snapshot.type_name(list), "/list");
+		assert!(list.references.is_empty());
+	}
+
+	#[test]
+	fn truncated() {
+		for len in 0..SNAPSHOT.len() {
+			assert!(Snapshot::parse(&SNAPSHOT[..len]).is_err(), "parsed {} bytes", len);
+		}
+	}
+
+	#[test]
+	fn invalid() {
+		let mut magic = SNAPSHOT.to_vec();
+		magic[7] = b'2';
+		assert!(Snapshot::parse(&magic).is_err());
+
+		// The datum's type and reference, then the root
+		for (position, value) in [(31, 0x02), (36, 0x02), (44, 0x02)] {
+			let mut data = SNAPSHOT.to_vec();
+			data[position] = value;
+			assert!(Snapshot::parse(&data).is_err(), "byte {} = {}", position, value);
+		}
+
+		// A count bigger than the rest of the file
+		let mut count = SNAPSHOT.to_vec();
+		count[8] = 0x7F;
+		assert!(Snapshot::parse(&count).is_err());
+	}
+}
@@ -2,7 +2,7 @@ pub mod disassemble_env;
 
 use auxtools::*;
 use retour::RawDetour;
-use std::{any::Any, cell::UnsafeCell, ffi::c_void};
+use std::{any::Any, ffi::c_void};
 
 #[cfg(windows)]
 signatures! {
@@ -36,7 +36,9 @@ pub trait InstructionHook: InstructionHookToAny {
 	fn handle_instruction(&mut self, ctx: *mut raw_types::procs::ExecutionContext);
 }
 
-pub static mut INSTRUCTION_HOOKS: UnsafeCell<Vec<Box<dyn InstructionHook>>> = UnsafeCell::new(Vec::new());
+// Cleared whenever auxtools shuts down. Hooks may be added or removed while
+// other hooks are running, but a hook must not remove itself.
+pub static INSTRUCTION_HOOKS: ReloadableState<Vec<Box<dyn InstructionHook>>> = ReloadableState::new(Vec::new);
 
 extern "C" {
 	// Trampoline to the original un-hooked BYOND execute_instruction code
@@ -82,22 +84,20 @@ fn instruction_hooking_init() -> Result<(), String> {
 	Ok(())
 }
 
-#[shutdown]
-fn instruction_hooking_shutdown() {
-	unsafe {
-		INSTRUCTION_HOOKS.get_mut().clear();
-	}
-}
-
 // Handles any instruction BYOND tries to execute.
 // This function has to leave `*CURRENT_EXECUTION_CONTEXT` in EAX, so make sure
 // to return it.
 #[no_mangle]
 extern "C" fn handle_instruction(ctx: *mut raw_types::procs::ExecutionContext) -> *const raw_types::procs::ExecutionContext {
-	unsafe {
-		for vec_box in &mut *INSTRUCTION_HOOKS.get() {
-			vec_box.handle_instruction(ctx);
+	// Hooks can run more DM, which comes back through here, and that DM can add
+	// or remove hooks. Each hook is looked up again after the previous one has
+	// run so the list is never borrowed while they're running.
+	let mut index = 0;
+	while let Some(hook) = INSTRUCTION_HOOKS.with(|hooks| hooks.get_mut(index).map(|hook| &mut **hook as *mut dyn InstructionHook)) {
+		unsafe {
+			(*hook).handle_instruction(ctx);
 		}
+		index += 1;
 	}
 
 	ctx
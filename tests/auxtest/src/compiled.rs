@@ -0,0 +1,56 @@
+use auxtest_impl::auxtest;
+use auxtools::*;
+use dmb::Dmb;
+
+fn new_datum(proc: &str) -> DMResult {
+	Proc::find(proc)
+		.ok_or_else(|| runtime!("test_compiled_dmb: {} not defined", proc))?
+		.call(&[])
+}
+
+// Parses the .dmb the test runner booted, which DreamMaker compiled, and checks
+// it against what BYOND loaded from it
+#[auxtest]
+fn compiled_dmb() -> Result<(), Runtime> {
+	let path = std::env::var_os("AUXTEST_DMB").ok_or_else(|| runtime!("test_compiled_dmb: AUXTEST_DMB not set"))?;
+	let dmb = Dmb::read(path.as_ref()).map_err(|e| runtime!("test_compiled_dmb: couldn't parse {:?}: {}", path, e))?;
+
+	for proc in &dmb.procs {
+		let loaded = Proc::find_override(&proc.path, proc.override_id)
+			.ok_or_else(|| runtime!("test_compiled_dmb: {} #{} isn't loaded", proc.path, proc.override_id))?;
+
+		if loaded.id.0 != proc.id || loaded.path != proc.path {
+			return Err(runtime!(
+				"test_compiled_dmb: {} #{} is proc {} in the dmb but {} ({}) in BYOND",
+				proc.path,
+				proc.override_id,
+				proc.id,
+				loaded.id.0,
+				loaded.path
+			));
+		}
+	}
+
+	if dmb.find_proc("/proc/auxtest_new_laser").is_none() {
+		return Err(runtime!("test_compiled_dmb: /proc/auxtest_new_laser missing from the dmb"));
+	}
+
+	// Every ancestor the dmb lists is one BYOND agrees with
+	for proc in ["/proc/auxtest_new_laser", "/proc/auxtest_new_obj"] {
+		let value = new_datum(proc)?;
+		let path = value.typepath()?.to_string()?;
+		let mut ty = dmb.find_type(&path);
+		if ty.is_none() {
+			return Err(runtime!("test_compiled_dmb: {} missing from the dmb", path));
+		}
+
+		while let Some(current) = ty {
+			if !value.is_type(&current.path) {
+				return Err(runtime!("test_compiled_dmb: {} isn't a {} in BYOND", path, current.path));
+			}
+			ty = dmb.parent(current);
+		}
+	}
+
+	Ok(())
+}
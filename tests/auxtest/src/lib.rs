@@ -1,10 +1,25 @@
+use std::{
+	panic::{self, AssertUnwindSafe},
+	time::Instant
+};
+
 use auxtools::*;
 
+mod compiled;
 mod lists;
 mod strings;
+mod types;
 mod value_from;
 mod weak;
 
+/// A test registered with `#[auxtest]`
+pub struct AuxTest {
+	pub name: &'static str,
+	pub func: fn() -> Result<(), Runtime>
+}
+
+inventory::collect!(AuxTest);
+
 #[hook("/proc/auxtest_inc_counter")]
 fn inc_counter() {
 	static mut COUNTER: u32 = 0;
@@ -20,3 +35,53 @@ fn out(msg: Value) {
 	eprintln!("\n{}", msg.as_string()?);
 	Ok(Value::NULL)
 }
+
+// Runs a single test, turning panics into failures so one broken test doesn't
+// take the rest down with it
+fn run_test(test: &AuxTest) -> Result<(), String> {
+	match panic::catch_unwind(AssertUnwindSafe(test.func)) {
+		Ok(Ok(())) => Ok(()),
+		Ok(Err(e)) => Err(e.message),
+		Err(payload) => Err(match payload.downcast_ref::<&str>() {
+			Some(msg) => format!("panicked: {}", msg),
+			None => match payload.downcast_ref::<String>() {
+				Some(msg) => format!("panicked: {}", msg),
+				None => "panicked".to_owned()
+			}
+		})
+	}
+}
+
+// Results are written to stderr as tab separated lines for the test runner:
+// AUXTEST <boot> <name> <ok|failed> <seconds> <message>
+#[hook("/proc/auxtest_run_all")]
+fn run_all() {
+	static mut BOOT: u32 = 0;
+
+	let boot = unsafe {
+		BOOT += 1;
+		BOOT
+	};
+
+	let mut tests: Vec<&AuxTest> = inventory::iter::<AuxTest>.into_iter().collect();
+	tests.sort_by_key(|x| x.name);
+
+	let mut all_passed = true;
+	for test in tests {
+		let start = Instant::now();
+		let result = run_test(test);
+		let elapsed = start.elapsed().as_secs_f64();
+
+		let (status, message) = match result {
+			Ok(()) => ("ok", String::new()),
+			Err(message) => {
+				all_passed = false;
+				("failed", message.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t"))
+			}
+		};
+
+		eprintln!("\nAUXTEST\t{}\t{}\t{}\t{:.6}\t{}", boot, test.name, status, elapsed, message);
+	}
+
+	Ok(Value::from(all_passed))
+}
@@ -1,7 +1,8 @@
+use auxtest_impl::auxtest;
 use auxtools::*;
 
-#[hook("/proc/auxtest_lists")]
-fn test_lists() {
+#[auxtest]
+fn lists() -> Result<(), Runtime> {
 	let list_a = List::new();
 
 	// Should be empty
@@ -52,5 +53,5 @@ fn test_lists() {
 		}
 	}
 
-	Ok(Value::from(true))
+	Ok(())
 }
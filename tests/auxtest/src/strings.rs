@@ -1,9 +1,10 @@
 use std::ffi::CString;
 
+use auxtest_impl::auxtest;
 use auxtools::*;
 
-#[hook("/proc/auxtest_strings")]
-fn test_strings() {
+#[auxtest]
+fn strings() -> Result<(), Runtime> {
 	use raw_types::{funcs, strings, values};
 
 	unsafe {
@@ -76,6 +77,6 @@ fn test_strings() {
 			return Err(runtime!("test_string: string_a's reference count != 1 after concat_strings"));
 		}
 
-		Ok(Value::from(true))
+		Ok(())
 	}
 }
@@ -0,0 +1,45 @@
+use auxtest_impl::auxtest;
+use auxtools::*;
+
+fn new_datum(proc: &str) -> DMResult {
+	Proc::find(proc).ok_or_else(|| runtime!("test_types: {} not defined", proc))?.call(&[])
+}
+
+// Checks the parents the type table reads from BYOND, both ones set with
+// `parent_type` and the ones implied by the path
+#[auxtest]
+fn types() -> Result<(), Runtime> {
+	let laser = new_datum("/proc/auxtest_new_laser")?;
+	let item = new_datum("/proc/auxtest_new_item")?;
+	let obj = new_datum("/proc/auxtest_new_obj")?;
+
+	for path in ["/datum/auxtest_gun/laser", "/datum/auxtest_gun", "/datum/auxtest_item", "/datum"] {
+		if !laser.is_type(path) {
+			return Err(runtime!("test_types: laser isn't a {}", path));
+		}
+	}
+
+	if item.is_type("/datum/auxtest_gun") {
+		return Err(runtime!("test_types: item is a /datum/auxtest_gun"));
+	}
+
+	if !laser.is_subtype(&item) || item.is_subtype(&laser) {
+		return Err(runtime!("test_types: is_subtype got laser and item the wrong way round"));
+	}
+
+	for path in ["/obj/auxtest_obj", "/obj", "/atom/movable", "/atom", "/datum"] {
+		if !obj.is_type(path) {
+			return Err(runtime!("test_types: obj isn't a {}", path));
+		}
+	}
+
+	if obj.is_type("/datum/auxtest_item") {
+		return Err(runtime!("test_types: obj is a /datum/auxtest_item"));
+	}
+
+	if !Value::from(List::new()).is_type("/list") {
+		return Err(runtime!("test_types: list isn't a /list"));
+	}
+
+	Ok(())
+}
@@ -1,9 +1,10 @@
 use std::{collections::HashMap, convert::TryFrom};
 
+use auxtest_impl::auxtest;
 use auxtools::*;
 
-#[hook("/proc/auxtest_value_from")]
-fn test_value_from() {
+#[auxtest]
+fn value_from() -> Result<(), Runtime> {
 	// Numbers
 	let value = Value::from(30);
 	if value.as_number()? != 30.0 {
@@ -41,7 +42,7 @@ fn test_value_from() {
 
 	// Todo: Other stuff
 
-	Ok(Value::from(true))
+	Ok(())
 }
 
 fn assert_meow_equals_one(value: Value) -> Result<(), Runtime> {
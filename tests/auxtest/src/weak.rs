@@ -1,7 +1,12 @@
+use auxtest_impl::auxtest;
 use auxtools::*;
 
-#[hook("/proc/auxtest_weak_values")]
-fn test_weak_values(someval: Value) {
+#[auxtest]
+fn weak_values() -> Result<(), Runtime> {
+	let someval = Proc::find("/proc/auxtest_weak_subject")
+		.ok_or_else(|| runtime!("test_weak_values: /proc/auxtest_weak_subject not defined"))?
+		.call(&[])?;
+
 	let weak = someval.as_weak()?;
 
 	if weak.upgrade().is_none() {
@@ -10,7 +15,7 @@ fn test_weak_values(someval: Value) {
 
 	Proc::find("/proc/del_value")
 		.ok_or_else(|| runtime!("test_weak_values: /proc/del_value not defined"))?
-		.call(&[someval])?;
+		.call(&[&someval])?;
 
 	Proc::find("/proc/create_datum_for_weak")
 		.ok_or_else(|| runtime!("test_weak_values: /proc/create_datum_for_weak not defined"))?
@@ -20,5 +25,5 @@ fn test_weak_values(someval: Value) {
 		return Err(runtime!("test_weak_values: Upgraded a weak reference to deleted value"));
 	}
 
-	Ok(Value::from(true))
+	Ok(())
 }
@@ -0,0 +1,46 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+
+/// Registers a function as a test to be run by `/proc/auxtest_run_all`.
+/// Tests take no arguments and return `Result<(), Runtime>`.
+///
+/// # Examples
+/// ```ignore
+/// #[auxtest]
+/// fn lists() -> Result<(), Runtime> {
+///     if !List::new().is_empty() {
+///         return Err(runtime!("new list isn't empty"));
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn auxtest(attr: TokenStream, item: TokenStream) -> TokenStream {
+	let func = syn::parse_macro_input!(item as syn::ItemFn);
+
+	if !attr.is_empty() {
+		return syn::Error::new(func.sig.ident.span(), "auxtest doesn't take any arguments")
+			.to_compile_error()
+			.into();
+	}
+
+	if !func.sig.inputs.is_empty() {
+		return syn::Error::new(func.sig.inputs.span(), "auxtests can't take arguments")
+			.to_compile_error()
+			.into();
+	}
+
+	let func_name = &func.sig.ident;
+	let test_name = func_name.to_string();
+
+	let code = quote! {
+		#func
+		auxtools::inventory::submit!(
+			crate::AuxTest { name: #test_name, func: #func_name }
+		);
+	};
+
+	code.into()
+}
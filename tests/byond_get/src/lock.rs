@@ -0,0 +1,56 @@
+//! The lockfile records the SHA-256 of every BYOND archive we've installed, so
+//! a corrupted download or a cached archive that has been tampered with is
+//! caught before anything gets extracted.
+//!
+//! Each line is a version followed by the hash, like `515.1647 <hex>`. Blank
+//! lines and lines starting with `#` are ignored.
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use sha2::{Digest, Sha256};
+
+pub struct Lockfile {
+	hashes: BTreeMap<String, String>
+}
+
+pub fn hash(data: &[u8]) -> String {
+	Sha256::digest(data).iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+impl Lockfile {
+	pub fn read(path: &Path) -> io::Result<Self> {
+		let contents = match fs::read_to_string(path) {
+			Ok(contents) => contents,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+			Err(e) => return Err(e)
+		};
+
+		let mut hashes = BTreeMap::new();
+		for line in contents.lines().map(str::trim).filter(|x| !x.is_empty() && !x.starts_with('#')) {
+			let (version, hash) = line
+				.split_once(char::is_whitespace)
+				.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed lockfile line {:?}", line)))?;
+
+			hashes.insert(version.to_owned(), hash.trim().to_lowercase());
+		}
+
+		Ok(Lockfile { hashes })
+	}
+
+	pub fn write(&self, path: &Path) -> io::Result<()> {
+		let mut contents = String::from("# SHA-256 of each BYOND archive installed by byond_get\n");
+		for (version, hash) in &self.hashes {
+			contents.push_str(&format!("{} {}\n", version, hash));
+		}
+
+		fs::write(path, contents)
+	}
+
+	pub fn get(&self, version: &str) -> Option<&str> {
+		self.hashes.get(version).map(String::as_str)
+	}
+
+	pub fn insert(&mut self, version: &str, hash: String) {
+		self.hashes.insert(version.to_owned(), hash);
+	}
+}
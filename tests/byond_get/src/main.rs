@@ -1,11 +1,52 @@
-use std::{fs, path::Path};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	process::exit
+};
 
 use clap::{Arg, Command};
 
+mod lock;
+
+use lock::Lockfile;
+
 fn get_zip_url(major: u32, minor: u32) -> reqwest::Url {
 	reqwest::Url::parse(&format!("https://secure.byond.com/download/build/{0}/{0}.{1}_byond.zip", major, minor)).unwrap()
 }
 
+fn fail(message: String) -> ! {
+	eprintln!("{}", message);
+	exit(1);
+}
+
+// Finds the archive for a version, from `--from-zip`, the cache or the BYOND
+// site in that order. Downloads aren't cached straight away, instead the path
+// to cache them at is returned so that can happen once their hash is checked.
+fn fetch(major: u32, minor: u32, from_zip: Option<&Path>, cache: Option<&Path>, offline: bool) -> (Vec<u8>, Option<PathBuf>) {
+	if let Some(path) = from_zip {
+		return (fs::read(path).unwrap_or_else(|e| fail(format!("couldn't read {:?}: {}", path, e))), None);
+	}
+
+	let cached = cache.map(|cache| cache.join(format!("{}.{}_byond.zip", major, minor)));
+	if let Some(path) = cached.as_ref().filter(|x| x.is_file()) {
+		println!("Using cached archive {:?}", path);
+		return (fs::read(path).unwrap_or_else(|e| fail(format!("couldn't read {:?}: {}", path, e))), None);
+	}
+
+	if offline {
+		fail(format!("BYOND {}.{} isn't in the cache and --offline was passed", major, minor));
+	}
+
+	println!("Downloading BYOND {}.{}", major, minor);
+	let data = reqwest::blocking::get(get_zip_url(major, minor))
+		.and_then(|x| x.error_for_status())
+		.and_then(|x| x.bytes())
+		.unwrap_or_else(|e| fail(format!("couldn't download BYOND {}.{}: {}", major, minor, e)))
+		.to_vec();
+
+	(data, cached)
+}
+
 fn main() {
 	let matches = Command::new("byond_get")
 		.disable_version_flag(true)
@@ -28,6 +69,38 @@ fn main() {
 				.required(true)
 				.takes_value(true)
 		)
+		.arg(
+			Arg::new("cache")
+				.long("cache")
+				.allow_invalid_utf8(true)
+				.env("BYOND_CACHE")
+				.help("directory to keep downloaded archives in")
+				.takes_value(true)
+		)
+		.arg(
+			Arg::new("offline")
+				.long("offline")
+				.help("fail instead of downloading archives that aren't cached")
+		)
+		.arg(
+			Arg::new("from-zip")
+				.long("from-zip")
+				.allow_invalid_utf8(true)
+				.help("install from a local archive instead of the cache or the BYOND site")
+				.takes_value(true)
+		)
+		.arg(
+			Arg::new("lockfile")
+				.long("lockfile")
+				.allow_invalid_utf8(true)
+				.help("file of known archive hashes [default: tests/byond_get/byond.lock]")
+				.takes_value(true)
+		)
+		.arg(
+			Arg::new("update-lockfile")
+				.long("update-lockfile")
+				.help("add versions that aren't in the lockfile yet instead of failing")
+		)
 		.get_matches();
 
 	let major = matches.value_of("major").unwrap();
@@ -42,8 +115,47 @@ fn main() {
 		panic!("path {:?} already exists", destination);
 	}
 
-	let resp = reqwest::blocking::get(get_zip_url(major, minor)).unwrap().bytes().unwrap();
-	let stream = std::io::Cursor::new(resp);
+	let lockfile_path = matches
+		.value_of_os("lockfile")
+		.map(PathBuf::from)
+		.unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("byond.lock"));
+	let mut lockfile = Lockfile::read(&lockfile_path).unwrap_or_else(|e| fail(format!("couldn't read {:?}: {}", lockfile_path, e)));
+
+	let (data, cache_path) = fetch(
+		major,
+		minor,
+		matches.value_of_os("from-zip").map(Path::new),
+		matches.value_of_os("cache").map(Path::new),
+		matches.is_present("offline")
+	);
+
+	let version = format!("{}.{}", major, minor);
+	let hash = lock::hash(&data);
+	match lockfile.get(&version) {
+		Some(expected) if expected == hash => {}
+		Some(expected) => fail(format!(
+			"SHA-256 of the BYOND {} archive is {}, but the lockfile says it should be {}",
+			version, hash, expected
+		)),
+		None if !matches.is_present("update-lockfile") => fail(format!(
+			"BYOND {} isn't in the lockfile, pass --update-lockfile to add it with hash {}",
+			version, hash
+		)),
+		None => {
+			println!("Adding BYOND {} to {:?}", version, lockfile_path);
+			lockfile.insert(&version, hash);
+			lockfile
+				.write(&lockfile_path)
+				.unwrap_or_else(|e| fail(format!("couldn't write {:?}: {}", lockfile_path, e)));
+		}
+	}
+
+	if let Some(path) = cache_path {
+		fs::create_dir_all(path.parent().unwrap()).unwrap();
+		fs::write(&path, &data).unwrap_or_else(|e| fail(format!("couldn't write {:?}: {}", path, e)));
+	}
+
+	let stream = std::io::Cursor::new(data);
 	let mut archive = zip::ZipArchive::new(stream).unwrap();
 
 	for i in 0..archive.len() {
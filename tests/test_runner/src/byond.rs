@@ -0,0 +1,80 @@
+//! BYOND installs for the versions passed to the test runner. Each one is
+//! fetched with `byond_get` into the cache directory the first time it's used.
+
+use std::{
+	fmt, fs,
+	path::{Path, PathBuf},
+	process::Command,
+	str::FromStr
+};
+
+#[derive(Clone, Copy)]
+pub struct Version {
+	pub major: u32,
+	pub minor: u32
+}
+
+impl FromStr for Version {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (major, minor) = s
+			.split_once('.')
+			.ok_or_else(|| format!("expected a version like 515.1647, got {:?}", s))?;
+
+		Ok(Version {
+			major: major.parse().map_err(|_| format!("invalid major version in {:?}", s))?,
+			minor: minor.parse().map_err(|_| format!("invalid minor version in {:?}", s))?
+		})
+	}
+}
+
+impl fmt::Display for Version {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}.{}", self.major, self.minor)
+	}
+}
+
+/// Returns the path of an install of `version`, downloading it if it isn't in
+/// the cache yet. Archives are kept in the cache too, so an install can be
+/// rebuilt with `offline` set. Versions missing from the lockfile are refused
+/// unless `update_lockfile` is set, in which case their hash is recorded.
+pub fn install(cache: &Path, version: Version, offline: bool, update_lockfile: bool) -> PathBuf {
+	let path = cache.join(version.to_string());
+	if path.is_dir() {
+		return path;
+	}
+
+	fs::create_dir_all(cache).expect("couldn't create byond cache directory");
+
+	// Extracted somewhere else first so an interrupted download doesn't look
+	// like a finished install next time
+	let partial = cache.join(format!("{}.partial", version));
+	if partial.exists() {
+		fs::remove_dir_all(&partial).expect("couldn't remove partial byond install");
+	}
+
+	println!("Fetching BYOND {}", version);
+	let mut command = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+	command
+		.args(["run", "--quiet", "-p", "byond_get", "--"])
+		.arg(version.major.to_string())
+		.arg(version.minor.to_string())
+		.arg(&partial)
+		.arg("--cache")
+		.arg(cache.join("archives"));
+
+	if offline {
+		command.arg("--offline");
+	}
+
+	if update_lockfile {
+		command.arg("--update-lockfile");
+	}
+
+	let status = command.status().expect("couldn't run byond_get");
+	assert!(status.success(), "byond_get failed to fetch BYOND {}", version);
+
+	fs::rename(&partial, &path).expect("couldn't move byond install into place");
+	path
+}
@@ -1,17 +1,24 @@
+mod byond;
 mod paths;
+mod report;
 
-use std::process::Command;
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	process::Command
+};
+
+use byond::Version;
 
 trait ByondCommand {
-	fn with_byond_paths(&mut self) -> &mut Self;
+	fn with_byond_paths(&mut self, byond: &Path) -> &mut Self;
 }
 
 #[cfg(unix)]
 impl ByondCommand for Command {
 	// TODO: This doesn't read very nice
-	fn with_byond_paths(&mut self) -> &mut Command {
-		let byond_system = paths::find_byond();
-		let byond_bin = paths::find_byond_bin();
+	fn with_byond_paths(&mut self, byond: &Path) -> &mut Command {
+		let byond_bin = paths::find_byond_bin(byond);
 
 		let path = format!(
 			"{}:{}",
@@ -22,31 +29,81 @@ impl ByondCommand for Command {
 		let ld_library_path = format!(
 			"{}:{}",
 			byond_bin.as_os_str().to_str().unwrap(),
-			std::env::var_os("LD_LIBRARY_PATH").unwrap().to_str().unwrap()
+			std::env::var_os("LD_LIBRARY_PATH").unwrap_or_default().to_str().unwrap()
 		);
 
-		self.env("BYOND_SYSTEM", byond_system)
-			.env("PATH", path)
-			.env("LD_LIBRARY_PATH", ld_library_path)
+		self.env("BYOND_SYSTEM", byond).env("PATH", path).env("LD_LIBRARY_PATH", ld_library_path)
 	}
 }
 
 #[cfg(windows)]
 impl ByondCommand for Command {
-	fn with_byond_paths(&mut self) -> &mut Command {
+	fn with_byond_paths(&mut self, _byond: &Path) -> &mut Command {
 		self
 	}
 }
 
-fn main() {
-	let res = Command::new(paths::find_dm()).with_byond_paths().arg(paths::find_dme()).status().unwrap();
-	assert!(res.success(), "dreamdaemon build failed");
+struct Args {
+	/// BYOND versions to test against. If empty, the install at `BYOND_PATH` is
+	/// used instead.
+	versions: Vec<Version>,
+	/// Where versions are downloaded to, from `--cache <dir>`
+	cache: PathBuf,
+	/// Only use versions that have already been downloaded, from `--offline`
+	offline: bool,
+	/// Record the hash of versions missing from the lockfile, from
+	/// `--update-lockfile`
+	update_lockfile: bool,
+	/// Where to write reports, from `--junit <path>` and `--tap <path>`
+	junit: Option<PathBuf>,
+	tap: Option<PathBuf>
+}
+
+fn parse_args() -> Args {
+	let mut args = Args {
+		versions: vec![],
+		cache: PathBuf::from("target/byond"),
+		offline: false,
+		update_lockfile: false,
+		junit: None,
+		tap: None
+	};
+
+	let mut iter = std::env::args().skip(1);
+	while let Some(arg) = iter.next() {
+		let mut path = || PathBuf::from(iter.next().unwrap_or_else(|| panic!("{} needs a path", arg)));
+
+		match arg.as_str() {
+			"--cache" => args.cache = path(),
+			"--offline" => args.offline = true,
+			"--update-lockfile" => args.update_lockfile = true,
+			"--junit" => args.junit = Some(path()),
+			"--tap" => args.tap = Some(path()),
+			version => args.versions.push(version.parse().unwrap_or_else(|e| panic!("{}", e)))
+		}
+	}
+
+	args
+}
+
+// Builds and runs the test host with one BYOND install, returning the results
+// and any problems with the run as a whole
+fn run_suite(byond: &Path, label: &str) -> (Vec<report::TestResult>, Vec<String>) {
+	let res = Command::new(paths::find_dm(byond))
+		.with_byond_paths(byond)
+		.arg(paths::find_dme())
+		.status()
+		.unwrap();
+	if !res.success() {
+		return (vec![], vec!["dreammaker build failed".to_owned()]);
+	}
 
 	// Here we depend on BYOND not fucking with stderr too much so we can hijack it
 	// for our own communication
-	let output = Command::new(paths::find_dreamdaemon())
-		.with_byond_paths()
+	let output = Command::new(paths::find_dreamdaemon(byond))
+		.with_byond_paths(byond)
 		.env("AUXTEST_DLL", paths::find_dll())
+		.env("AUXTEST_DMB", paths::find_dmb())
 		.arg(paths::find_dmb())
 		.arg("-trusted")
 		.arg("-close")
@@ -54,18 +111,100 @@ fn main() {
 		.unwrap()
 		.stderr;
 
-	let res = std::str::from_utf8(&output).unwrap();
+	let res = String::from_utf8_lossy(&output);
+	let results = report::parse(&res, label);
 
 	// Check for any messages matching "FAILED: <msg>"
-	let errors = res.lines().filter(|x| x.starts_with("FAILED: ")).collect::<Vec<&str>>();
+	let mut errors: Vec<String> = res.lines().filter(|x| x.starts_with("FAILED: ")).map(str::to_owned).collect();
+
+	// Now make sure we have only one message matching "SUCCESS: <msg>"
+	if res.lines().filter(|x| x.starts_with("SUCCESS: ")).count() != 1 {
+		errors.push("Tests didn't output success message".to_owned());
+	}
+
+	if results.is_empty() {
+		errors.push("No test results were reported".to_owned());
+	}
+
+	(results, errors)
+}
+
+// One row per test, one column per BYOND version. A test only counts as passing
+// if it passed on every boot.
+fn print_matrix(labels: &[String], results: &[report::TestResult]) {
+	let mut rows: BTreeMap<&str, BTreeMap<&str, bool>> = BTreeMap::new();
+	for result in results {
+		let passed = rows.entry(&result.name).or_default().entry(&result.byond).or_insert(true);
+		*passed &= result.failure.is_none();
+	}
+
+	let width = rows.keys().map(|x| x.len()).max().unwrap_or(0).max("test".len());
+	let columns: Vec<usize> = labels.iter().map(|x| x.len().max("FAILED".len())).collect();
+
+	print!("{:<width$}", "test", width = width);
+	for (label, column) in labels.iter().zip(&columns) {
+		print!("  {:>column$}", label, column = column);
+	}
+	println!();
+
+	for (name, row) in &rows {
+		print!("{:<width$}", name, width = width);
+		for (label, column) in labels.iter().zip(&columns) {
+			let cell = match row.get(label.as_str()) {
+				Some(true) => "ok",
+				Some(false) => "FAILED",
+				None => "-"
+			};
+			print!("  {:>column$}", cell, column = column);
+		}
+		println!();
+	}
+}
+
+fn main() {
+	let args = parse_args();
+
+	let installs: Vec<(String, PathBuf)> = if args.versions.is_empty() {
+		vec![("local".to_owned(), paths::find_byond())]
+	} else {
+		args.versions
+			.iter()
+			.map(|version| (version.to_string(), byond::install(&args.cache, *version, args.offline, args.update_lockfile)))
+			.collect()
+	};
+
+	let mut results = vec![];
+	let mut errors = vec![];
+	for (label, byond) in &installs {
+		println!("Testing with BYOND {}", label);
+
+		let (run_results, run_errors) = run_suite(byond, label);
+		results.extend(run_results);
+		errors.extend(run_errors.into_iter().map(|x| format!("[{}] {}", label, x)));
+	}
+
+	for result in &results {
+		if let Some(message) = &result.failure {
+			println!("[{}] boot {} {} ... FAILED: {}", result.byond, result.boot, result.name, message);
+		}
+	}
+
+	let labels: Vec<String> = installs.into_iter().map(|(label, _)| label).collect();
+	println!();
+	print_matrix(&labels, &results);
+	println!();
+
+	if let Some(path) = &args.junit {
+		std::fs::write(path, report::junit(&results)).expect("failed to write junit report");
+	}
+
+	if let Some(path) = &args.tap {
+		std::fs::write(path, report::tap(&results)).expect("failed to write tap report");
+	}
 
 	if !errors.is_empty() {
 		panic!("TESTS FAILED\n{}", errors.join("\n"));
 	}
 
-	// Now make sure we have only one message matching "SUCCESS: <msg>"
-	let successes = res.lines().filter(|x| x.starts_with("SUCCESS: ")).collect::<Vec<&str>>();
-	assert_eq!(successes.len(), 1, "Tests didn't output success message");
-
 	println!("Tests Succeeded");
 }
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub fn find_byond() -> PathBuf {
 	let path = PathBuf::from(std::env::var_os("BYOND_PATH").unwrap());
@@ -7,15 +7,15 @@ pub fn find_byond() -> PathBuf {
 }
 
 #[allow(dead_code)]
-pub fn find_byond_bin() -> PathBuf {
-	let mut path = find_byond();
+pub fn find_byond_bin(byond: &Path) -> PathBuf {
+	let mut path = byond.to_path_buf();
 	path.push("bin");
 	assert!(path.is_dir(), "couldn't find byond/bin");
 	path
 }
 
-pub fn find_dm() -> PathBuf {
-	let mut path = find_byond();
+pub fn find_dm(byond: &Path) -> PathBuf {
+	let mut path = byond.to_path_buf();
 
 	#[cfg(unix)]
 	path.push("bin/DreamMaker");
@@ -27,8 +27,8 @@ pub fn find_dm() -> PathBuf {
 	path
 }
 
-pub fn find_dreamdaemon() -> PathBuf {
-	let mut path = find_byond();
+pub fn find_dreamdaemon(byond: &Path) -> PathBuf {
+	let mut path = byond.to_path_buf();
 
 	#[cfg(unix)]
 	path.push("bin/DreamDaemon");
@@ -0,0 +1,199 @@
+//! Per-test results reported by `/proc/auxtest_run_all`, and the JUnit XML and
+//! TAP files written from them.
+
+use std::fmt::Write;
+
+pub struct TestResult {
+	/// The BYOND version the test ran against
+	pub byond: String,
+	/// Which boot of the world (starting at 1) the test ran in
+	pub boot: u32,
+	pub name: String,
+	pub seconds: f64,
+	/// The failure message, or `None` if the test passed
+	pub failure: Option<String>
+}
+
+fn unescape(message: &str) -> String {
+	let mut out = String::with_capacity(message.len());
+	let mut chars = message.chars();
+
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			out.push(c);
+			continue;
+		}
+
+		match chars.next() {
+			Some('n') => out.push('\n'),
+			Some('t') => out.push('\t'),
+			Some(c) => out.push(c),
+			None => out.push('\\')
+		}
+	}
+
+	out
+}
+
+/// Picks the `AUXTEST` lines out of Dream Daemon's stderr
+pub fn parse(output: &str, byond: &str) -> Vec<TestResult> {
+	output
+		.lines()
+		.filter_map(|line| {
+			let mut fields = line.strip_prefix("AUXTEST\t")?.splitn(5, '\t');
+
+			let boot = fields.next()?.parse().ok()?;
+			let name = fields.next()?.to_owned();
+			let passed = fields.next()? == "ok";
+			let seconds = fields.next()?.parse().ok()?;
+			let message = unescape(fields.next().unwrap_or(""));
+
+			Some(TestResult {
+				byond: byond.to_owned(),
+				boot,
+				name,
+				seconds,
+				failure: if passed { None } else { Some(message) }
+			})
+		})
+		.collect()
+}
+
+fn escape_xml(text: &str) -> String {
+	let mut out = String::with_capacity(text.len());
+
+	for c in text.chars() {
+		match c {
+			'&' => out.push_str("&amp;"),
+			'<' => out.push_str("&lt;"),
+			'>' => out.push_str("&gt;"),
+			'"' => out.push_str("&quot;"),
+			'\'' => out.push_str("&apos;"),
+			_ => out.push(c)
+		}
+	}
+
+	out
+}
+
+pub fn junit(results: &[TestResult]) -> String {
+	let failures = results.iter().filter(|x| x.failure.is_some()).count();
+	let seconds: f64 = results.iter().map(|x| x.seconds).sum();
+
+	let mut out = String::new();
+	writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+	writeln!(
+		out,
+		r#"<testsuite name="auxtest" tests="{}" failures="{}" errors="0" time="{:.6}">"#,
+		results.len(),
+		failures,
+		seconds
+	)
+	.unwrap();
+
+	for result in results {
+		write!(
+			out,
+			r#"  <testcase classname="auxtest.{}.boot{}" name="{}" time="{:.6}""#,
+			escape_xml(&result.byond).replace('.', "_"),
+			result.boot,
+			escape_xml(&result.name),
+			result.seconds
+		)
+		.unwrap();
+
+		match &result.failure {
+			Some(message) => {
+				writeln!(out, ">").unwrap();
+				let summary = message.lines().next().unwrap_or("");
+				writeln!(out, r#"    <failure message="{}">{}</failure>"#, escape_xml(summary), escape_xml(message)).unwrap();
+				writeln!(out, "  </testcase>").unwrap();
+			}
+			None => writeln!(out, " />").unwrap()
+		}
+	}
+
+	writeln!(out, "</testsuite>").unwrap();
+	out
+}
+
+pub fn tap(results: &[TestResult]) -> String {
+	let mut out = String::new();
+	writeln!(out, "TAP version 13").unwrap();
+	writeln!(out, "1..{}", results.len()).unwrap();
+
+	for (index, result) in results.iter().enumerate() {
+		let status = if result.failure.is_some() { "not ok" } else { "ok" };
+		writeln!(out, "{} {} - {} boot {}: {}", status, index + 1, result.byond, result.boot, result.name).unwrap();
+
+		if let Some(message) = &result.failure {
+			writeln!(out, "  ---").unwrap();
+			writeln!(out, "  message: |").unwrap();
+			for line in message.lines() {
+				writeln!(out, "    {}", line).unwrap();
+			}
+			writeln!(out, "  ...").unwrap();
+		}
+	}
+
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn results() -> Vec<TestResult> {
+		parse(
+			"noise\nAUXTEST\t1\tlists\tok\t0.250000\t\nAUXTEST\t2\t<weak> & \"values\"\tfailed\t0.5\tfirst 'line' <b>\\nsecond\\tline\\\\\n",
+			"515.1630"
+		)
+	}
+
+	#[test]
+	fn parsing() {
+		let results = results();
+		assert_eq!(results.len(), 2);
+
+		assert_eq!(results[0].byond, "515.1630");
+		assert_eq!((results[0].boot, results[0].name.as_str(), results[0].seconds), (1, "lists", 0.25));
+		assert_eq!(results[0].failure, None);
+
+		assert_eq!((results[1].boot, results[1].name.as_str()), (2, "<weak> & \"values\""));
+		assert_eq!(results[1].failure.as_deref(), Some("first 'line' <b>\nsecond\tline\\"));
+	}
+
+	#[test]
+	fn junit_output() {
+		assert_eq!(
+			junit(&results()),
+			concat!(
+				"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+				"<testsuite name=\"auxtest\" tests=\"2\" failures=\"1\" errors=\"0\" time=\"0.750000\">\n",
+				"  <testcase classname=\"auxtest.515_1630.boot1\" name=\"lists\" time=\"0.250000\" />\n",
+				"  <testcase classname=\"auxtest.515_1630.boot2\" name=\"&lt;weak&gt; &amp; &quot;values&quot;\" time=\"0.500000\">\n",
+				"    <failure message=\"first &apos;line&apos; &lt;b&gt;\">first &apos;line&apos; &lt;b&gt;\nsecond\tline\\</failure>\n",
+				"  </testcase>\n",
+				"</testsuite>\n"
+			)
+		);
+	}
+
+	#[test]
+	fn tap_output() {
+		assert_eq!(
+			tap(&results()),
+			concat!(
+				"TAP version 13\n",
+				"1..2\n",
+				"ok 1 - 515.1630 boot 1: lists\n",
+				"not ok 2 - 515.1630 boot 2: <weak> & \"values\"\n",
+				"  ---\n",
+				"  message: |\n",
+				"    first 'line' <b>\n",
+				"    second\tline\\\n",
+				"  ...\n"
+			)
+		);
+	}
+}